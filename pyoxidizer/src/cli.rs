@@ -5,6 +5,7 @@
 use {
     crate::{
         environment::{default_target_triple, PYOXIDIZER_VERSION},
+        logging::JsonLogger,
         project_building, projectmgmt,
     },
     anyhow::{anyhow, Context, Result},
@@ -12,6 +13,7 @@ use {
     std::{
         collections::HashMap,
         path::{Path, PathBuf},
+        time::Duration,
     },
 };
 
@@ -128,6 +130,15 @@ be `None` instead of a `string`.
 If a Starlark variable is defined multiple times, an error occurs.
 ";
 
+const TIMEOUT_HELP: &str = "\
+Abort evaluating the configuration file after this many seconds.
+
+The Starlark interpreter can't be interrupted, so the timeout is only
+checked when including files, resolving Python distributions, and
+building targets. Code running between these points, such as an infinite
+loop in the configuration file, isn't aborted.
+";
+
 fn add_env_args(app: Command) -> Command {
     app.arg(
         Arg::new("vars")
@@ -221,6 +232,15 @@ pub fn run_cli() -> Result<()> {
                 .global(true)
                 .multiple_occurrences(true)
                 .help("Increase logging verbosity. Can be specified multiple times"),
+        )
+        .arg(
+            Arg::new("log_format")
+                .long("log-format")
+                .global(true)
+                .takes_value(true)
+                .possible_values(["text", "json"])
+                .default_value("text")
+                .help("Format of log output"),
         );
 
     let app = app.subcommand(
@@ -239,13 +259,63 @@ pub fn run_cli() -> Result<()> {
                 Arg::new("target_triple")
                     .long("target-triple")
                     .takes_value(true)
-                    .help("Rust target triple to build for"),
+                    .multiple_occurrences(true)
+                    .help("Rust target triple to build for. Can be specified multiple times to build for several triples"),
             )
             .arg(
                 Arg::new("release")
                     .long("release")
                     .help("Build a release binary"),
             )
+            .arg(
+                Arg::new("offline")
+                    .long("offline")
+                    .help("Fail instead of downloading Python distributions"),
+            )
+            .arg(
+                Arg::new("ca_bundle")
+                    .long("ca-bundle")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .help("PEM file of additional certificates to trust when downloading"),
+            )
+            .arg(
+                Arg::new("distributions_dir")
+                    .long("distributions-dir")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .help("Directory to download and extract Python distributions into"),
+            )
+            .arg(
+                Arg::new("dump_context")
+                    .long("dump-context")
+                    .help("Print the resolved build context as JSON instead of building"),
+            )
+            .arg(
+                Arg::new("timeout")
+                    .long("timeout")
+                    .takes_value(true)
+                    .value_name("SECONDS")
+                    .help("Abort evaluating the configuration file after this many seconds")
+                    .long_help(TIMEOUT_HELP),
+            )
+            .arg(
+                Arg::new("event_log")
+                    .long("event-log")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .help("Write build lifecycle events as JSON lines to this file"),
+            )
+            .arg(
+                Arg::new("config_toml")
+                    .long("config-toml")
+                    .help("Define top-level keys of a pyoxidizer.toml next to the configuration file as globals"),
+            )
+            .arg(
+                Arg::new("dry_run")
+                    .long("dry-run")
+                    .help("Evaluate the configuration file without downloading Python distributions or building targets"),
+            )
             .arg(
                 Arg::new("path")
                     .long("path")
@@ -441,6 +511,25 @@ pub fn run_cli() -> Result<()> {
                     .long("release")
                     .help("Run a release binary"),
             )
+            .arg(
+                Arg::new("offline")
+                    .long("offline")
+                    .help("Fail instead of downloading Python distributions"),
+            )
+            .arg(
+                Arg::new("ca_bundle")
+                    .long("ca-bundle")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .help("PEM file of additional certificates to trust when downloading"),
+            )
+            .arg(
+                Arg::new("distributions_dir")
+                    .long("distributions-dir")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .help("Directory to download and extract Python distributions into"),
+            )
             .arg(
                 Arg::new("path")
                     .long("path")
@@ -499,16 +588,20 @@ pub fn run_cli() -> Result<()> {
         _ => log::LevelFilter::Trace,
     };
 
-    let mut builder = env_logger::Builder::from_env(
-        env_logger::Env::default().default_filter_or(log_level.as_str()),
-    );
+    if matches.value_of("log_format") == Some("json") {
+        JsonLogger::new(log_level, Box::new(std::io::stderr())).init()?;
+    } else {
+        let mut builder = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or(log_level.as_str()),
+        );
 
-    builder
-        .format_timestamp(None)
-        .format_level(false)
-        .format_target(false);
+        builder
+            .format_timestamp(None)
+            .format_level(false)
+            .format_target(false);
 
-    builder.init();
+        builder.init();
+    }
 
     if matches.is_present("system_rust") {
         env.unmanage_rust().context("unmanaging Rust")?;
@@ -530,20 +623,65 @@ pub fn run_cli() -> Result<()> {
         "build" => {
             let starlark_vars = starlark_vars(args)?;
             let release = args.is_present("release");
-            let target_triple = args.value_of("target_triple");
+            let offline = args.is_present("offline");
+            let ca_bundle = args.value_of("ca_bundle").map(Path::new);
+            let distributions_dir = args
+                .value_of("distributions_dir")
+                .map(|p| std::env::current_dir().map(|cwd| cwd.join(p)))
+                .transpose()?;
+            let dry_run = args.is_present("dry_run");
+            let config_toml = args.is_present("config_toml");
+            let event_log = args.value_of("event_log").map(Path::new);
+            let timeout = args
+                .value_of("timeout")
+                .map(|value| {
+                    value
+                        .parse::<u64>()
+                        .map(Duration::from_secs)
+                        .with_context(|| format!("parsing --timeout value {}", value))
+                })
+                .transpose()?;
+            let target_triples = args
+                .values_of("target_triple")
+                .map(|values| values.collect::<Vec<_>>())
+                .unwrap_or_default();
             let path = args.value_of("path").unwrap();
             let resolve_targets = args
                 .values_of("targets")
                 .map(|values| values.map(|x| x.to_string()).collect());
 
+            if args.is_present("dump_context") {
+                if target_triples.len() > 1 {
+                    return Err(anyhow!(
+                        "--dump-context cannot be used with multiple --target-triple arguments"
+                    ));
+                }
+
+                return projectmgmt::dump_context(
+                    &env,
+                    Path::new(path),
+                    target_triples.first().copied(),
+                    starlark_vars,
+                    release,
+                    verbose,
+                );
+            }
+
             projectmgmt::build(
                 &env,
                 Path::new(path),
-                target_triple,
+                &target_triples,
                 resolve_targets,
                 starlark_vars,
                 release,
                 verbose,
+                offline,
+                ca_bundle,
+                distributions_dir.as_deref(),
+                dry_run,
+                config_toml,
+                timeout,
+                event_log,
             )
         }
 
@@ -658,6 +796,12 @@ pub fn run_cli() -> Result<()> {
             let starlark_vars = starlark_vars(args)?;
             let target_triple = args.value_of("target_triple");
             let release = args.is_present("release");
+            let offline = args.is_present("offline");
+            let ca_bundle = args.value_of("ca_bundle").map(Path::new);
+            let distributions_dir = args
+                .value_of("distributions_dir")
+                .map(|p| std::env::current_dir().map(|cwd| cwd.join(p)))
+                .transpose()?;
             let path = args.value_of("path").unwrap();
             let target = args.value_of("target");
             let extra: Vec<&str> = args.values_of("extra").unwrap_or_default().collect();
@@ -671,6 +815,9 @@ pub fn run_cli() -> Result<()> {
                 starlark_vars,
                 &extra,
                 verbose,
+                offline,
+                ca_bundle,
+                distributions_dir.as_deref(),
             )
         }
 
@@ -152,6 +152,14 @@ pub fn default_target_triple() -> &'static str {
     }
 }
 
+/// Resolve the triple of the machine we are building on.
+///
+/// `None` resolves to the triple this binary was built for, as
+/// remapped by [default_target_triple()].
+pub fn resolve_host_triple(host: Option<&str>) -> &str {
+    host.unwrap_or_else(default_target_triple)
+}
+
 /// Describes the location of the PyOxidizer source files.
 #[derive(Clone, Debug)]
 pub enum PyOxidizerSource {
@@ -0,0 +1,134 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Structured build lifecycle events.
+//!
+//! Tools driving PyOxidizer can opt in to receiving events as JSON lines
+//! instead of parsing log output. Logging is unaffected.
+
+use {
+    anyhow::{Context, Result},
+    serde::Serialize,
+    serde_json::Value,
+    std::{
+        io::Write,
+        path::{Path, PathBuf},
+        sync::Mutex,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// A build lifecycle event.
+///
+/// Serialized with a `type` key holding the snake case variant name.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BuildEvent {
+    /// Building of a target started.
+    TargetStarted { target: String },
+
+    /// A Python distribution was resolved.
+    DistributionResolved {
+        /// URL or local path of the distribution archive.
+        location: String,
+        sha256: String,
+        duration_ms: u64,
+    },
+
+    /// Building of a target finished successfully.
+    TargetFinished {
+        target: String,
+        duration_ms: u64,
+        output_path: PathBuf,
+    },
+
+    /// Building of a target failed.
+    TargetFailed {
+        target: String,
+        duration_ms: u64,
+        error: String,
+    },
+}
+
+/// Convert a duration to whole milliseconds for use in an event.
+pub fn duration_ms(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Format an event as a single line JSON object.
+///
+/// `timestamp` is added to the event's fields, in milliseconds since the
+/// Unix epoch.
+pub fn format_event(event: &BuildEvent, timestamp: SystemTime) -> String {
+    let mut value = serde_json::to_value(event).expect("events are serializable");
+
+    if let Value::Object(object) = &mut value {
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(duration_ms)
+            .unwrap_or_default();
+        object.insert("timestamp".to_string(), Value::from(timestamp));
+    }
+
+    value.to_string()
+}
+
+/// Writes build events as JSON lines.
+pub struct EventSink {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl EventSink {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Construct an instance writing to a file, which is truncated.
+    pub fn create(path: &Path) -> Result<Self> {
+        let fh = std::fs::File::create(path)
+            .with_context(|| format!("creating event log {}", path.display()))?;
+
+        Ok(Self::new(Box::new(fh)))
+    }
+
+    /// Write an event.
+    ///
+    /// Events are advisory. Write errors are ignored so they don't fail builds.
+    pub fn emit(&self, event: BuildEvent) {
+        let line = format_event(&event, SystemTime::now());
+
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writeln!(writer, "{}", line);
+            let _ = writer.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_event() -> Result<()> {
+        let line = format_event(
+            &BuildEvent::TargetFinished {
+                target: "exe".to_string(),
+                duration_ms: 1500,
+                output_path: PathBuf::from("build/exe"),
+            },
+            UNIX_EPOCH + Duration::from_secs(10),
+        );
+
+        let value: Value = serde_json::from_str(&line)?;
+        assert_eq!(value["type"], "target_finished");
+        assert_eq!(value["timestamp"], 10000);
+        assert_eq!(value["target"], "exe");
+        assert_eq!(value["duration_ms"], 1500);
+        assert_eq!(value["output_path"], "build/exe");
+
+        Ok(())
+    }
+}
@@ -13,7 +13,9 @@ This library exposes that functionality to other tools.
 
 mod default_python_distributions;
 pub mod environment;
+pub mod events;
 pub mod licensing;
+pub mod logging;
 pub mod project_building;
 pub mod project_layout;
 pub mod projectmgmt;
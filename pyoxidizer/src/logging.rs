@@ -0,0 +1,266 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Logging support.
+
+use {
+    log::{LevelFilter, Log, Metadata, Record},
+    serde_json::{Map, Value},
+    std::{
+        cell::RefCell,
+        collections::BTreeMap,
+        io::Write,
+        marker::PhantomData,
+        rc::Rc,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+    },
+};
+
+thread_local! {
+    /// Fields added to records a [JsonLogger] emits on this thread.
+    ///
+    /// Each live [ScopedLogFields] owns an entry. Later entries take
+    /// precedence.
+    static LOG_FIELDS: RefCell<Vec<(u64, BTreeMap<String, String>)>> = RefCell::new(vec![]);
+}
+
+/// Identifies the next [ScopedLogFields] instance.
+static NEXT_SCOPE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Obtain the fields added to records emitted on the current thread.
+pub fn log_fields() -> BTreeMap<String, String> {
+    LOG_FIELDS.with(|scopes| {
+        scopes
+            .borrow()
+            .iter()
+            .flat_map(|(_, fields)| fields.clone())
+            .collect()
+    })
+}
+
+/// Log fields that apply to the current thread until dropped.
+///
+/// Evaluation contexts use this to record the config file and target triple
+/// being evaluated for as long as they are alive. Instances can be dropped
+/// in any order.
+pub struct ScopedLogFields {
+    id: u64,
+    // Fields are thread-local. So instances can't be moved across threads.
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl Default for ScopedLogFields {
+    fn default() -> Self {
+        let id = NEXT_SCOPE_ID.fetch_add(1, Ordering::Relaxed);
+
+        LOG_FIELDS.with(|scopes| scopes.borrow_mut().push((id, BTreeMap::new())));
+
+        Self {
+            id,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl ScopedLogFields {
+    /// Set a field until this instance is dropped.
+    pub fn set(&mut self, key: impl ToString, value: impl ToString) {
+        LOG_FIELDS.with(|scopes| {
+            if let Some((_, fields)) = scopes
+                .borrow_mut()
+                .iter_mut()
+                .find(|(id, _)| *id == self.id)
+            {
+                fields.insert(key.to_string(), value.to_string());
+            }
+        });
+    }
+}
+
+impl Drop for ScopedLogFields {
+    fn drop(&mut self) {
+        // The thread-local may already be destroyed during thread teardown.
+        let _ = LOG_FIELDS.try_with(|scopes| scopes.borrow_mut().retain(|(id, _)| *id != self.id));
+    }
+}
+
+/// Format a log record as a single line JSON object.
+///
+/// The object has `level`, `target`, and `message` keys in addition to
+/// `fields`.
+pub fn format_json_record(record: &Record, fields: &BTreeMap<String, String>) -> String {
+    let mut object = fields
+        .iter()
+        .map(|(k, v)| (k.clone(), Value::from(v.as_str())))
+        .collect::<Map<_, _>>();
+
+    object.insert("level".to_string(), Value::from(record.level().as_str()));
+    object.insert("target".to_string(), Value::from(record.target()));
+    object.insert(
+        "message".to_string(),
+        Value::from(record.args().to_string()),
+    );
+
+    Value::Object(object).to_string()
+}
+
+/// A logger writing records as JSON lines.
+pub struct JsonLogger {
+    level: LevelFilter,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLogger {
+    pub fn new(level: LevelFilter, writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            level,
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Install this instance as the global logger.
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        let level = self.level;
+
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+
+        Ok(())
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format_json_record(record, &log_fields());
+
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writeln!(writer, "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, log::Level, std::sync::Arc};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_logger() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let logger = JsonLogger::new(LevelFilter::Info, Box::new(buffer.clone()));
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("hello {}", "world"))
+                .level(Level::Warn)
+                .target("pyoxidizer")
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .args(format_args!("not logged"))
+                .level(Level::Debug)
+                .target("pyoxidizer")
+                .build(),
+        );
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+
+        let value: serde_json::Value = serde_json::from_str(lines[0])?;
+        assert_eq!(value["message"], "hello world");
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["target"], "pyoxidizer");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_json_record_fields() -> anyhow::Result<()> {
+        let fields = [
+            (
+                "config_path".to_string(),
+                "/project/pyoxidizer.bzl".to_string(),
+            ),
+            (
+                "target_triple".to_string(),
+                "x86_64-unknown-linux-gnu".to_string(),
+            ),
+        ]
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+
+        let line = format_json_record(
+            &Record::builder()
+                .args(format_args!("message"))
+                .level(Level::Info)
+                .target("pyoxidizer")
+                .build(),
+            &fields,
+        );
+
+        let value: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!(value["config_path"], "/project/pyoxidizer.bzl");
+        assert_eq!(value["target_triple"], "x86_64-unknown-linux-gnu");
+        assert_eq!(value["message"], "message");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scoped_log_fields() -> anyhow::Result<()> {
+        let mut first = ScopedLogFields::default();
+        first.set("config_path", "first.bzl");
+        first.set("target_triple", "x86_64-unknown-linux-gnu");
+
+        let mut second = ScopedLogFields::default();
+        second.set("config_path", "second.bzl");
+
+        assert_eq!(log_fields()["config_path"], "second.bzl");
+        assert_eq!(log_fields()["target_triple"], "x86_64-unknown-linux-gnu");
+
+        // Other threads don't see the fields.
+        assert!(std::thread::spawn(log_fields).join().unwrap().is_empty());
+
+        // Dropping out of order doesn't clobber the remaining fields.
+        drop(first);
+        assert_eq!(log_fields()["config_path"], "second.bzl");
+        assert!(!log_fields().contains_key("target_triple"));
+
+        drop(second);
+        assert!(log_fields().is_empty());
+
+        Ok(())
+    }
+}
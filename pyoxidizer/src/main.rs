@@ -30,7 +30,9 @@ a rather effective and powerful tool.
 mod cli;
 mod default_python_distributions;
 mod environment;
+mod events;
 mod licensing;
+mod logging;
 mod project_building;
 mod project_layout;
 mod projectmgmt;
@@ -7,6 +7,7 @@
 use {
     crate::{
         environment::{canonicalize_path, default_target_triple, Environment, PyOxidizerSource},
+        events::EventSink,
         licensing::{licenses_from_cargo_manifest, log_licensing_info},
         project_building::find_pyoxidizer_config_file_env,
         project_layout::{initialize_project, write_new_pyoxidizer_config_file},
@@ -22,6 +23,7 @@ use {
         starlark::eval::EvaluationContextBuilder,
     },
     anyhow::{anyhow, Context, Result},
+    itertools::Itertools,
     python_packaging::licensing::LicenseFlavor,
     python_packaging::{
         filesystem_scanning::find_python_resources,
@@ -34,6 +36,8 @@ use {
         fs::create_dir_all,
         io::{Cursor, Read},
         path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::Duration,
     },
     tugger_file_manifest::{FileData, FileManifest},
 };
@@ -101,19 +105,136 @@ pub fn list_targets(env: &Environment, project_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Construct a distribution cache rendering download and extraction progress in the terminal.
+fn terminal_distribution_cache(
+    env: &Environment,
+    verbose: bool,
+    offline: bool,
+    ca_bundle: Option<&Path>,
+) -> Arc<DistributionCache> {
+    let bar = Mutex::new(None);
+
+    Arc::new(
+        DistributionCache::new(Some(&env.python_distributions_dir()))
+            .offline(offline)
+            .ca_bundle(ca_bundle)
+            .verbose(verbose)
+            .progress(move |current, total| {
+                let total = match total {
+                    Some(total) => total,
+                    None => return,
+                };
+
+                let mut bar = bar.lock().unwrap();
+                let pb = bar.get_or_insert_with(|| {
+                    let mut pb = pbr::ProgressBar::new(total);
+                    pb.set_units(pbr::Units::Bytes);
+                    pb
+                });
+
+                pb.set(current);
+
+                if current >= total {
+                    pb.finish();
+                    *bar = None;
+                }
+            }),
+    )
+}
+
 /// Build a PyOxidizer enabled project.
 ///
 /// This is a glorified wrapper around `cargo build`. Our goal is to get the
 /// output from repackaging to give the user something for debugging.
+///
+/// The config file is evaluated once per entry in `target_triples`, or for the
+/// default target triple if it is empty. Each evaluation writes to a build
+/// directory for its triple. All evaluations share a distribution cache.
 #[allow(clippy::too_many_arguments)]
 pub fn build(
     env: &Environment,
     project_path: &Path,
-    target_triple: Option<&str>,
+    target_triples: &[&str],
     resolve_targets: Option<Vec<String>>,
     extra_vars: HashMap<String, Option<String>>,
     release: bool,
     verbose: bool,
+    offline: bool,
+    ca_bundle: Option<&Path>,
+    distributions_dir: Option<&Path>,
+    dry_run: bool,
+    config_toml: bool,
+    timeout: Option<Duration>,
+    event_log: Option<&Path>,
+) -> Result<()> {
+    let config_path = find_pyoxidizer_config_file_env(project_path).ok_or_else(|| {
+        anyhow!(
+            "unable to find PyOxidizer config file at {}",
+            project_path.display()
+        )
+    })?;
+    let target_triples = if target_triples.is_empty() {
+        vec![resolve_target(None)?]
+    } else {
+        target_triples
+            .iter()
+            .map(|triple| triple.to_string())
+            .unique()
+            .collect::<Vec<_>>()
+    };
+
+    let distribution_cache =
+        terminal_distribution_cache(env, verbose, offline || dry_run, ca_bundle);
+
+    let event_sink = event_log
+        .map(|path| EventSink::create(path).map(Arc::new))
+        .transpose()?;
+
+    let multiple = target_triples.len() > 1;
+
+    for target_triple in target_triples {
+        if multiple {
+            println!("building for {}", target_triple);
+        }
+
+        let mut context =
+            EvaluationContextBuilder::new(env, config_path.clone(), target_triple.clone())
+                .extra_vars(extra_vars.clone())
+                .release(release)
+                .verbose(verbose)
+                .offline(offline)
+                .ca_bundle(ca_bundle)
+                .python_distributions_path(distributions_dir)
+                .dry_run(dry_run)
+                .config_toml(config_toml)
+                .timeout(timeout)
+                .event_sink(event_sink.clone())
+                .distribution_cache(distribution_cache.clone())
+                .resolve_targets_optional(resolve_targets.clone())
+                .into_context()?;
+
+        context
+            .evaluate_file(&config_path)
+            .with_context(|| format!("evaluating config for {}", target_triple))?;
+
+        for target in context.targets_to_resolve()? {
+            context.build_resolved_target(&target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Evaluate a project's config file and print the resolved build context as JSON.
+///
+/// No targets are resolved.
+pub fn dump_context(
+    env: &Environment,
+    project_path: &Path,
+    target_triple: Option<&str>,
+    extra_vars: HashMap<String, Option<String>>,
+    release: bool,
+    verbose: bool,
 ) -> Result<()> {
     let config_path = find_pyoxidizer_config_file_env(project_path).ok_or_else(|| {
         anyhow!(
@@ -127,14 +248,12 @@ pub fn build(
         .extra_vars(extra_vars)
         .release(release)
         .verbose(verbose)
-        .resolve_targets_optional(resolve_targets)
+        .resolve_targets(vec![])
         .into_context()?;
 
     context.evaluate_file(&config_path)?;
 
-    for target in context.targets_to_resolve()? {
-        context.build_resolved_target(&target)?;
-    }
+    println!("{}", context.context_summary_json()?);
 
     Ok(())
 }
@@ -149,6 +268,9 @@ pub fn run(
     extra_vars: HashMap<String, Option<String>>,
     _extra_args: &[&str],
     verbose: bool,
+    offline: bool,
+    ca_bundle: Option<&Path>,
+    distributions_dir: Option<&Path>,
 ) -> Result<()> {
     let config_path = find_pyoxidizer_config_file_env(project_path).ok_or_else(|| {
         anyhow!(
@@ -162,6 +284,12 @@ pub fn run(
         .extra_vars(extra_vars)
         .release(release)
         .verbose(verbose)
+        .offline(offline)
+        .ca_bundle(ca_bundle)
+        .python_distributions_path(distributions_dir)
+        .distribution_cache(terminal_distribution_cache(
+            env, verbose, offline, ca_bundle,
+        ))
         .resolve_target_optional(target)
         .into_context()?;
 
@@ -10,31 +10,36 @@ use {
     super::{
         binary::{LibpythonLinkMode, PythonBinaryBuilder},
         config::PyembedPythonInterpreterConfig,
-        standalone_distribution::StandaloneDistribution,
+        standalone_distribution::{
+            extract_distribution_archive, DistributionArchiveFormat, StandaloneDistribution,
+        },
     },
     crate::{environment::Environment, python_distributions::PYTHON_DISTRIBUTIONS},
     anyhow::{anyhow, Context, Result},
     fs2::FileExt,
-    log::info,
+    log::{info, warn},
     python_packaging::{
         bytecode::PythonBytecodeCompiler, module_util::PythonModuleSuffixes,
         policy::PythonPackagingPolicy, resource::PythonResource,
     },
     sha2::{Digest, Sha256},
     std::{
-        collections::HashMap,
+        collections::{HashMap, VecDeque},
         fmt::{Display, Formatter},
         fs,
         fs::{create_dir_all, File},
-        io::Read,
+        io::{Read, Write},
         ops::DerefMut,
         path::{Path, PathBuf},
-        sync::{Arc, Mutex},
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
     },
-    tugger_common::http::get_http_client,
+    tugger_common::http::{get_http_client_with_ca_bundle, is_transient_error, ProxyConfig},
     tugger_file_manifest::FileEntry,
     url::Url,
-    uuid::Uuid,
 };
 
 /// Denotes how a binary should link libpython.
@@ -54,6 +59,16 @@ pub enum PythonDistributionLocation {
     Url { url: String, sha256: String },
 }
 
+impl PythonDistributionLocation {
+    /// The SHA-256 of the distribution archive content.
+    pub fn sha256(&self) -> &str {
+        match self {
+            Self::Local { sha256, .. } => sha256,
+            Self::Url { sha256, .. } => sha256,
+        }
+    }
+}
+
 impl std::fmt::Display for PythonDistributionLocation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -275,6 +290,132 @@ impl Drop for DistributionExtractLock {
     }
 }
 
+/// Flag requesting that obtaining distributions stop early.
+///
+/// Set it to `true` from another thread to cancel.
+pub type CancellationToken = Arc<AtomicBool>;
+
+/// Error returned when obtaining a distribution was cancelled.
+///
+/// Use `anyhow::Error::downcast_ref` to distinguish it from other failures.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("obtaining Python distribution was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Return a [Cancelled] error if cancellation has been requested.
+pub fn check_cancelled(cancel: Option<&AtomicBool>) -> Result<()> {
+    if cancel.map(|c| c.load(Ordering::Relaxed)).unwrap_or(false) {
+        Err(Cancelled.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// How long to wait for another process to finish downloading a distribution.
+pub const DOWNLOAD_LOCK_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Default number of times a distribution download is retried.
+pub const DEFAULT_DOWNLOAD_RETRIES: u32 = 3;
+
+/// How to retry distribution downloads that fail with transient errors.
+///
+/// Only timeouts, connection errors, and server errors (5xx) are retried.
+/// Other HTTP errors, such as 404, and checksum mismatches fail immediately.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DownloadRetryPolicy {
+    /// Number of retries after the initial attempt.
+    pub retries: u32,
+    /// Delay before the first retry. It doubles for each subsequent retry.
+    pub backoff_base: Duration,
+}
+
+impl Default for DownloadRetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_DOWNLOAD_RETRIES,
+            backoff_base: Duration::from_secs(1),
+        }
+    }
+}
+
+impl DownloadRetryPolicy {
+    /// The delay before retry number `retry`, counting from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.backoff_base
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+    }
+}
+
+/// Multiple processes could race to download the same archive.
+///
+/// This lock is held while downloading an archive so only 1 process fetches it.
+/// The lock is keyed on the archive filename and is released when dropped,
+/// including on error paths.
+pub struct DistributionDownloadLock {
+    file: std::fs::File,
+}
+
+impl DistributionDownloadLock {
+    /// Obtain the lock for an archive path, waiting up to `timeout`.
+    pub fn new(archive_path: &Path, timeout: Duration) -> Result<Self> {
+        let mut lock_path = archive_path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let file = File::create(&lock_path)
+            .with_context(|| format!("could not create {}", lock_path.display()))?;
+
+        let start = Instant::now();
+        let mut logged = false;
+
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => return Ok(Self { file }),
+                Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                    if start.elapsed() >= timeout {
+                        return Err(anyhow!(
+                            "timed out after {}s waiting for lock {}; is another process downloading {}?",
+                            timeout.as_secs(),
+                            lock_path.display(),
+                            archive_path.display()
+                        ));
+                    }
+
+                    if !logged {
+                        info!(
+                            "waiting for another process to download {}",
+                            archive_path.display()
+                        );
+                        logged = true;
+                    }
+
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("failed to obtain lock for {}", lock_path.display())
+                    })
+                }
+            }
+        }
+    }
+}
+
+impl Drop for DistributionDownloadLock {
+    fn drop(&mut self) {
+        // The lock file itself is left in place. Deleting it could race with
+        // another process that has opened it but not yet locked it.
+        let _ = self.file.unlock();
+    }
+}
+
 fn sha256_path(path: &Path) -> Vec<u8> {
     let mut hasher = Sha256::new();
     let fh = File::open(&path).unwrap();
@@ -293,63 +434,236 @@ fn sha256_path(path: &Path) -> Vec<u8> {
     hasher.finalize().to_vec()
 }
 
+/// Obtain the filename component of a distribution URL.
+fn url_basename(url: &Url) -> Result<String> {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|basename| !basename.is_empty())
+        .map(|basename| basename.to_string())
+        .ok_or_else(|| anyhow!("unable to determine filename from URL {}", url))
+}
+
+/// Verify the SHA-256 of Python distribution content matches an expected value.
+fn verify_distribution_sha256(expected: &[u8], actual: &[u8]) -> Result<()> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "sha256 of Python distribution does not validate: expected {}, got {}",
+            hex::encode(expected),
+            hex::encode(actual)
+        ))
+    }
+}
+
+/// Path of the file holding a partially downloaded archive.
+fn partial_download_path(archive_path: &Path) -> PathBuf {
+    let mut path = archive_path.as_os_str().to_owned();
+    path.push(".partial");
+
+    PathBuf::from(path)
+}
+
+/// Parse the start offset and total length from a `Content-Range` header value.
+///
+/// The total length is `None` if the server reports it as unknown.
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+
+    Some((start.parse().ok()?, total))
+}
+
+/// Settings for downloading Python distributions.
+#[derive(Clone, Copy, Default)]
+pub struct DownloadOptions<'a> {
+    /// PEM bundle of additional certificates to trust.
+    pub ca_bundle: Option<&'a Path>,
+
+    /// Called as data is received.
+    ///
+    /// Not called if the archive is already present in the destination.
+    pub progress: Option<&'a DistributionProgressFn>,
+
+    /// How to retry downloads that fail with transient errors.
+    pub retry_policy: DownloadRetryPolicy,
+
+    /// Checked between chunks of received data.
+    ///
+    /// Once set, a [Cancelled] error is returned. Data received so far is
+    /// kept so a later download can resume from it.
+    pub cancellation: Option<&'a AtomicBool>,
+}
+
 /// Ensure a Python distribution at a URL is available in a local directory.
 ///
 /// The path to the downloaded and validated file is returned.
 pub fn download_distribution(url: &str, sha256: &str, cache_dir: &Path) -> Result<PathBuf> {
+    download_distribution_with_options(url, sha256, cache_dir, &DownloadOptions::default())
+}
+
+/// Like [download_distribution] but with custom download settings.
+pub fn download_distribution_with_options(
+    url: &str,
+    sha256: &str,
+    cache_dir: &Path,
+    options: &DownloadOptions,
+) -> Result<PathBuf> {
+    let DownloadOptions {
+        ca_bundle,
+        progress,
+        retry_policy,
+        cancellation: cancel,
+    } = *options;
+
     let expected_hash = hex::decode(sha256)?;
     let u = Url::parse(url)?;
 
-    let basename = u
-        .path_segments()
-        .expect("cannot be base path")
-        .last()
-        .unwrap()
-        .to_string();
+    let cache_path = cache_dir.join(url_basename(&u)?);
 
-    let cache_path = cache_dir.join(basename);
+    // We don't care about timing side-channels from the string compare.
+    if cache_path.exists() && sha256_path(&cache_path) == expected_hash {
+        return Ok(cache_path);
+    }
 
-    if cache_path.exists() {
-        let file_hash = sha256_path(&cache_path);
+    let _lock = DistributionDownloadLock::new(&cache_path, DOWNLOAD_LOCK_TIMEOUT)?;
 
-        // We don't care about timing side-channels from the string compare.
-        if file_hash == expected_hash {
-            return Ok(cache_path);
-        }
+    // Another process may have downloaded the archive while we waited for the lock.
+    if cache_path.exists() && sha256_path(&cache_path) == expected_hash {
+        return Ok(cache_path);
     }
 
-    let mut data: Vec<u8> = Vec::new();
-
     println!("downloading {}", u);
-    let client = get_http_client()?;
-    let mut response = client.get(u.as_str()).send()?;
-    response.read_to_end(&mut data)?;
+    if let Some(proxy) = ProxyConfig::from_env().proxy_for(&u) {
+        // Don't log credentials that may be in the proxy URL.
+        info!(
+            "connecting via proxy {}://{}:{}",
+            proxy.scheme(),
+            proxy.host_str().unwrap_or_default(),
+            proxy.port_or_known_default().unwrap_or_default()
+        );
+    }
+    let client = get_http_client_with_ca_bundle(ca_bundle)?;
 
-    let mut hasher = Sha256::new();
-    hasher.update(&data);
+    // Data is written to a partial file so an interrupted download can be
+    // resumed, both by retries and by later invocations.
+    let partial_path = partial_download_path(&cache_path);
+    // Length of the remote content, as first seen by this invocation.
+    let mut remote_length = None;
 
-    let url_hash = hasher.finalize().to_vec();
-    if url_hash != expected_hash {
-        return Err(anyhow!("sha256 of Python distribution does not validate"));
-    }
+    let mut fetch = || -> Result<()> {
+        check_cancelled(cancel)?;
 
-    let mut temp_cache_path = cache_path.clone();
-    temp_cache_path.set_file_name(format!("{}.tmp", Uuid::new_v4()));
+        let send = |offset: u64| {
+            let mut request = client.get(u.as_str());
+            if offset > 0 {
+                request = request.header("Range", format!("bytes={}-", offset));
+            }
 
-    fs::write(&temp_cache_path, data).context("unable to write distribution file")?;
+            request.send()
+        };
 
-    fs::rename(&temp_cache_path, &cache_path)
-        .or_else(|e| -> Result<()> {
-            fs::remove_file(&temp_cache_path)
-                .context("unable to remove temporary distribution file")?;
+        let mut offset = fs::metadata(&partial_path).map(|m| m.len()).unwrap_or(0);
+        let mut response = send(offset)?;
+        let mut total = None;
+
+        if offset > 0 {
+            let content_range = response
+                .headers()
+                .get("Content-Range")
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_content_range);
+
+            let resumable = response.status().as_u16() == 206
+                && matches!(content_range, Some((start, length))
+                    if start == offset && (remote_length.is_none() || length == remote_length));
+
+            if !resumable {
+                // Servers ignoring the range send the full content, which we can
+                // use. Otherwise request the full content.
+                if response.status().is_success() && response.status().as_u16() != 206 {
+                    info!(
+                        "server does not support resuming {}; downloading in full",
+                        u
+                    );
+                } else {
+                    info!("unable to resume downloading {}; downloading in full", u);
+                    response = send(0)?;
+                }
+                offset = 0;
+            } else {
+                info!("resuming download of {} at byte {}", u, offset);
+                total = content_range.and_then(|(_, total)| total);
+            }
+        }
 
-            if cache_path.exists() {
-                download_distribution(url, sha256, cache_dir)?;
-                return Ok(());
+        let mut response = response.error_for_status()?;
+        if offset == 0 {
+            total = response.content_length();
+        }
+        remote_length = remote_length.or(total);
+
+        let mut fh = if offset > 0 {
+            fs::OpenOptions::new().append(true).open(&partial_path)?
+        } else {
+            File::create(&partial_path)?
+        };
+
+        let mut received = offset;
+        let mut buffer = [0u8; 65536];
+        loop {
+            check_cancelled(cancel)?;
+
+            let count = response.read(&mut buffer)?;
+            if count == 0 {
+                break;
             }
 
-            Err(e.into())
-        })
+            fh.write_all(&buffer[0..count])?;
+            received += count as u64;
+
+            if let Some(progress) = progress {
+                progress(received, total);
+            }
+        }
+
+        Ok(())
+    };
+
+    let mut retry = 0;
+    loop {
+        match fetch() {
+            Ok(()) => break,
+            Err(e) if retry < retry_policy.retries && is_transient_error(&e) => {
+                retry += 1;
+                let delay = retry_policy.backoff(retry);
+                warn!(
+                    "error downloading {}: {:#}; retrying in {:.1}s ({} of {})",
+                    u,
+                    e,
+                    delay.as_secs_f64(),
+                    retry,
+                    retry_policy.retries
+                );
+                std::thread::sleep(delay);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // A partial file with the wrong content can't be resumed from.
+    if let Err(e) = verify_distribution_sha256(&expected_hash, &sha256_path(&partial_path)) {
+        fs::remove_file(&partial_path).context("unable to remove partial distribution file")?;
+
+        return Err(e);
+    }
+
+    fs::rename(&partial_path, &cache_path)
         .context("unable to rename downloaded distribution file")?;
 
     Ok(cache_path)
@@ -373,10 +687,7 @@ pub fn copy_local_distribution(path: &Path, sha256: &str, cache_dir: &Path) -> R
     }
 
     let source_hash = sha256_path(path);
-
-    if source_hash != expected_hash {
-        return Err(anyhow!("sha256 of Python distribution does not validate"));
-    }
+    verify_distribution_sha256(&expected_hash, &source_hash)?;
 
     println!("copying {}", path.display());
     std::fs::copy(path, &cache_path)?;
@@ -384,6 +695,32 @@ pub fn copy_local_distribution(path: &Path, sha256: &str, cache_dir: &Path) -> R
     Ok(cache_path)
 }
 
+/// Ensure a Python distribution archive is available without network access.
+///
+/// Distributions referenced by URL must already be present in `cache_dir` and
+/// pass integrity checks. Local distributions are always available.
+pub fn ensure_distribution_available_offline(
+    location: &PythonDistributionLocation,
+    cache_dir: &Path,
+) -> Result<()> {
+    if let PythonDistributionLocation::Url { url, sha256 } = location {
+        let cache_path = cache_dir.join(url_basename(&Url::parse(url)?)?);
+
+        if !cache_path.exists() {
+            return Err(anyhow!(
+                "offline mode is enabled and Python distribution archive {} does not exist; refusing to download {}",
+                cache_path.display(),
+                url
+            ));
+        }
+
+        verify_distribution_sha256(&hex::decode(sha256)?, &sha256_path(&cache_path))
+            .with_context(|| format!("validating {}", cache_path.display()))?;
+    }
+
+    Ok(())
+}
+
 /// Obtain a local Path for a Python distribution tar archive.
 ///
 /// Takes a parsed config and a cache directory as input. Usually the cache
@@ -412,6 +749,97 @@ pub fn resolve_python_distribution_archive(
     }
 }
 
+/// Obtain the path a distribution archive is stored at in a cache directory.
+fn distribution_archive_cache_path(
+    location: &PythonDistributionLocation,
+    cache_dir: &Path,
+) -> Result<PathBuf> {
+    Ok(match location {
+        PythonDistributionLocation::Local { local_path, .. } => cache_dir.join(
+            Path::new(local_path)
+                .file_name()
+                .ok_or_else(|| anyhow!("unable to determine filename of {}", local_path))?,
+        ),
+        PythonDistributionLocation::Url { url, .. } => {
+            cache_dir.join(url_basename(&Url::parse(url)?)?)
+        }
+    })
+}
+
+/// Name of the directory in a cache directory holding content-addressed archives.
+pub const CONTENT_ADDRESSED_DIR: &str = "sha256";
+
+/// Link a distribution archive to its content-addressed copy.
+///
+/// The archive is stored once under `sha256/<hash>` in `cache_dir` and `path`
+/// becomes a hard link to it, so archives with identical content under
+/// different names share storage. A copy is made if hard links aren't
+/// supported. If `path` doesn't exist but the content-addressed copy does,
+/// `path` is created from it. Nothing is done if `path` already is the
+/// content-addressed copy.
+///
+/// The archive's [DistributionDownloadLock] is held while linking so another
+/// process doesn't observe `path` missing.
+fn link_content_addressed(path: &Path, sha256: &str, cache_dir: &Path) -> Result<()> {
+    let blob_dir = cache_dir.join(CONTENT_ADDRESSED_DIR);
+    create_dir_all(&blob_dir)?;
+    let blob_path = blob_dir.join(sha256.to_lowercase());
+
+    let _lock = DistributionDownloadLock::new(path, DOWNLOAD_LOCK_TIMEOUT)?;
+
+    let link = |src: &Path, dest: &Path| -> Result<()> {
+        fs::hard_link(src, dest)
+            .or_else(|_| fs::copy(src, dest).map(|_| ()))
+            .with_context(|| format!("linking {} to {}", dest.display(), src.display()))
+    };
+
+    if !blob_path.exists() {
+        if path.exists() {
+            link(path, &blob_path)?;
+        }
+
+        return Ok(());
+    }
+
+    if path.exists() {
+        if is_same_file(path, &blob_path)? {
+            return Ok(());
+        }
+
+        fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?;
+    }
+
+    link(&blob_path, path)
+}
+
+/// Whether `path` is already linked (or copied) from `blob_path`.
+///
+/// Hard links share an inode. Copies are only recognized by their length,
+/// which suffices because the blob is named after the content hash.
+fn is_same_file(path: &Path, blob_path: &Path) -> Result<bool> {
+    let metadata = fs::metadata(path)?;
+    let blob_metadata = fs::metadata(blob_path)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if metadata.dev() == blob_metadata.dev() && metadata.ino() == blob_metadata.ino() {
+            return Ok(true);
+        }
+    }
+
+    Ok(metadata.len() == blob_metadata.len())
+}
+
+/// Obtain the name of the directory a distribution is extracted to.
+///
+/// The name is derived only from the distribution's content hash so a
+/// directory of distributions can be moved or shared between machines.
+fn distribution_extract_dir_name(location: &PythonDistributionLocation) -> String {
+    format!("python.{}", &location.sha256().to_lowercase()[0..12])
+}
+
 /// Resolve a Python distribution archive.
 ///
 /// Returns a tuple of (archive path, extract directory).
@@ -423,12 +851,7 @@ pub fn resolve_python_distribution_from_location(
     let path = resolve_python_distribution_archive(location, distributions_dir)?;
     info!("Python distribution available at {}", path.display());
 
-    let distribution_hash = match location {
-        PythonDistributionLocation::Local { sha256, .. } => sha256,
-        PythonDistributionLocation::Url { sha256, .. } => sha256,
-    };
-
-    let distribution_path = distributions_dir.join(format!("python.{}", &distribution_hash[0..12]));
+    let distribution_path = distributions_dir.join(distribution_extract_dir_name(location));
 
     Ok((path, distribution_path))
 }
@@ -476,29 +899,495 @@ impl TryFrom<&str> for DistributionFlavor {
     }
 }
 
-type DistributionCacheKey = (PathBuf, PythonDistributionLocation);
+/// Distributions are keyed by destination directory, archive content hash, and build tag.
+///
+/// Keying by content hash means the same archive referenced from multiple
+/// locations (e.g. a URL and a vendored local copy) is only resolved once.
+/// Distribution flavors are distinct archives, so the hash also keeps
+/// distributions of different flavors for the same version and target apart.
+/// The build tag only labels entries: it isn't verified against the archive
+/// and tagged and untagged entries for the same hash extract to the same
+/// directory.
+///
+/// The destination directory only distinguishes in-memory entries. On-disk
+/// artifacts are named after the archive filename and content hash, never the
+/// destination directory, so a populated directory can be relocated.
+type DistributionCacheKey = (PathBuf, String, Option<String>);
+
+/// Compute the cache key for a distribution location.
+fn distribution_cache_key(
+    location: &PythonDistributionLocation,
+    dest_dir: &Path,
+    build_tag: Option<&str>,
+) -> DistributionCacheKey {
+    (
+        dest_dir.to_path_buf(),
+        location.sha256().to_lowercase(),
+        build_tag.map(|tag| tag.to_string()),
+    )
+}
 type DistributionCacheValue = Arc<Mutex<Option<Arc<StandaloneDistribution>>>>;
 
+/// Function receiving progress of obtaining a Python distribution.
+///
+/// Arguments are the number of bytes processed so far and the total number
+/// of bytes, if known.
+pub type DistributionProgressFn = dyn Fn(u64, Option<u64>) + Send + Sync;
+
+/// Entries in a `DistributionCache`.
+#[derive(Debug, Default)]
+struct DistributionCacheEntries {
+    entries: HashMap<DistributionCacheKey, DistributionCacheValue>,
+
+    /// Keys ordered from least to most recently used.
+    order: VecDeque<DistributionCacheKey>,
+}
+
+impl DistributionCacheEntries {
+    /// Mark a key as the most recently used.
+    fn touch(&mut self, key: &DistributionCacheKey) {
+        if let Some(index) = self.order.iter().position(|k| k == key) {
+            if let Some(key) = self.order.remove(index) {
+                self.order.push_back(key);
+            }
+        }
+    }
+}
+
+/// A Python distribution archive in a local source directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LocalDistributionArchive {
+    /// Path to the archive.
+    pub path: PathBuf,
+    /// Lowercase hex SHA-256 of the archive.
+    pub sha256: String,
+    /// Rust target triple of the distribution, if it is a known distribution.
+    pub target_triple: Option<String>,
+    /// X.Y Python version of the distribution, if it is a known distribution.
+    pub python_major_minor_version: Option<String>,
+}
+
+/// Index distribution archives in a directory.
+///
+/// Files that aren't distribution archives are ignored. Archives are matched
+/// against known distributions by SHA-256 to determine their target triple and
+/// Python version.
+fn index_local_distributions(dir: &Path) -> Result<Vec<LocalDistributionArchive>> {
+    let mut res = vec![];
+
+    for entry in fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+
+        if !path.is_file() || DistributionArchiveFormat::from_path(&path).is_err() {
+            continue;
+        }
+
+        let sha256 = hex::encode(sha256_path(&path));
+        let record = PYTHON_DISTRIBUTIONS
+            .iter()
+            .find(|dist| dist.location.sha256().to_lowercase() == sha256);
+
+        res.push(LocalDistributionArchive {
+            path,
+            sha256,
+            target_triple: record.map(|dist| dist.target_triple.clone()),
+            python_major_minor_version: record.map(|dist| dist.python_major_minor_version.clone()),
+        });
+    }
+
+    res.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(res)
+}
+
 /// Holds references to resolved PythonDistribution instances.
-#[derive(Debug)]
 pub struct DistributionCache {
-    cache: Mutex<HashMap<DistributionCacheKey, DistributionCacheValue>>,
+    cache: Mutex<DistributionCacheEntries>,
     default_dest_dir: Option<PathBuf>,
+    /// Maximum number of entries to hold. `None` means unbounded.
+    capacity: Option<usize>,
+    /// Whether to refuse to download distributions.
+    offline: bool,
+    /// PEM bundle of additional certificates to trust when downloading.
+    ca_bundle: Option<PathBuf>,
+    /// Number of resolutions served from the cache.
+    hits: AtomicU64,
+    /// Number of resolutions that constructed a new distribution.
+    misses: AtomicU64,
+    /// Receives progress of downloading and extracting distributions.
+    progress: Option<Box<DistributionProgressFn>>,
+    /// Whether to log how long constructing distributions takes.
+    verbose: bool,
+    /// Whether archives are stored once per content hash.
+    content_addressed: bool,
+    /// How to retry downloads failing with transient errors.
+    retry_policy: DownloadRetryPolicy,
+    /// Archives to use instead of downloading distributions.
+    local_source: Vec<LocalDistributionArchive>,
+    /// Requests that downloading and extracting distributions stop early.
+    cancellation: Option<CancellationToken>,
+}
+
+impl std::fmt::Debug for DistributionCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DistributionCache")
+            .field("cache", &self.cache)
+            .field("default_dest_dir", &self.default_dest_dir)
+            .field("capacity", &self.capacity)
+            .field("offline", &self.offline)
+            .field("ca_bundle", &self.ca_bundle)
+            .field("hits", &self.hits)
+            .field("misses", &self.misses)
+            .field("progress", &self.progress.is_some())
+            .field("verbose", &self.verbose)
+            .field("content_addressed", &self.content_addressed)
+            .field("retry_policy", &self.retry_policy)
+            .field("local_source", &self.local_source)
+            .field("cancellation", &self.cancellation)
+            .finish()
+    }
+}
+
+/// Counters describing how a `DistributionCache` has been used.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DistributionCacheStats {
+    /// Number of resolutions served from the cache.
+    pub hits: u64,
+    /// Number of resolutions that constructed a new distribution.
+    pub misses: u64,
 }
 
 impl DistributionCache {
     pub fn new(default_dest_dir: Option<&Path>) -> Self {
+        Self::new_with_capacity(default_dest_dir, None)
+    }
+
+    /// Construct an instance holding at most `capacity` distributions.
+    ///
+    /// When full, the least recently used distribution is evicted to make
+    /// room for a new one.
+    pub fn new_with_capacity(default_dest_dir: Option<&Path>, capacity: Option<usize>) -> Self {
         Self {
-            cache: Mutex::new(HashMap::new()),
+            cache: Mutex::new(DistributionCacheEntries::default()),
             default_dest_dir: default_dest_dir.map(|x| x.to_path_buf()),
+            capacity,
+            offline: false,
+            ca_bundle: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            progress: None,
+            verbose: false,
+            content_addressed: false,
+            retry_policy: DownloadRetryPolicy::default(),
+            local_source: vec![],
+            cancellation: None,
         }
     }
 
+    /// Obtain hit and miss counters for this cache.
+    pub fn stats(&self) -> DistributionCacheStats {
+        DistributionCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether archives are stored in a content-addressed layout.
+    pub fn is_content_addressed(&self) -> bool {
+        self.content_addressed
+    }
+
+    /// Obtain the keys of resolved distributions, sorted.
+    ///
+    /// Keys are the lowercase SHA-256 of the distribution archive, suffixed
+    /// with `@<build tag>` for distributions pinned to a build tag. They are
+    /// derived only from the distributions, so they are stable across runs
+    /// regardless of resolution order or where distributions are stored.
+    pub fn cached_distribution_keys(&self) -> Result<Vec<String>> {
+        let lock = self
+            .cache
+            .lock()
+            .map_err(|e| anyhow!("cannot obtain distribution cache lock: {}", e))?;
+
+        let mut keys = vec![];
+
+        for ((_, sha256, build_tag), value) in lock.entries.iter() {
+            let resolved = value
+                .lock()
+                .map_err(|e| anyhow!("cannot obtain distribution lock: {}", e))?
+                .is_some();
+
+            if resolved {
+                keys.push(match build_tag {
+                    Some(tag) => format!("{}@{}", sha256, tag),
+                    None => sha256.clone(),
+                });
+            }
+        }
+
+        keys.sort();
+        keys.dedup();
+
+        Ok(keys)
+    }
+
+    /// Set whether to operate in offline mode.
+    ///
+    /// In offline mode, resolving a distribution that isn't already available
+    /// on the local filesystem is an error instead of triggering a download.
+    #[must_use]
+    pub fn offline(mut self, value: bool) -> Self {
+        self.offline = value;
+        self
+    }
+
+    /// Set a PEM bundle of additional certificates to trust when downloading.
+    #[must_use]
+    pub fn ca_bundle(mut self, path: Option<impl AsRef<Path>>) -> Self {
+        self.ca_bundle = path.map(|p| p.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set a function to receive progress of obtaining distributions.
+    ///
+    /// The function is called as distribution archives are downloaded. It is
+    /// also called when extraction of an archive starts and finishes, with
+    /// the archive size as the total.
+    #[must_use]
+    pub fn progress(mut self, callback: impl Fn(u64, Option<u64>) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Set whether to log the time spent constructing distributions.
+    ///
+    /// Construction can take a few seconds in debug builds. When enabled,
+    /// each cache miss and the elapsed time to resolve it are logged.
+    #[must_use]
+    pub fn verbose(mut self, value: bool) -> Self {
+        self.verbose = value;
+        self
+    }
+
+    /// Set whether to store archives in a content-addressed layout.
+    ///
+    /// When enabled, each archive is stored once under the `sha256/`
+    /// directory of the destination directory and named archives are hard
+    /// links to it. Distributions whose archives have identical content are
+    /// then only stored and downloaded once.
+    #[must_use]
+    pub fn content_addressed(mut self, value: bool) -> Self {
+        self.content_addressed = value;
+        self
+    }
+
+    /// Set how to retry downloads that fail with transient errors.
+    #[must_use]
+    pub fn retry_policy(mut self, policy: DownloadRetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Set a token for cancelling construction of distributions.
+    ///
+    /// The token is checked between chunks of downloaded data and between
+    /// archive members during extraction. Once set, resolving a distribution
+    /// that isn't cached fails with a [Cancelled] error.
+    #[must_use]
+    pub fn cancellation(mut self, token: Option<CancellationToken>) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Resolve distributions from archives in a local directory.
+    ///
+    /// Distributions whose SHA-256 matches an archive in `dir` are obtained
+    /// from it instead of their location. This allows fully offline use when
+    /// combined with offline mode. Archives are indexed once, when this is
+    /// called.
+    pub fn with_local_source(mut self, dir: &Path) -> Result<Self> {
+        self.local_source = index_local_distributions(dir)?;
+
+        Ok(self)
+    }
+
+    /// Find an archive in the local source by target triple and X.Y Python version.
+    pub fn local_distribution(
+        &self,
+        target_triple: &str,
+        python_major_minor_version: &str,
+    ) -> Option<&LocalDistributionArchive> {
+        self.local_source.iter().find(|archive| {
+            archive.target_triple.as_deref() == Some(target_triple)
+                && archive.python_major_minor_version.as_deref() == Some(python_major_minor_version)
+        })
+    }
+
+    /// Ensure a distribution archive is available locally, downloading it if needed.
+    ///
+    /// Returns the paths of the archive and of the directory to extract it to.
+    fn obtain_archive(
+        &self,
+        location: &PythonDistributionLocation,
+        dest_dir: &Path,
+    ) -> Result<(PathBuf, PathBuf)> {
+        if let Some(archive) = self
+            .local_source
+            .iter()
+            .find(|archive| archive.sha256 == location.sha256().to_lowercase())
+        {
+            create_dir_all(dest_dir)?;
+
+            return resolve_python_distribution_from_location(
+                &PythonDistributionLocation::Local {
+                    local_path: archive.path.display().to_string(),
+                    sha256: archive.sha256.clone(),
+                },
+                dest_dir,
+            );
+        }
+
+        if self.offline {
+            ensure_distribution_available_offline(location, dest_dir)?;
+        } else if let PythonDistributionLocation::Url { url, sha256 } = location {
+            info!("resolving Python distribution {}", location);
+            create_dir_all(dest_dir)?;
+            let path = download_distribution_with_options(
+                url,
+                sha256,
+                dest_dir,
+                &DownloadOptions {
+                    ca_bundle: self.ca_bundle.as_deref(),
+                    progress: self.progress.as_deref(),
+                    retry_policy: self.retry_policy,
+                    cancellation: self.cancellation.as_deref(),
+                },
+            )?;
+            info!("Python distribution available at {}", path.display());
+
+            return Ok((path, dest_dir.join(distribution_extract_dir_name(location))));
+        }
+
+        resolve_python_distribution_from_location(location, dest_dir)
+    }
+
+    /// The message to log after constructing a distribution, if any.
+    fn construction_timing_message(
+        &self,
+        location: &PythonDistributionLocation,
+        elapsed: Duration,
+    ) -> Option<String> {
+        if self.verbose {
+            Some(format!(
+                "constructed Python distribution {} in {:.2}s",
+                location.sha256(),
+                elapsed.as_secs_f64()
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn report_progress(&self, current: u64, total: Option<u64>) {
+        if let Some(progress) = &self.progress {
+            progress(current, total);
+        }
+    }
+
+    /// Obtain the entry for a key, inserting an empty one if missing.
+    ///
+    /// Updates the recently used ordering and performs eviction if the cache
+    /// exceeds its capacity.
+    fn get_or_insert_entry(&self, key: &DistributionCacheKey) -> Result<DistributionCacheValue> {
+        let mut lock = self
+            .cache
+            .lock()
+            .map_err(|e| anyhow!("cannot obtain distribution cache lock: {}", e))?;
+
+        if let Some(value) = lock.entries.get(key).cloned() {
+            lock.touch(key);
+
+            return Ok(value);
+        }
+
+        let value = Arc::new(Mutex::new(None));
+        lock.entries.insert(key.clone(), value.clone());
+        lock.order.push_back(key.clone());
+
+        if let Some(capacity) = self.capacity {
+            while lock.entries.len() > capacity {
+                if let Some(evicted) = lock.order.pop_front() {
+                    lock.entries.remove(&evicted);
+                } else {
+                    break;
+                }
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Whether a distribution is available without downloading it.
+    ///
+    /// A distribution is available if it has been resolved by this instance or
+    /// if its archive exists in the destination directory. The archive's
+    /// integrity isn't verified. This never performs network I/O and doesn't
+    /// affect hit and miss counters.
+    pub fn is_cached(
+        &self,
+        location: &PythonDistributionLocation,
+        dest_dir: Option<&Path>,
+        build_tag: Option<&str>,
+    ) -> Result<bool> {
+        let dest_dir = if let Some(p) = dest_dir {
+            p
+        } else if let Some(p) = &self.default_dest_dir {
+            p
+        } else {
+            return Err(anyhow!("no destination directory available"));
+        };
+
+        let key = distribution_cache_key(location, dest_dir, build_tag);
+
+        let entry = self
+            .cache
+            .lock()
+            .map_err(|e| anyhow!("cannot obtain distribution cache lock: {}", e))?
+            .entries
+            .get(&key)
+            .cloned();
+
+        // An entry being resolved by another thread is locked. Fall back to
+        // the filesystem rather than waiting for it.
+        if let Some(entry) = entry {
+            if let Ok(value) = entry.try_lock() {
+                if value.is_some() {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(distribution_archive_cache_path(location, dest_dir)?.exists())
+    }
+
     /// Resolve a `PythonDistribution` given its source and storage locations.
     pub fn resolve_distribution(
         &self,
         location: &PythonDistributionLocation,
         dest_dir: Option<&Path>,
+    ) -> Result<Arc<StandaloneDistribution>> {
+        self.resolve_distribution_with_build_tag(location, dest_dir, None)
+    }
+
+    /// Resolve a `PythonDistribution` pinned to an upstream build tag.
+    ///
+    /// The build tag only affects caching: distributions with different tags
+    /// are never shared, even if their content is identical.
+    pub fn resolve_distribution_with_build_tag(
+        &self,
+        location: &PythonDistributionLocation,
+        dest_dir: Option<&Path>,
+        build_tag: Option<&str>,
     ) -> Result<Arc<StandaloneDistribution>> {
         let dest_dir = if let Some(p) = dest_dir {
             p
@@ -508,7 +1397,7 @@ impl DistributionCache {
             return Err(anyhow!("no destination directory available"));
         };
 
-        let key = (dest_dir.to_path_buf(), location.clone());
+        let key = distribution_cache_key(location, dest_dir, build_tag);
 
         // This logic is whack. Surely there's a cleaner way to do this...
         //
@@ -542,21 +1431,7 @@ impl DistributionCache {
         // value resolution. Multiple threads can resolve different keys in
         // parallel. By other threads will be blocked resolving a single key.
 
-        let entry = {
-            let mut lock = self
-                .cache
-                .lock()
-                .map_err(|e| anyhow!("cannot obtain distribution cache lock: {}", e))?;
-
-            if let Some(value) = lock.get(&key) {
-                value.clone()
-            } else {
-                let value = Arc::new(Mutex::new(None));
-                lock.insert(key.clone(), value.clone());
-
-                value
-            }
-        };
+        let entry = self.get_or_insert_entry(&key)?;
 
         let mut lock = entry
             .lock()
@@ -565,9 +1440,51 @@ impl DistributionCache {
         let value = lock.deref_mut();
 
         if let Some(dist) = value {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+
             Ok(dist.clone())
         } else {
-            let dist = Arc::new(StandaloneDistribution::from_location(location, dest_dir)?);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+
+            check_cancelled(self.cancellation.as_deref())?;
+
+            let start = Instant::now();
+            if self.verbose {
+                info!(
+                    "distribution cache miss for {}; constructing",
+                    location.sha256()
+                );
+            }
+
+            // Materialize the named archive from its content-addressed copy so
+            // it isn't downloaded again.
+            if self.content_addressed {
+                let archive_path = distribution_archive_cache_path(location, dest_dir)?;
+                if !archive_path.exists() {
+                    link_content_addressed(&archive_path, location.sha256(), dest_dir)?;
+                }
+            }
+
+            let (archive_path, extract_path) = self
+                .obtain_archive(location, dest_dir)
+                .with_context(|| format!("obtaining {}", describe_distribution(location)))?;
+            if self.content_addressed {
+                link_content_addressed(&archive_path, location.sha256(), dest_dir)?;
+            }
+            let archive_size = fs::metadata(&archive_path)?.len();
+
+            self.report_progress(0, Some(archive_size));
+            extract_distribution_archive(
+                &archive_path,
+                &extract_path,
+                self.cancellation.as_deref(),
+            )?;
+            let dist = Arc::new(StandaloneDistribution::from_directory(&extract_path)?);
+            self.report_progress(archive_size, Some(archive_size));
+
+            if let Some(message) = self.construction_timing_message(location, start.elapsed()) {
+                info!("{}", message);
+            }
 
             lock.replace(dist.clone());
 
@@ -620,6 +1537,23 @@ pub fn default_distribution_location(
     Ok(dist.location)
 }
 
+/// Describe a distribution location for use in error messages.
+///
+/// Known distributions are matched by their SHA-256, so mirrored copies are
+/// also described with the target triple and Python version they provide.
+pub fn describe_distribution(location: &PythonDistributionLocation) -> String {
+    match PYTHON_DISTRIBUTIONS
+        .iter()
+        .find(|dist| dist.location.sha256() == location.sha256())
+    {
+        Some(dist) => format!(
+            "Python {} distribution for {} from {}",
+            dist.python_major_minor_version, dist.target_triple, location
+        ),
+        None => format!("Python distribution from {}", location),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use {super::*, crate::testutil::*};
@@ -630,4 +1564,769 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_copy_local_distribution_hash_mismatch() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let source_path = temp_dir.path().join("dist.tar.zst");
+        std::fs::write(&source_path, b"not a distribution")?;
+        let cache_dir = temp_dir.path().join("cache");
+        std::fs::create_dir(&cache_dir)?;
+
+        let expected = "00".repeat(32);
+        let actual = hex::encode(sha256_path(&source_path));
+
+        let err = copy_local_distribution(&source_path, &expected, &cache_dir).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains(&expected));
+        assert!(message.contains(&actual));
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_cache_offline() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let cache = DistributionCache::new(Some(temp_dir.path())).offline(true);
+
+        let location = PythonDistributionLocation::Url {
+            url: "https://example.com/cpython-3.10.4-x86_64-unknown-linux-gnu.tar.zst".to_string(),
+            sha256: "00".repeat(32),
+        };
+
+        let err = cache.resolve_distribution(&location, None).unwrap_err();
+        let message = format!("{:?}", err);
+        assert!(message.contains("offline mode is enabled"));
+        assert!(message.contains(
+            &temp_dir
+                .path()
+                .join("cpython-3.10.4-x86_64-unknown-linux-gnu.tar.zst")
+                .display()
+                .to_string()
+        ));
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_cache_stats() -> Result<()> {
+        let env = get_env()?;
+        let cache = DistributionCache::new(Some(&env.python_distributions_dir()));
+        assert_eq!(cache.stats(), DistributionCacheStats::default());
+
+        let a = cache.host_distribution(None, None)?;
+        let b = cache.host_distribution(None, None)?;
+        assert!(Arc::ptr_eq(&a, &b));
+
+        assert_eq!(cache.stats(), DistributionCacheStats { hits: 1, misses: 1 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_cache_timing_message() {
+        let location = PythonDistributionLocation::Url {
+            url: "https://example.com/python.tar.zst".to_string(),
+            sha256: "ab".repeat(32),
+        };
+        let elapsed = Duration::from_millis(2500);
+
+        let cache = DistributionCache::new(None);
+        assert!(cache
+            .construction_timing_message(&location, elapsed)
+            .is_none());
+
+        let cache = DistributionCache::new(None).verbose(true);
+        assert_eq!(
+            cache.construction_timing_message(&location, elapsed),
+            Some(format!(
+                "constructed Python distribution {} in 2.50s",
+                "ab".repeat(32)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_distribution_cache_is_cached() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let location = default_distribution_location(
+            &DistributionFlavor::Standalone,
+            crate::environment::default_target_triple(),
+            None,
+        )?;
+
+        let cache = DistributionCache::new(Some(temp_dir.path()));
+        assert!(!cache.is_cached(&location, None, None)?);
+
+        let cache = DistributionCache::new(Some(&env.python_distributions_dir()));
+        cache.resolve_distribution(&location, None)?;
+        assert!(cache.is_cached(&location, None, None)?);
+        assert_eq!(cache.stats(), DistributionCacheStats { hits: 0, misses: 1 });
+
+        // The archive on disk is sufficient.
+        let cache = DistributionCache::new(Some(&env.python_distributions_dir()));
+        assert!(cache.is_cached(&location, None, None)?);
+
+        // A local archive is cached once it has been copied to the destination.
+        let local = PythonDistributionLocation::Local {
+            local_path: temp_dir.path().join("python.tar.zst").display().to_string(),
+            sha256: "00".repeat(32),
+        };
+        let dest_dir = temp_dir.path().join("dest");
+        assert!(!cache.is_cached(&local, Some(&dest_dir), None)?);
+        std::fs::create_dir_all(&dest_dir)?;
+        std::fs::write(dest_dir.join("python.tar.zst"), b"")?;
+        assert!(cache.is_cached(&local, Some(&dest_dir), None)?);
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_content_addressed() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+        let cache_dir = temp_dir.path();
+
+        let sha256 = "AB".repeat(32);
+        let a = cache_dir.join("cpython-x86_64-unknown-linux-gnu.tar.zst");
+        let b = cache_dir.join("cpython-x86_64-unknown-linux-musl.tar.zst");
+        std::fs::write(&a, b"archive")?;
+        std::fs::write(&b, b"archive")?;
+
+        link_content_addressed(&a, &sha256, cache_dir)?;
+        link_content_addressed(&b, &sha256, cache_dir)?;
+
+        // Identical content is stored once.
+        let blobs = std::fs::read_dir(cache_dir.join(CONTENT_ADDRESSED_DIR))?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        assert_eq!(blobs, vec![std::ffi::OsString::from("ab".repeat(32))]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let blob = std::fs::metadata(cache_dir.join(CONTENT_ADDRESSED_DIR).join(&blobs[0]))?;
+            assert_eq!(blob.nlink(), 3);
+            assert_eq!(std::fs::metadata(&a)?.ino(), blob.ino());
+            assert_eq!(std::fs::metadata(&b)?.ino(), blob.ino());
+        }
+
+        // Linking again leaves an already linked archive alone.
+        let blob_path = cache_dir.join(CONTENT_ADDRESSED_DIR).join(&blobs[0]);
+        assert!(is_same_file(&a, &blob_path)?);
+        link_content_addressed(&a, &sha256, cache_dir)?;
+        assert_eq!(std::fs::read(&a)?, b"archive");
+
+        let c = cache_dir.join("other.tar.zst");
+        std::fs::write(&c, b"other archive")?;
+        assert!(!is_same_file(&c, &blob_path)?);
+
+        // Missing named archives are restored from the content-addressed copy.
+        std::fs::remove_file(&a)?;
+        link_content_addressed(&a, &sha256, cache_dir)?;
+        assert_eq!(std::fs::read(&a)?, b"archive");
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_cache_key_build_tag() {
+        let location = PythonDistributionLocation::Url {
+            url: "https://example.com/python.tar.zst".to_string(),
+            sha256: "AB".repeat(32),
+        };
+        let dest_dir = Path::new("dest");
+
+        let unpinned = distribution_cache_key(&location, dest_dir, None);
+        let a = distribution_cache_key(&location, dest_dir, Some("20211017"));
+        let b = distribution_cache_key(&location, dest_dir, Some("20220227"));
+
+        assert_ne!(unpinned, a);
+        assert_ne!(a, b);
+        assert_eq!(
+            a,
+            distribution_cache_key(&location, dest_dir, Some("20211017"))
+        );
+    }
+
+    #[test]
+    fn test_distribution_cache_flavors() -> Result<()> {
+        let cache = DistributionCache::new(None);
+        let dest_dir = Path::new("dest");
+
+        let mut pairs = 0;
+
+        let combinations = PYTHON_DISTRIBUTIONS
+            .iter()
+            .map(|dist| {
+                (
+                    dist.target_triple.as_str(),
+                    dist.python_major_minor_version.as_str(),
+                )
+            })
+            .collect::<std::collections::BTreeSet<_>>();
+
+        for (triple, version) in combinations {
+            let resolve =
+                |flavor| PYTHON_DISTRIBUTIONS.find_distribution(triple, &flavor, Some(version));
+
+            let (dynamic, r#static) = match (
+                resolve(DistributionFlavor::StandaloneDynamic),
+                resolve(DistributionFlavor::StandaloneStatic),
+            ) {
+                (Some(dynamic), Some(r#static)) => (dynamic.location, r#static.location),
+                _ => continue,
+            };
+
+            let dynamic_key = distribution_cache_key(&dynamic, dest_dir, None);
+            let static_key = distribution_cache_key(&r#static, dest_dir, None);
+            assert_ne!(dynamic_key, static_key);
+            assert_ne!(
+                distribution_extract_dir_name(&dynamic),
+                distribution_extract_dir_name(&r#static)
+            );
+
+            let dynamic_entry = cache.get_or_insert_entry(&dynamic_key)?;
+            let static_entry = cache.get_or_insert_entry(&static_key)?;
+            assert!(!Arc::ptr_eq(&dynamic_entry, &static_entry));
+
+            pairs += 1;
+        }
+
+        // Windows distributions are available in both flavors.
+        assert!(pairs > 0);
+        assert_eq!(cache.cache.lock().unwrap().entries.len(), pairs * 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cached_distribution_keys() -> Result<()> {
+        let env = get_env()?;
+        let cache = DistributionCache::new(Some(&env.python_distributions_dir()));
+        assert!(cache.cached_distribution_keys()?.is_empty());
+
+        let dist = cache.host_distribution(None, None)?;
+        let location = default_distribution_location(
+            &DistributionFlavor::Standalone,
+            crate::environment::default_target_triple(),
+            None,
+        )?;
+        let sha256 = location.sha256().to_lowercase();
+
+        // Unresolved entries aren't listed.
+        cache.get_or_insert_entry(&(PathBuf::from("other"), "ff".repeat(32), None))?;
+
+        // Populate a second entry, pinned to a build tag, in another directory.
+        let key = (
+            PathBuf::from("other"),
+            sha256.clone(),
+            Some("0".to_string()),
+        );
+        cache
+            .get_or_insert_entry(&key)?
+            .lock()
+            .unwrap()
+            .replace(dist);
+
+        let keys = cache.cached_distribution_keys()?;
+        assert_eq!(keys, vec![sha256.clone(), format!("{}@0", sha256)]);
+        assert_eq!(cache.cached_distribution_keys()?, keys);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_cache_progress() -> Result<()> {
+        let env = get_env()?;
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let events_inner = events.clone();
+
+        let cache = DistributionCache::new(Some(&env.python_distributions_dir())).progress(
+            move |current, total| {
+                events_inner.lock().unwrap().push((current, total));
+            },
+        );
+        cache.host_distribution(None, None)?;
+
+        let events = events.lock().unwrap();
+        let (_, total) = events.last().cloned().unwrap();
+        let total = total.unwrap();
+        assert!(total > 0);
+        assert!(events.contains(&(0, Some(total))));
+        assert_eq!(events.last(), Some(&(total, Some(total))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_cache_relocatable() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let location = default_distribution_location(
+            &DistributionFlavor::Standalone,
+            crate::environment::default_target_triple(),
+            None,
+        )?;
+        let archive_path =
+            resolve_python_distribution_archive(&location, &env.python_distributions_dir())?;
+
+        let root_a = temp_dir.path().join("a");
+        std::fs::create_dir(&root_a)?;
+        std::fs::copy(
+            &archive_path,
+            root_a.join(archive_path.file_name().unwrap()),
+        )?;
+
+        DistributionCache::new(Some(&root_a))
+            .offline(true)
+            .resolve_distribution(&location, None)?;
+
+        let root_b = temp_dir.path().join("b");
+        std::fs::rename(&root_a, &root_b)?;
+
+        let marker = root_b
+            .join(distribution_extract_dir_name(&location))
+            .join("python")
+            .join("PYTHON.json");
+        let mtime = std::fs::metadata(&marker)?.modified()?;
+
+        // Offline mode proves the archive isn't downloaded again.
+        let dist = DistributionCache::new(Some(&root_b))
+            .offline(true)
+            .resolve_distribution(&location, None)?;
+
+        assert!(dist.python_exe_path().starts_with(&root_b));
+        assert_eq!(std::fs::metadata(&marker)?.modified()?, mtime);
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_lock_timeout() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+        let archive_path = temp_dir.path().join("python.tar.zst");
+
+        let lock = DistributionDownloadLock::new(&archive_path, Duration::from_secs(1))?;
+
+        let err = DistributionDownloadLock::new(&archive_path, Duration::from_millis(200))
+            .err()
+            .unwrap();
+        assert!(format!("{}", err).contains("timed out"));
+
+        drop(lock);
+        DistributionDownloadLock::new(&archive_path, Duration::from_millis(200))?;
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_cache_capacity() -> Result<()> {
+        let cache = DistributionCache::new_with_capacity(None, Some(2));
+
+        let keys = (0..3)
+            .map(|i| (PathBuf::from("dest"), format!("{:064}", i), None))
+            .collect::<Vec<_>>();
+
+        cache.get_or_insert_entry(&keys[0])?;
+        cache.get_or_insert_entry(&keys[1])?;
+        // Using the first entry makes the second the least recently used.
+        cache.get_or_insert_entry(&keys[0])?;
+        cache.get_or_insert_entry(&keys[2])?;
+
+        let lock = cache.cache.lock().unwrap();
+        assert_eq!(lock.entries.len(), 2);
+        assert!(lock.entries.contains_key(&keys[0]));
+        assert!(!lock.entries.contains_key(&keys[1]));
+        assert!(lock.entries.contains_key(&keys[2]));
+
+        Ok(())
+    }
+
+    /// Serve canned HTTP responses, one per connection, on a local port.
+    ///
+    /// Returns the base URL and the requests received.
+    fn serve_http_responses(responses: Vec<Vec<u8>>) -> Result<(String, Arc<Mutex<Vec<String>>>)> {
+        use std::io::Write;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+
+                let mut request = vec![];
+                let mut buffer = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let count = stream.read(&mut buffer).unwrap();
+                    if count == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[0..count]);
+                }
+
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&request).to_string());
+                stream.write_all(&response).unwrap();
+            }
+        });
+
+        Ok((url, requests))
+    }
+
+    fn http_response(status: &str, body: &[u8]) -> Vec<u8> {
+        let mut res = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            body.len()
+        )
+        .into_bytes();
+        res.extend_from_slice(body);
+        res
+    }
+
+    #[test]
+    fn test_download_retry_policy_backoff() {
+        let policy = DownloadRetryPolicy {
+            retries: 3,
+            backoff_base: Duration::from_millis(100),
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(DownloadRetryPolicy::default().retries, 3);
+    }
+
+    #[test]
+    fn test_download_distribution_retry() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let content = b"distribution content";
+        let sha256 = hex::encode(Sha256::digest(content));
+        let policy = DownloadRetryPolicy {
+            retries: 3,
+            backoff_base: Duration::from_millis(1),
+        };
+
+        // Server errors are retried.
+        let (url, requests) = serve_http_responses(vec![
+            http_response("503 Service Unavailable", b""),
+            http_response("500 Internal Server Error", b""),
+            http_response("200 OK", content),
+        ])?;
+        let path = download_distribution_with_options(
+            &format!("{}/retry.tar.zst", url),
+            &sha256,
+            temp_dir.path(),
+            &DownloadOptions {
+                retry_policy: policy,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(fs::read(&path)?, content);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+
+        // Not found is not retried.
+        let (url, requests) = serve_http_responses(vec![
+            http_response("404 Not Found", b""),
+            http_response("200 OK", content),
+        ])?;
+        assert!(download_distribution_with_options(
+            &format!("{}/missing.tar.zst", url),
+            &sha256,
+            temp_dir.path(),
+            &DownloadOptions {
+                retry_policy: policy,
+                ..Default::default()
+            },
+        )
+        .is_err());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // Nor is a checksum mismatch.
+        let (url, requests) = serve_http_responses(vec![
+            http_response("200 OK", b"other content"),
+            http_response("200 OK", content),
+        ])?;
+        let err = download_distribution_with_options(
+            &format!("{}/mismatch.tar.zst", url),
+            &sha256,
+            temp_dir.path(),
+            &DownloadOptions {
+                retry_policy: policy,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("does not validate"));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // Giving up after exhausting retries.
+        let (url, requests) = serve_http_responses(vec![
+            http_response("503 Service Unavailable", b""),
+            http_response("503 Service Unavailable", b""),
+        ])?;
+        assert!(download_distribution_with_options(
+            &format!("{}/unavailable.tar.zst", url),
+            &sha256,
+            temp_dir.path(),
+            &DownloadOptions {
+                retry_policy: DownloadRetryPolicy {
+                    retries: 1,
+                    backoff_base: Duration::from_millis(1),
+                },
+                ..Default::default()
+            },
+        )
+        .is_err());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 10-99/100"),
+            Some((10, Some(100)))
+        );
+        assert_eq!(parse_content_range("bytes 10-99/*"), Some((10, None)));
+        assert_eq!(parse_content_range("bytes */100"), None);
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+
+    #[test]
+    fn test_download_distribution_resume() -> Result<()> {
+        use std::io::Write;
+
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let content = b"0123456789abcdefghijklmnopqrstuvwxyz";
+        let sha256 = hex::encode(Sha256::digest(content));
+        let policy = DownloadRetryPolicy {
+            retries: 3,
+            backoff_base: Duration::from_millis(1),
+        };
+
+        // The connection is closed midway through the first response.
+        let mut interrupted = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            content.len()
+        )
+        .into_bytes();
+        interrupted.write_all(&content[0..10])?;
+
+        let mut resumed = format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 10-{}/{}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            content.len() - 1,
+            content.len(),
+            content.len() - 10
+        )
+        .into_bytes();
+        resumed.write_all(&content[10..])?;
+
+        let (url, requests) = serve_http_responses(vec![interrupted, resumed])?;
+        let path = download_distribution_with_options(
+            &format!("{}/resume.tar.zst", url),
+            &sha256,
+            temp_dir.path(),
+            &DownloadOptions {
+                retry_policy: policy,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(fs::read(&path)?, content);
+        assert!(!partial_download_path(&path).exists());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].to_lowercase().contains("range:"));
+        assert!(requests[1].to_lowercase().contains("range: bytes=10-"));
+
+        // Servers not supporting ranges send the full content.
+        let archive_path = temp_dir.path().join("norange.tar.zst");
+        fs::write(partial_download_path(&archive_path), &content[0..5])?;
+
+        let (url, requests) = serve_http_responses(vec![http_response("200 OK", content)])?;
+        let path = download_distribution_with_options(
+            &format!("{}/norange.tar.zst", url),
+            &sha256,
+            temp_dir.path(),
+            &DownloadOptions {
+                retry_policy: policy,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(fs::read(&path)?, content);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_download_error_context() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let record = PYTHON_DISTRIBUTIONS
+            .find_distribution(
+                "x86_64-unknown-linux-gnu",
+                &DistributionFlavor::Standalone,
+                None,
+            )
+            .unwrap();
+
+        // A mirror of a known distribution that fails to serve it.
+        let (url, _) = serve_http_responses(vec![http_response("404 Not Found", b"")])?;
+        let location = PythonDistributionLocation::Url {
+            url: format!("{}/mirror.tar.zst", url),
+            sha256: record.location.sha256().to_string(),
+        };
+
+        let cache =
+            DistributionCache::new(Some(temp_dir.path())).retry_policy(DownloadRetryPolicy {
+                retries: 0,
+                backoff_base: Duration::from_millis(1),
+            });
+        let err = format!(
+            "{:?}",
+            cache.resolve_distribution(&location, None).unwrap_err()
+        );
+
+        assert!(err.contains(&format!(
+            "obtaining Python {} distribution for x86_64-unknown-linux-gnu from {}/mirror.tar.zst",
+            record.python_major_minor_version, url
+        )));
+        assert!(err.contains("404"));
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_cache_local_source() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let record = PYTHON_DISTRIBUTIONS
+            .find_distribution(
+                crate::environment::default_target_triple(),
+                &DistributionFlavor::Standalone,
+                None,
+            )
+            .unwrap();
+
+        // Populate a fixture directory with a distribution archive.
+        get_distribution(&record.location)?;
+        let fixtures = temp_dir.path().join("fixtures");
+        create_dir_all(&fixtures)?;
+        fs::copy(
+            distribution_archive_cache_path(&record.location, &env.python_distributions_dir())?,
+            fixtures.join("python.tar.zst"),
+        )?;
+        fs::write(fixtures.join("README"), "not a distribution")?;
+
+        let cache = DistributionCache::new(Some(&temp_dir.path().join("dest")))
+            .offline(true)
+            .with_local_source(&fixtures)?;
+
+        let archive = cache
+            .local_distribution(&record.target_triple, &record.python_major_minor_version)
+            .unwrap();
+        assert_eq!(archive.path, fixtures.join("python.tar.zst"));
+        assert_eq!(archive.sha256, record.location.sha256());
+        assert!(cache
+            .local_distribution(&record.target_triple, "2.7")
+            .is_none());
+
+        // The remote location is served from the fixture despite offline mode.
+        let dist = cache.resolve_distribution(&record.location, None)?;
+        assert_eq!(
+            dist.python_major_minor_version(),
+            record.python_major_minor_version
+        );
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_distribution_cache_cancellation() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        // Content large enough to be received in several chunks.
+        let content = vec![42u8; 1024 * 1024];
+        let sha256 = hex::encode(Sha256::digest(&content));
+        let (url, _) = serve_http_responses(vec![http_response("200 OK", &content)])?;
+        let location = PythonDistributionLocation::Url {
+            url: format!("{}/cancel.tar.zst", url),
+            sha256,
+        };
+
+        // Cancel once the download is under way.
+        let token = CancellationToken::default();
+        let cancel = token.clone();
+        let cache = DistributionCache::new(Some(temp_dir.path()))
+            .cancellation(Some(token))
+            .progress(move |received, _| {
+                if received > 0 {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            });
+
+        let err = cache.resolve_distribution(&location, None).unwrap_err();
+        assert_eq!(err.downcast_ref::<Cancelled>(), Some(&Cancelled));
+
+        // Received data is retained for resuming.
+        let archive_path = temp_dir.path().join("cancel.tar.zst");
+        assert!(!archive_path.exists());
+        let partial_len = fs::metadata(partial_download_path(&archive_path))?.len();
+        assert!(partial_len > 0 && partial_len < content.len() as u64);
+
+        // Nothing further is attempted while cancelled.
+        let err = cache.resolve_distribution(&location, None).unwrap_err();
+        assert!(err.downcast_ref::<Cancelled>().is_some());
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
 }
@@ -16,6 +16,7 @@ pub mod embedding;
 pub mod filtering;
 pub mod libpython;
 pub mod packaging_tool;
+pub mod placeholder_builder;
 pub mod resource;
 pub mod standalone_builder;
 pub mod standalone_distribution;
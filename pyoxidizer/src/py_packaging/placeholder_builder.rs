@@ -0,0 +1,305 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*!
+A `PythonBinaryBuilder` standing in for an executable whose Python
+distribution is unavailable.
+*/
+
+use {
+    super::{
+        binary::{
+            LibpythonLinkMode, PackedResourcesLoadMode, PythonBinaryBuilder,
+            ResourceAddCollectionContextCallback, WindowsRuntimeDllsMode,
+        },
+        distribution::AppleSdkInfo,
+        embedding::EmbeddedPythonContext,
+    },
+    crate::environment::Environment,
+    anyhow::{anyhow, Result},
+    python_packaging::{
+        licensing::{LicensedComponent, LicensedComponents},
+        policy::PythonPackagingPolicy,
+        resource::{
+            PythonExtensionModule, PythonModuleSource, PythonPackageDistributionResource,
+            PythonPackageResource, PythonResource,
+        },
+        resource_collection::{
+            AddResourceAction, PrePackagedResource, PythonResourceAddCollectionContext,
+        },
+    },
+    std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+    tugger_file_manifest::File,
+    tugger_windows::VcRedistributablePlatform,
+};
+
+/// A `PythonBinaryBuilder` that records settings but holds no resources.
+///
+/// Used in dry run mode so configs can call `PythonExecutable` methods
+/// without a Python distribution. Adding resources is accepted and
+/// ignored, running Python (e.g. `pip_install()`) yields no resources, and
+/// the builder can't be turned into an embedded Python context.
+#[derive(Clone, Debug)]
+pub struct PlaceholderPythonExecutableBuilder {
+    name: String,
+    target_triple: String,
+    packaging_policy: PythonPackagingPolicy,
+    python_exe: PathBuf,
+    licensed_components: LicensedComponents,
+    resources_load_mode: PackedResourcesLoadMode,
+    licenses_filename: Option<String>,
+    windows_subsystem: String,
+    tcl_files_path: Option<String>,
+    windows_runtime_dlls_mode: WindowsRuntimeDllsMode,
+}
+
+impl PlaceholderPythonExecutableBuilder {
+    pub fn new(name: &str, target_triple: &str, packaging_policy: PythonPackagingPolicy) -> Self {
+        Self {
+            name: name.to_string(),
+            target_triple: target_triple.to_string(),
+            packaging_policy,
+            python_exe: PathBuf::new(),
+            licensed_components: LicensedComponents::default(),
+            resources_load_mode: PackedResourcesLoadMode::EmbeddedInBinary(
+                "packed-resources".to_string(),
+            ),
+            licenses_filename: Some("COPYING.txt".into()),
+            windows_subsystem: "console".to_string(),
+            tcl_files_path: None,
+            windows_runtime_dlls_mode: WindowsRuntimeDllsMode::WhenPresent,
+        }
+    }
+}
+
+impl PythonBinaryBuilder for PlaceholderPythonExecutableBuilder {
+    fn clone_trait(&self) -> Arc<dyn PythonBinaryBuilder> {
+        Arc::new(self.clone())
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn libpython_link_mode(&self) -> LibpythonLinkMode {
+        LibpythonLinkMode::Static
+    }
+
+    fn target_triple(&self) -> &str {
+        &self.target_triple
+    }
+
+    fn vc_runtime_requirements(&self) -> Option<(String, VcRedistributablePlatform)> {
+        None
+    }
+
+    fn cache_tag(&self) -> &str {
+        ""
+    }
+
+    fn python_packaging_policy(&self) -> &PythonPackagingPolicy {
+        &self.packaging_policy
+    }
+
+    fn host_python_exe_path(&self) -> &Path {
+        &self.python_exe
+    }
+
+    fn target_python_exe_path(&self) -> &Path {
+        &self.python_exe
+    }
+
+    fn apple_sdk_info(&self) -> Option<&AppleSdkInfo> {
+        None
+    }
+
+    fn windows_runtime_dlls_mode(&self) -> &WindowsRuntimeDllsMode {
+        &self.windows_runtime_dlls_mode
+    }
+
+    fn set_windows_runtime_dlls_mode(&mut self, value: WindowsRuntimeDllsMode) {
+        self.windows_runtime_dlls_mode = value;
+    }
+
+    fn tcl_files_path(&self) -> &Option<String> {
+        &self.tcl_files_path
+    }
+
+    fn set_tcl_files_path(&mut self, value: Option<String>) {
+        self.tcl_files_path = value;
+    }
+
+    fn windows_subsystem(&self) -> &str {
+        &self.windows_subsystem
+    }
+
+    fn set_windows_subsystem(&mut self, value: &str) -> Result<()> {
+        self.windows_subsystem = value.to_string();
+
+        Ok(())
+    }
+
+    fn licenses_filename(&self) -> Option<&str> {
+        self.licenses_filename.as_deref()
+    }
+
+    fn set_licenses_filename(&mut self, value: Option<String>) {
+        self.licenses_filename = value;
+    }
+
+    fn packed_resources_load_mode(&self) -> &PackedResourcesLoadMode {
+        &self.resources_load_mode
+    }
+
+    fn set_packed_resources_load_mode(&mut self, load_mode: PackedResourcesLoadMode) {
+        self.resources_load_mode = load_mode;
+    }
+
+    fn iter_resources<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (&'a String, &'a PrePackagedResource)> + 'a> {
+        Box::new(std::iter::empty())
+    }
+
+    fn index_package_license_info_from_resources<'a>(
+        &mut self,
+        _resources: &[PythonResource<'a>],
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn pip_download(
+        &mut self,
+        _env: &Environment,
+        _verbose: bool,
+        _args: &[String],
+    ) -> Result<Vec<PythonResource>> {
+        Ok(vec![])
+    }
+
+    fn pip_install(
+        &mut self,
+        _env: &Environment,
+        _verbose: bool,
+        _install_args: &[String],
+        _extra_envs: &HashMap<String, String>,
+    ) -> Result<Vec<PythonResource>> {
+        Ok(vec![])
+    }
+
+    fn read_package_root(
+        &mut self,
+        _path: &Path,
+        _packages: &[String],
+    ) -> Result<Vec<PythonResource>> {
+        Ok(vec![])
+    }
+
+    fn read_virtualenv(&mut self, _path: &Path) -> Result<Vec<PythonResource>> {
+        Ok(vec![])
+    }
+
+    fn setup_py_install(
+        &mut self,
+        _env: &Environment,
+        _package_path: &Path,
+        _verbose: bool,
+        _extra_envs: &HashMap<String, String>,
+        _extra_global_arguments: &[String],
+    ) -> Result<Vec<PythonResource>> {
+        Ok(vec![])
+    }
+
+    fn add_distribution_resources(
+        &mut self,
+        _callback: Option<ResourceAddCollectionContextCallback>,
+    ) -> Result<Vec<AddResourceAction>> {
+        Ok(vec![])
+    }
+
+    fn add_python_module_source(
+        &mut self,
+        _module: &PythonModuleSource,
+        _add_context: Option<PythonResourceAddCollectionContext>,
+    ) -> Result<Vec<AddResourceAction>> {
+        Ok(vec![])
+    }
+
+    fn add_python_package_resource(
+        &mut self,
+        _resource: &PythonPackageResource,
+        _add_context: Option<PythonResourceAddCollectionContext>,
+    ) -> Result<Vec<AddResourceAction>> {
+        Ok(vec![])
+    }
+
+    fn add_python_package_distribution_resource(
+        &mut self,
+        _resource: &PythonPackageDistributionResource,
+        _add_context: Option<PythonResourceAddCollectionContext>,
+    ) -> Result<Vec<AddResourceAction>> {
+        Ok(vec![])
+    }
+
+    fn add_python_extension_module(
+        &mut self,
+        _extension_module: &PythonExtensionModule,
+        _add_context: Option<PythonResourceAddCollectionContext>,
+    ) -> Result<Vec<AddResourceAction>> {
+        Ok(vec![])
+    }
+
+    fn add_file_data(
+        &mut self,
+        _file: &File,
+        _add_context: Option<PythonResourceAddCollectionContext>,
+    ) -> Result<Vec<AddResourceAction>> {
+        Ok(vec![])
+    }
+
+    fn filter_resources_from_files(
+        &mut self,
+        _files: &[&Path],
+        _glob_patterns: &[&str],
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn requires_jemalloc(&self) -> bool {
+        false
+    }
+
+    fn requires_mimalloc(&self) -> bool {
+        false
+    }
+
+    fn requires_snmalloc(&self) -> bool {
+        false
+    }
+
+    fn licensed_components(&self) -> Result<LicensedComponents> {
+        Ok(self.licensed_components.clone())
+    }
+
+    fn add_licensed_component(&mut self, component: LicensedComponent) -> Result<()> {
+        self.licensed_components.add_component(component);
+
+        Ok(())
+    }
+
+    fn to_embedded_python_context(
+        &self,
+        _env: &Environment,
+        _opt_level: &str,
+    ) -> Result<EmbeddedPythonContext> {
+        Err(anyhow!(
+            "{} has no Python distribution and can't be built",
+            self.name
+        ))
+    }
+}
@@ -9,8 +9,9 @@ use {
         binary::{LibpythonLinkMode, PythonBinaryBuilder},
         config::{default_memory_allocator, PyembedPythonInterpreterConfig},
         distribution::{
-            resolve_python_distribution_from_location, AppleSdkInfo, BinaryLibpythonLinkMode,
-            DistributionExtractLock, PythonDistribution, PythonDistributionLocation,
+            check_cancelled, resolve_python_distribution_from_location, AppleSdkInfo,
+            BinaryLibpythonLinkMode, DistributionExtractLock, PythonDistribution,
+            PythonDistributionLocation,
         },
         distutils::prepare_hacked_distutils,
         standalone_builder::StandalonePythonExecutableBuilder,
@@ -39,7 +40,7 @@ use {
         collections::{hash_map::RandomState, BTreeMap, HashMap},
         io::{BufRead, BufReader, Read},
         path::{Path, PathBuf},
-        sync::Arc,
+        sync::{atomic::AtomicBool, Arc},
     },
     tugger_file_manifest::{FileData, FileEntry},
 };
@@ -220,12 +221,13 @@ struct PythonJsonMain {
 
 fn parse_python_json(path: &Path) -> Result<PythonJsonMain> {
     if !path.exists() {
-        return Err(anyhow!("PYTHON.json does not exist; are you using an up-to-date Python distribution that conforms with our requirements?"));
+        return Err(anyhow!("{} does not exist; are you using an up-to-date Python distribution that conforms with our requirements?", path.display()));
     }
 
     let buf = std::fs::read(path)?;
 
-    let value: serde_json::Value = serde_json::from_slice(&buf)?;
+    let value: serde_json::Value = serde_json::from_slice(&buf)
+        .with_context(|| format!("{} is not valid JSON", path.display()))?;
     let o = value
         .as_object()
         .ok_or_else(|| anyhow!("PYTHON.json does not parse to an object"))?;
@@ -246,7 +248,8 @@ fn parse_python_json(path: &Path) -> Result<PythonJsonMain> {
         None => return Err(anyhow!("version key not present in PYTHON.json")),
     }
 
-    let v: PythonJsonMain = serde_json::from_slice(&buf)?;
+    let v: PythonJsonMain = serde_json::from_slice(&buf)
+        .with_context(|| format!("{} has unexpected content", path.display()))?;
 
     Ok(v)
 }
@@ -499,6 +502,199 @@ pub struct StandaloneDistribution {
     config_vars: HashMap<String, String>,
 }
 
+/// Compression formats of Python distribution archives.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DistributionArchiveFormat {
+    /// A zstd compressed tar archive (`.tar.zst`).
+    TarZstd,
+    /// A gzip compressed tar archive (`.tar.gz`).
+    TarGzip,
+}
+
+impl DistributionArchiveFormat {
+    /// Detect the format of an archive from its leading bytes.
+    pub fn from_magic(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::TarZstd)
+        } else if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGzip)
+        } else {
+            None
+        }
+    }
+
+    /// Detect the format of an archive file.
+    ///
+    /// Detection uses the file's content, not its extension.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let mut fh = std::fs::File::open(path)
+            .with_context(|| format!("unable to open {}", path.display()))?;
+
+        let mut magic = Vec::with_capacity(4);
+        (&mut fh)
+            .take(4)
+            .read_to_end(&mut magic)
+            .with_context(|| format!("reading {}", path.display()))?;
+
+        Self::from_magic(&magic).ok_or_else(|| {
+            anyhow!(
+                "unhandled distribution format: {}; only zstd and gzip compressed tar archives are supported",
+                path.display()
+            )
+        })
+    }
+}
+
+/// Extract a Python distribution archive to a directory.
+///
+/// The compression format is detected from the archive's content. See
+/// [extract_distribution_tar] for the meaning of `cancel`.
+pub fn extract_distribution_archive(
+    path: &Path,
+    extract_dir: &Path,
+    cancel: Option<&AtomicBool>,
+) -> Result<()> {
+    let format = DistributionArchiveFormat::from_path(path)?;
+
+    let fh =
+        std::fs::File::open(path).with_context(|| format!("unable to open {}", path.display()))?;
+    let reader = BufReader::new(fh);
+
+    match format {
+        DistributionArchiveFormat::TarZstd => {
+            extract_distribution_tar(zstd::stream::Decoder::new(reader)?, extract_dir, cancel)
+                .context("reading tar.zst distribution data")
+        }
+        DistributionArchiveFormat::TarGzip => {
+            extract_distribution_tar(flate2::read::GzDecoder::new(reader), extract_dir, cancel)
+                .context("reading tar.gz distribution data")
+        }
+    }
+}
+
+/// Extract a tar stream holding a Python distribution to a directory.
+///
+/// Extraction is skipped if the directory already holds a distribution.
+///
+/// `cancel` is checked before each archive member is extracted. If it is
+/// set, the partially extracted directory is removed and a
+/// [Cancelled](super::distribution::Cancelled) error is returned.
+#[allow(clippy::unnecessary_unwrap)]
+pub fn extract_distribution_tar<R: Read>(
+    source: R,
+    extract_dir: &Path,
+    cancel: Option<&AtomicBool>,
+) -> Result<()> {
+    let mut tf = tar::Archive::new(source);
+
+    let _lock = DistributionExtractLock::new(extract_dir)?;
+
+    // The content of the distribution could change between runs. But caching
+    // the extraction does keep things fast.
+    let test_path = extract_dir.join("python").join("PYTHON.json");
+    if !test_path.exists() {
+        std::fs::create_dir_all(extract_dir)?;
+        let absolute_path = std::fs::canonicalize(extract_dir)?;
+
+        let mut symlinks = vec![];
+
+        for entry in tf.entries()? {
+            if let Err(e) = check_cancelled(cancel) {
+                // A partial extraction could be mistaken for a complete one.
+                std::fs::remove_dir_all(&absolute_path).with_context(|| {
+                    format!("removing partial extraction {}", absolute_path.display())
+                })?;
+
+                return Err(e);
+            }
+
+            let mut entry = entry.map_err(|e| anyhow!("failed to iterate over archive: {}", e))?;
+
+            // The mtimes in the archive may be 0 / UNIX epoch. This shouldn't
+            // matter. However, pip will sometimes attempt to produce a zip file of
+            // its own content and Python's zip code won't handle times before 1980,
+            // which is later than UNIX epoch. This can lead to pip blowing up at
+            // run-time. We work around this by not adjusting the mtime when
+            // extracting the archive. This effectively makes the mtime "now."
+            entry.set_preserve_mtime(false);
+
+            // Windows doesn't support symlinks without special permissions.
+            // So we track symlinks explicitly and copy files post extract if
+            // running on that platform.
+            let link_name = entry.link_name().unwrap_or(None);
+
+            if link_name.is_some() && cfg!(target_family = "windows") {
+                // The entry's path is the file to write, relative to the archive's
+                // root. We need to expand to an absolute path to facilitate copying.
+
+                // The link name is the file to symlink to, or the file we're copying.
+                // This path is relative to the entry path. So we need join with the
+                // entry's directory and canonicalize. There is also a security issue
+                // at play: archives could contain bogus symlinks pointing outside the
+                // archive. So we detect this, just in case.
+
+                let mut dest = absolute_path.clone();
+                dest.extend(entry.path()?.components());
+                let dest = dest
+                    .parse_dot()
+                    .with_context(|| "dedotting symlinked source")?
+                    .to_path_buf();
+
+                let mut source = dest
+                    .parent()
+                    .ok_or_else(|| anyhow!("unable to resolve parent"))?
+                    .to_path_buf();
+                source.extend(link_name.unwrap().components());
+                let source = source
+                    .parse_dot()
+                    .with_context(|| "dedotting symlink destination")?
+                    .to_path_buf();
+
+                if !source.starts_with(&absolute_path) {
+                    return Err(anyhow!("malicious symlink detected in archive"));
+                }
+
+                symlinks.push((source, dest));
+            } else {
+                entry
+                    .unpack_in(&absolute_path)
+                    .with_context(|| "unable to extract tar member")?;
+            }
+        }
+
+        for (source, dest) in symlinks {
+            std::fs::copy(&source, &dest).with_context(|| {
+                format!(
+                    "copying symlinked file {} -> {}",
+                    source.display(),
+                    dest.display(),
+                )
+            })?;
+        }
+
+        // Ensure unpacked files are writable. We've had issues where we
+        // consume archives with read-only file permissions. When we later
+        // copy these files, we can run into trouble overwriting a read-only
+        // file.
+        let walk = walkdir::WalkDir::new(&absolute_path);
+        for entry in walk.into_iter() {
+            let entry = entry?;
+
+            let metadata = entry.metadata()?;
+            let mut permissions = metadata.permissions();
+
+            if permissions.readonly() {
+                permissions.set_readonly(false);
+                std::fs::set_permissions(entry.path(), permissions).with_context(|| {
+                    format!("unable to mark {} as writable", entry.path().display())
+                })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 impl StandaloneDistribution {
     pub fn from_location(
         location: &PythonDistributionLocation,
@@ -507,28 +703,16 @@ impl StandaloneDistribution {
         let (archive_path, extract_path) =
             resolve_python_distribution_from_location(location, distributions_dir)?;
 
-        Self::from_tar_zst_file(&archive_path, &extract_path)
+        Self::from_archive_file(&archive_path, &extract_path)
     }
 
-    /// Create an instance from a .tar.zst file.
+    /// Create an instance from a .tar.zst or .tar.gz file.
     ///
     /// The distribution will be extracted to ``extract_dir`` if necessary.
-    pub fn from_tar_zst_file(path: &Path, extract_dir: &Path) -> Result<Self> {
-        let basename = path
-            .file_name()
-            .ok_or_else(|| anyhow!("unable to determine filename"))?
-            .to_string_lossy();
-
-        if !basename.ends_with(".tar.zst") {
-            return Err(anyhow!("unhandled distribution format: {}", path.display()));
-        }
+    pub fn from_archive_file(path: &Path, extract_dir: &Path) -> Result<Self> {
+        extract_distribution_archive(path, extract_dir, None)?;
 
-        let fh = std::fs::File::open(path)
-            .with_context(|| format!("unable to open {}", path.display()))?;
-
-        let reader = BufReader::new(fh);
-
-        Self::from_tar_zst(reader, extract_dir).context("reading tar.zst distribution data")
+        Self::from_directory(extract_dir)
     }
 
     /// Extract and analyze a standalone distribution from a zstd compressed tar stream.
@@ -539,108 +723,8 @@ impl StandaloneDistribution {
     }
 
     /// Extract and analyze a standalone distribution from a tar stream.
-    #[allow(clippy::unnecessary_unwrap)]
     pub fn from_tar<R: Read>(source: R, extract_dir: &Path) -> Result<Self> {
-        let mut tf = tar::Archive::new(source);
-
-        {
-            let _lock = DistributionExtractLock::new(extract_dir)?;
-
-            // The content of the distribution could change between runs. But caching
-            // the extraction does keep things fast.
-            let test_path = extract_dir.join("python").join("PYTHON.json");
-            if !test_path.exists() {
-                std::fs::create_dir_all(extract_dir)?;
-                let absolute_path = std::fs::canonicalize(extract_dir)?;
-
-                let mut symlinks = vec![];
-
-                for entry in tf.entries()? {
-                    let mut entry =
-                        entry.map_err(|e| anyhow!("failed to iterate over archive: {}", e))?;
-
-                    // The mtimes in the archive may be 0 / UNIX epoch. This shouldn't
-                    // matter. However, pip will sometimes attempt to produce a zip file of
-                    // its own content and Python's zip code won't handle times before 1980,
-                    // which is later than UNIX epoch. This can lead to pip blowing up at
-                    // run-time. We work around this by not adjusting the mtime when
-                    // extracting the archive. This effectively makes the mtime "now."
-                    entry.set_preserve_mtime(false);
-
-                    // Windows doesn't support symlinks without special permissions.
-                    // So we track symlinks explicitly and copy files post extract if
-                    // running on that platform.
-                    let link_name = entry.link_name().unwrap_or(None);
-
-                    if link_name.is_some() && cfg!(target_family = "windows") {
-                        // The entry's path is the file to write, relative to the archive's
-                        // root. We need to expand to an absolute path to facilitate copying.
-
-                        // The link name is the file to symlink to, or the file we're copying.
-                        // This path is relative to the entry path. So we need join with the
-                        // entry's directory and canonicalize. There is also a security issue
-                        // at play: archives could contain bogus symlinks pointing outside the
-                        // archive. So we detect this, just in case.
-
-                        let mut dest = absolute_path.clone();
-                        dest.extend(entry.path()?.components());
-                        let dest = dest
-                            .parse_dot()
-                            .with_context(|| "dedotting symlinked source")?
-                            .to_path_buf();
-
-                        let mut source = dest
-                            .parent()
-                            .ok_or_else(|| anyhow!("unable to resolve parent"))?
-                            .to_path_buf();
-                        source.extend(link_name.unwrap().components());
-                        let source = source
-                            .parse_dot()
-                            .with_context(|| "dedotting symlink destination")?
-                            .to_path_buf();
-
-                        if !source.starts_with(&absolute_path) {
-                            return Err(anyhow!("malicious symlink detected in archive"));
-                        }
-
-                        symlinks.push((source, dest));
-                    } else {
-                        entry
-                            .unpack_in(&absolute_path)
-                            .with_context(|| "unable to extract tar member")?;
-                    }
-                }
-
-                for (source, dest) in symlinks {
-                    std::fs::copy(&source, &dest).with_context(|| {
-                        format!(
-                            "copying symlinked file {} -> {}",
-                            source.display(),
-                            dest.display(),
-                        )
-                    })?;
-                }
-
-                // Ensure unpacked files are writable. We've had issues where we
-                // consume archives with read-only file permissions. When we later
-                // copy these files, we can run into trouble overwriting a read-only
-                // file.
-                let walk = walkdir::WalkDir::new(&absolute_path);
-                for entry in walk.into_iter() {
-                    let entry = entry?;
-
-                    let metadata = entry.metadata()?;
-                    let mut permissions = metadata.permissions();
-
-                    if permissions.readonly() {
-                        permissions.set_readonly(false);
-                        std::fs::set_permissions(entry.path(), permissions).with_context(|| {
-                            format!("unable to mark {} as writable", entry.path().display())
-                        })?;
-                    }
-                }
-            }
-        }
+        extract_distribution_tar(source, extract_dir, None)?;
 
         Self::from_directory(extract_dir)
     }
@@ -1457,7 +1541,7 @@ impl PythonDistribution for StandaloneDistribution {
 pub mod tests {
     use {
         super::*,
-        crate::testutil::*,
+        crate::{py_packaging::distribution::Cancelled, testutil::*},
         python_packaging::{
             bytecode::CompileMode, policy::ExtensionModuleFilter,
             resource::BytecodeOptimizationLevel,
@@ -1465,6 +1549,105 @@ pub mod tests {
         std::collections::BTreeSet,
     };
 
+    #[test]
+    fn test_extract_distribution_archive_formats() -> Result<()> {
+        let temp_dir = get_env()?.temporary_directory("pyoxidizer-test")?;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in [
+            ("python/PYTHON.json", b"{}".as_ref()),
+            ("python/install/lib/foo.py", b"print('foo')".as_ref()),
+            ("python/LICENSE.rst", b"license".as_ref()),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data)?;
+        }
+        let tar_data = builder.into_inner()?;
+
+        let zst_path = temp_dir.path().join("dist.tar.zst");
+        std::fs::write(&zst_path, zstd::stream::encode_all(tar_data.as_slice(), 3)?)?;
+
+        let gz_path = temp_dir.path().join("dist.tar.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &tar_data)?;
+        std::fs::write(&gz_path, encoder.finish()?)?;
+
+        assert_eq!(
+            DistributionArchiveFormat::from_path(&zst_path)?,
+            DistributionArchiveFormat::TarZstd
+        );
+        assert_eq!(
+            DistributionArchiveFormat::from_path(&gz_path)?,
+            DistributionArchiveFormat::TarGzip
+        );
+
+        let tree = |root: &Path| -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+            let mut files = BTreeMap::new();
+            for entry in walkdir::WalkDir::new(root) {
+                let entry = entry?;
+                if entry.file_type().is_file() {
+                    files.insert(
+                        entry.path().strip_prefix(root)?.to_path_buf(),
+                        std::fs::read(entry.path())?,
+                    );
+                }
+            }
+            Ok(files)
+        };
+
+        let zst_dir = temp_dir.path().join("zst");
+        let gz_dir = temp_dir.path().join("gz");
+        extract_distribution_archive(&zst_path, &zst_dir, None)?;
+        extract_distribution_archive(&gz_path, &gz_dir, None)?;
+
+        let zst_tree = tree(&zst_dir)?;
+        assert_eq!(zst_tree.len(), 3);
+        assert_eq!(zst_tree, tree(&gz_dir)?);
+
+        // Formats are detected from content, not the file extension.
+        let plain_path = temp_dir.path().join("dist.tar.gz.tar");
+        std::fs::write(&plain_path, &tar_data)?;
+        let err = extract_distribution_archive(&plain_path, &temp_dir.path().join("plain"), None)
+            .unwrap_err();
+        assert!(err.to_string().contains("unhandled distribution format"));
+
+        // Cancelled extraction leaves nothing behind.
+        let cancelled_dir = temp_dir.path().join("cancelled");
+        let err =
+            extract_distribution_archive(&zst_path, &cancelled_dir, Some(&AtomicBool::new(true)))
+                .unwrap_err();
+        assert!(err.downcast_ref::<Cancelled>().is_some());
+        assert!(!cancelled_dir.exists());
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_python_json_invalid() -> Result<()> {
+        let temp_dir = get_env()?.temporary_directory("pyoxidizer-test")?;
+        let python_json = temp_dir.path().join("PYTHON.json");
+
+        let err = parse_python_json(&python_json).unwrap_err();
+        assert!(format!("{}", err).contains("does not exist"));
+
+        std::fs::write(&python_json, b"not json")?;
+        let err = parse_python_json(&python_json).unwrap_err();
+        assert!(format!("{}", err).contains("is not valid JSON"));
+
+        std::fs::write(&python_json, b"{\"version\": \"7\"}")?;
+        let err = parse_python_json(&python_json).unwrap_err();
+        assert!(format!("{}", err).contains("has unexpected content"));
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_stdlib_annotations() -> Result<()> {
         let distribution = get_default_distribution(None)?;
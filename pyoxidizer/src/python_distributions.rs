@@ -49,13 +49,11 @@ impl PythonDistributionCollection {
     }
 
     /// Obtain records for all registered distributions.
-    #[allow(unused)]
     pub fn iter(&self) -> impl Iterator<Item = &PythonDistributionRecord> {
         self.dists.iter()
     }
 
     /// All target triples of distributions in this collection.
-    #[allow(unused)]
     pub fn all_target_triples(&self) -> impl Iterator<Item = &str> {
         self.dists
             .iter()
@@ -3,25 +3,380 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use {
-    crate::py_packaging::distribution::DistributionCache,
-    anyhow::{Context, Result},
+    super::{loader::ConfigFileLoader, path::resolve_relative_path, util::ToValue},
+    crate::{
+        environment::resolve_host_triple,
+        events::{BuildEvent, EventSink},
+        py_packaging::distribution::{
+            default_distribution_location, CancellationToken, DistributionCache, DistributionFlavor,
+        },
+        python_distributions::PYTHON_DISTRIBUTIONS,
+    },
+    anyhow::{anyhow, Context, Result},
+    codemap::CodeMap,
+    itertools::Itertools,
+    log::{error, info, warn},
+    serde::Serialize,
+    sha2::{Digest, Sha256},
     starlark::{
         environment::{Environment, EnvironmentError, TypeValues},
+        syntax::dialect::Dialect,
         values::{
+            dict::Dictionary,
             error::{RuntimeError, ValueError},
             none::NoneType,
             {Mutable, TypedValue, Value, ValueResult},
         },
+        {
+            starlark_fun, starlark_module, starlark_parse_param_type, starlark_signature,
+            starlark_signature_extraction, starlark_signatures,
+        },
+    },
+    starlark_dialect_build_targets::{
+        check_sandbox, get_context_value, optional_str_arg, required_list_arg, required_type_arg,
+        EnvironmentContext, PrintCapture,
     },
-    starlark_dialect_build_targets::{get_context_value, EnvironmentContext},
     std::{
-        collections::HashMap,
+        collections::{BTreeMap, BTreeSet, HashMap},
         path::{Path, PathBuf},
-        sync::Arc,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
     },
     tugger::starlark::TuggerContext,
 };
 
+/// Map a target triple to its canonical Rust spelling.
+///
+/// Common architecture aliases are normalized and a missing vendor component
+/// is filled in, so e.g. `x86_64-windows-msvc` becomes `x86_64-pc-windows-msvc`.
+/// Triples not matching a known alias are returned unchanged, aside from
+/// surrounding whitespace and case.
+pub fn canonical_target_triple(triple: &str) -> String {
+    let triple = triple.trim().to_lowercase();
+    let mut parts = triple.split('-').collect::<Vec<_>>();
+
+    parts[0] = match parts[0] {
+        "amd64" | "x64" => "x86_64",
+        "arm64" => "aarch64",
+        "x86" => "i686",
+        arch => arch,
+    };
+
+    let rest = match parts[1..] {
+        ["windows", env] => vec!["pc", "windows", env],
+        ["linux", env] => vec!["unknown", "linux", env],
+        ["darwin"] | ["macos"] | ["apple", "macos"] => vec!["apple", "darwin"],
+        _ => parts[1..].to_vec(),
+    };
+
+    std::iter::once(parts[0]).chain(rest).join("-")
+}
+
+/// Operating system family a target triple belongs to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TargetOsFamily {
+    Linux,
+    MacOs,
+    Windows,
+    Other,
+}
+
+impl TargetOsFamily {
+    /// Classify a target triple by operating system family.
+    ///
+    /// All Windows environments (MSVC and GNU) are classified as `Windows`.
+    pub fn from_triple(triple: &str) -> Self {
+        let parts = triple.split('-').collect::<Vec<_>>();
+
+        if parts.contains(&"windows") {
+            Self::Windows
+        } else if parts.contains(&"darwin") {
+            Self::MacOs
+        } else if parts.contains(&"linux") {
+            Self::Linux
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Whether a target triple links against the musl C library.
+pub fn target_triple_is_musl(triple: &str) -> bool {
+    triple.split('-').any(|part| part.starts_with("musl"))
+}
+
+/// Target triples PyOxidizer can build for, sorted.
+///
+/// These are the triples we have Python distributions for.
+pub fn supported_target_triples() -> Vec<String> {
+    PYTHON_DISTRIBUTIONS
+        .all_target_triples()
+        .map(|triple| triple.to_string())
+        .collect()
+}
+
+/// Ensure a target triple is one we have Python distributions for.
+pub fn validate_target_triple(triple: &str) -> Result<()> {
+    let supported = supported_target_triples();
+
+    if supported.iter().any(|candidate| candidate == triple) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "unsupported target triple {}; supported triples are: {}",
+            triple,
+            supported.join(", ")
+        ))
+    }
+}
+
+/// Whether a version satisfies a version requirement like `>=0.22`.
+///
+/// Pre-release versions are treated as the release they precede, so
+/// development builds satisfy requirements on the version being developed.
+pub fn version_satisfies(version: &str, requirement: &str) -> Result<bool> {
+    let requirement = semver::VersionReq::parse(requirement)
+        .with_context(|| format!("parsing version requirement {}", requirement))?;
+    let mut version =
+        semver::Version::parse(version).with_context(|| format!("parsing version {}", version))?;
+    version.pre = semver::Prerelease::EMPTY;
+
+    Ok(requirement.matches(&version))
+}
+
+/// Error code for Starlark errors caused by exceeding the evaluation timeout.
+pub const TIMEOUT_ERROR_CODE: &str = "PYOXIDIZER_TIMEOUT";
+
+/// Error code for Starlark errors raised by `fail()`.
+pub const FAIL_ERROR_CODE: &str = "PYOXIDIZER_CONFIG_FAIL";
+
+/// Error code for failures to resolve the PyOxidizer context.
+pub const NO_CONTEXT_ERROR_CODE: &str = "PYOXIDIZER_NO_CONTEXT";
+
+/// Error code for failures configuring a build.
+pub const BUILD_ERROR_CODE: &str = "PYOXIDIZER_BUILD";
+
+/// Errors raised by PyOxidizer's Starlark functions that callers may want to distinguish.
+///
+/// Values convert into a [ValueError] carrying [StarlarkError::code()] as the
+/// error code. `ValueError` only holds strings, so the message includes the
+/// full chain of any source error.
+#[derive(Debug)]
+pub enum StarlarkError {
+    /// The PyOxidizer context could not be resolved from the type values.
+    MissingContext {
+        /// Names of values that were registered for the `PyOxidizer` type.
+        available: Vec<String>,
+    },
+    /// The Python distributions path could not be set.
+    DistributionsPath(anyhow::Error),
+}
+
+impl StarlarkError {
+    /// The error code of the `ValueError` this error converts to.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MissingContext { .. } => NO_CONTEXT_ERROR_CODE,
+            Self::DistributionsPath(_) => BUILD_ERROR_CODE,
+        }
+    }
+
+    /// The label of the `ValueError` this error converts to.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::MissingContext { .. } => "get_context()",
+            Self::DistributionsPath(_) => "set_python_distributions_path()",
+        }
+    }
+}
+
+impl std::fmt::Display for StarlarkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingContext { available } => write!(
+                f,
+                "unable to resolve {}.CONTEXT (was populate_environment() called?); registered values: {}",
+                PyOxidizerContext::TYPE,
+                if available.is_empty() {
+                    "<none>".to_string()
+                } else {
+                    available.join(", ")
+                }
+            ),
+            Self::DistributionsPath(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for StarlarkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::MissingContext { .. } => None,
+            Self::DistributionsPath(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl From<StarlarkError> for ValueError {
+    fn from(e: StarlarkError) -> Self {
+        ValueError::from(RuntimeError {
+            code: e.code(),
+            message: e.to_string(),
+            label: e.label().to_string(),
+        })
+    }
+}
+
+/// Name of the TOML file next to a config file whose keys become Starlark globals.
+pub const CONFIG_TOML_FILENAME: &str = "pyoxidizer.toml";
+
+/// Convert a TOML value to a Starlark `Value`.
+fn toml_to_value(key: &str, value: &toml::Value) -> Result<Value> {
+    Ok(match value {
+        toml::Value::String(v) => Value::from(v.as_str()),
+        toml::Value::Integer(v) => Value::from(*v),
+        toml::Value::Boolean(v) => Value::from(*v),
+        toml::Value::Array(values) => Value::from(
+            values
+                .iter()
+                .map(|v| toml_to_value(key, v))
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        _ => {
+            return Err(anyhow!(
+                "{} has unsupported type {}; only strings, integers, booleans, and arrays are supported",
+                key,
+                value.type_str()
+            ))
+        }
+    })
+}
+
+/// Load top-level values from the TOML file next to a config file, if present.
+///
+/// Returns an empty map if the file does not exist.
+pub fn load_config_toml(config_dir: &Path) -> Result<BTreeMap<String, Value>> {
+    let path = config_dir.join(CONFIG_TOML_FILENAME);
+
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let data =
+        std::fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+    let table = data
+        .parse::<toml::Value>()
+        .with_context(|| format!("parsing {}", path.display()))?;
+    let table = table
+        .as_table()
+        .ok_or_else(|| anyhow!("{} is not a TOML table", path.display()))?;
+
+    table
+        .iter()
+        .map(|(key, value)| {
+            Ok((
+                key.clone(),
+                toml_to_value(key, value).with_context(|| format!("loading {}", path.display()))?,
+            ))
+        })
+        .collect()
+}
+
+/// Keywords that can't be used as Starlark identifiers.
+const STARLARK_KEYWORDS: &[&str] = &[
+    "and", "break", "continue", "def", "elif", "else", "for", "if", "in", "lambda", "load", "not",
+    "or", "pass", "return", "None", "True", "False",
+];
+
+/// Validate that a string is a legal Starlark identifier.
+pub fn validate_identifier(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+
+    let valid = match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    };
+
+    if valid && !STARLARK_KEYWORDS.contains(&name) {
+        Ok(())
+    } else {
+        Err(anyhow!("{:?} is not a valid Starlark identifier", name))
+    }
+}
+
+/// Machine-readable summary of the settings a build context resolved to.
+///
+/// Fields are declared in sorted order so serialized output is stable.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct BuildContextSummary {
+    pub build_host_triple: String,
+    pub build_opt_level: String,
+    pub build_path: PathBuf,
+    pub build_release: bool,
+    pub build_target_triple: String,
+    pub python_distributions_path: PathBuf,
+}
+
+/// Optimization levels accepted for building binaries.
+const VALID_OPT_LEVELS: &[&str] = &["0", "1", "2", "3", "s", "z"];
+
+/// Validate and normalize an optimization level.
+///
+/// Surrounding whitespace and case are ignored.
+pub fn normalize_opt_level(level: &str) -> Result<String> {
+    let normalized = level.trim().to_lowercase();
+
+    if VALID_OPT_LEVELS.contains(&normalized.as_str()) {
+        Ok(normalized)
+    } else {
+        Err(anyhow!(
+            "invalid optimization level {}; must be one of {}",
+            level,
+            VALID_OPT_LEVELS.join(", ")
+        ))
+    }
+}
+
+/// Default maximum size in bytes of files `read_file()` will read.
+pub const DEFAULT_READ_FILE_MAX_SIZE: u64 = 4 * 1024 * 1024;
+
+/// A Starlark environment that `include()` evaluates files into.
+#[derive(Clone)]
+pub struct IncludeEnvironment(pub Environment);
+
+impl std::fmt::Debug for IncludeEnvironment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("IncludeEnvironment")
+    }
+}
+
+/// How an evaluation context obtains Python distributions.
+#[derive(Clone, Debug, Default)]
+pub struct DistributionOptions {
+    /// Whether to refuse to download Python distributions.
+    pub offline: bool,
+
+    /// PEM bundle of additional certificates to trust when downloading.
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Whether Python distribution archives are stored once per content hash.
+    pub content_addressed: bool,
+
+    /// Directory of distribution archives to use instead of downloading.
+    pub local_distributions: Option<PathBuf>,
+
+    /// Requests that downloading and extracting distributions stop early.
+    pub cancellation: Option<CancellationToken>,
+
+    /// Cache of resolved distributions to use.
+    ///
+    /// A provided cache retains its own settings; the other options only
+    /// apply to the cache constructed when this is `None`.
+    pub cache: Option<Arc<DistributionCache>>,
+}
+
 /// Holds state for evaluating a Starlark config file.
 #[derive(Debug)]
 pub struct PyOxidizerEnvironmentContext {
@@ -51,6 +406,58 @@ pub struct PyOxidizerEnvironmentContext {
     /// Optimization level when building binaries.
     pub build_opt_level: String,
 
+    /// How Python distributions are obtained.
+    pub distribution_options: DistributionOptions,
+
+    /// Whether `set_build_path()` must stay within the config file's directory.
+    pub confine_build_path: bool,
+
+    /// Whether targets are built directly beneath the build path.
+    ///
+    /// By default, target build paths are `<build_path>/<target_triple>/<profile>/<target>`
+    /// so builds for different triples and profiles don't collide. When set,
+    /// they are `<build_path>/<target>`.
+    pub flat_build_layout: bool,
+
+    /// Whether Starlark functions that access the filesystem are disabled.
+    pub sandbox: bool,
+
+    /// Paths `load()` is permitted to load. `None` permits all paths.
+    pub allowed_loads: Option<BTreeSet<PathBuf>>,
+
+    /// Maximum size in bytes of files `read_file()` will read.
+    pub read_file_max_size: u64,
+
+    /// Whether to validate the config without downloading or building anything.
+    ///
+    /// In dry run mode:
+    ///
+    /// * Python distributions are never downloaded. Distributions already
+    ///   available locally are still resolved.
+    /// * `PythonDistribution` methods that need an unavailable distribution
+    ///   return placeholders: `make_python_packaging_policy()` and
+    ///   `make_python_interpreter_config()` return default values,
+    ///   `python_resources()` returns an empty list and `to_python_executable()`
+    ///   returns an executable without resources that can't be built. A
+    ///   warning is recorded for each placeholder.
+    /// * Targets are resolved but not built. `EvaluationContext::build_resolved_target()`
+    ///   calls the target's function if it isn't resolved yet, so errors in it
+    ///   are reported, but does not call the resolved value's `build()`.
+    pub dry_run: bool,
+
+    /// Where to write build lifecycle events. `None` disables events.
+    pub event_sink: Option<Arc<EventSink>>,
+
+    /// Receives `print()` output in addition to it being logged.
+    ///
+    /// `None` disables capturing.
+    pub print_capture: Option<PrintCapture>,
+
+    /// Explicitly configured directory to store Python distributions in.
+    ///
+    /// If not set, the environment's default directory is used.
+    python_distributions_path: Option<PathBuf>,
+
     /// Cache of ready-to-clone Python distribution objects.
     ///
     /// This exists because constructing a new instance can take a
@@ -58,25 +465,75 @@ pub struct PyOxidizerEnvironmentContext {
     pub distribution_cache: Arc<DistributionCache>,
 
     /// Extra variables to inject into Starlark environment.
-    extra_vars: HashMap<String, Option<String>>,
+    pub extra_vars: HashMap<String, Option<String>>,
+
+    /// Environment variables read via `env()` and the values they resolved to.
+    env_lookups: BTreeMap<String, Option<String>>,
+
+    /// Warnings emitted during evaluation via `warn()`.
+    warnings: Vec<String>,
+
+    /// User-defined string state set via `set_build_state()`.
+    build_state: BTreeMap<String, String>,
+
+    /// Time after which evaluation is aborted.
+    ///
+    /// Checked cooperatively when including files, resolving Python
+    /// distributions, and building targets. `None` means no timeout.
+    pub evaluation_deadline: Option<(Instant, Duration)>,
+
+    /// Number of logical CPUs on the machine.
+    ///
+    /// Resolved once when the context is constructed.
+    pub cpu_count: usize,
+
+    /// Starlark environment that `include()` evaluates files into.
+    ///
+    /// This holds a reference to the environment holding this instance. So it
+    /// needs to be cleared when evaluation is finished to avoid a reference cycle.
+    pub include_env: Option<IncludeEnvironment>,
+
+    /// Stack of config files currently being evaluated via `include()`.
+    ///
+    /// Used to detect include cycles.
+    include_stack: Vec<PathBuf>,
 }
 
 impl PyOxidizerEnvironmentContext {
-    #[allow(clippy::too_many_arguments)]
+    /// Construct a new instance.
+    ///
+    /// Evaluation occurs from `working_dir` if set, otherwise from the directory
+    /// of `config_path`. Setting `working_dir` allows `config_path` to be a
+    /// virtual path that doesn't exist on the filesystem.
+    ///
+    /// `build_host_triple` defaults to the triple this binary was built for.
+    ///
+    /// Binaries are built in debug mode with optimization level `0` unless
+    /// `build_release` and `set_build_opt_level()` say otherwise.
     pub fn new(
         env: &crate::environment::Environment,
         verbose: bool,
         config_path: &Path,
-        build_host_triple: &str,
+        working_dir: Option<&Path>,
+        build_host_triple: Option<&str>,
         build_target_triple: &str,
-        build_release: bool,
-        build_opt_level: &str,
-        distribution_cache: Option<Arc<DistributionCache>>,
-        extra_vars: HashMap<String, Option<String>>,
+        distribution_options: DistributionOptions,
     ) -> Result<PyOxidizerEnvironmentContext> {
-        let parent = config_path
-            .parent()
-            .with_context(|| "resolving parent directory of config".to_string())?;
+        let build_host_triple = canonical_target_triple(resolve_host_triple(build_host_triple));
+        let build_target_triple = canonical_target_triple(build_target_triple);
+        validate_target_triple(&build_target_triple)?;
+        // Only some operations need a distribution for the host, so an
+        // unrecognized host isn't fatal.
+        if let Err(e) = validate_target_triple(&build_host_triple) {
+            warn!("build host triple may not work: {}", e);
+        }
+
+        let parent = match working_dir {
+            Some(path) => path,
+            None => config_path
+                .parent()
+                .with_context(|| "resolving parent directory of config".to_string())?,
+        };
 
         let parent = if parent.is_relative() {
             std::env::current_dir()?.join(parent)
@@ -84,23 +541,58 @@ impl PyOxidizerEnvironmentContext {
             parent.to_path_buf()
         };
 
-        let distribution_cache = distribution_cache.unwrap_or_else(|| {
-            Arc::new(DistributionCache::new(Some(
-                &env.python_distributions_dir(),
-            )))
-        });
+        let distribution_cache = match &distribution_options.cache {
+            Some(cache) => cache.clone(),
+            None => {
+                let mut cache = DistributionCache::new(Some(&env.python_distributions_dir()))
+                    .offline(distribution_options.offline)
+                    .ca_bundle(distribution_options.ca_bundle.as_deref())
+                    .verbose(verbose)
+                    .content_addressed(distribution_options.content_addressed)
+                    .cancellation(distribution_options.cancellation.clone());
+
+                if let Some(dir) = &distribution_options.local_distributions {
+                    cache = cache.with_local_source(dir).with_context(|| {
+                        format!("indexing local distributions in {}", dir.display())
+                    })?;
+                }
+
+                Arc::new(cache)
+            }
+        };
+
+        let include_stack = vec![parent.join(config_path.file_name().unwrap_or_default())];
 
         Ok(PyOxidizerEnvironmentContext {
             env: env.clone(),
             verbose,
             cwd: parent,
             config_path: config_path.to_path_buf(),
-            build_host_triple: build_host_triple.to_string(),
-            build_target_triple: build_target_triple.to_string(),
-            build_release,
-            build_opt_level: build_opt_level.to_string(),
+            build_host_triple,
+            build_target_triple,
+            build_release: false,
+            build_opt_level: "0".to_string(),
+            distribution_options,
+            confine_build_path: false,
+            flat_build_layout: false,
+            sandbox: false,
+            allowed_loads: None,
+            read_file_max_size: DEFAULT_READ_FILE_MAX_SIZE,
+            dry_run: false,
+            event_sink: None,
+            print_capture: None,
+            python_distributions_path: None,
             distribution_cache,
-            extra_vars,
+            extra_vars: HashMap::new(),
+            env_lookups: BTreeMap::new(),
+            warnings: vec![],
+            build_state: BTreeMap::new(),
+            evaluation_deadline: None,
+            cpu_count: std::thread::available_parallelism()
+                .map(|count| count.get())
+                .unwrap_or(1),
+            include_env: None,
+            include_stack,
         })
     }
 
@@ -117,8 +609,66 @@ impl PyOxidizerEnvironmentContext {
         Ok(context.build_path().to_path_buf())
     }
 
+    /// Obtain a summary of the settings this context resolved to.
+    pub fn summary(&self, type_values: &TypeValues) -> Result<BuildContextSummary, ValueError> {
+        Ok(BuildContextSummary {
+            build_host_triple: self.build_host_triple.clone(),
+            build_opt_level: self.build_opt_level.clone(),
+            build_path: self.build_path(type_values)?,
+            build_release: self.build_release,
+            build_target_triple: self.build_target_triple.clone(),
+            python_distributions_path: self.python_distributions_path()?,
+        })
+    }
+
+    /// Serialize the settings this context resolved to as JSON.
+    pub fn summary_json(&self, type_values: &TypeValues) -> Result<String> {
+        let summary = self
+            .summary(type_values)
+            .map_err(|e| anyhow!("unable to resolve build context: {:?}", e))?;
+
+        Ok(serde_json::to_string_pretty(&summary)?)
+    }
+
+    /// Error if the evaluation timeout has elapsed.
+    pub fn check_timeout(&self) -> Result<()> {
+        match self.evaluation_deadline {
+            Some((deadline, timeout)) if Instant::now() >= deadline => Err(anyhow!(
+                "evaluation of {} exceeded timeout of {}s",
+                self.config_path.display(),
+                timeout.as_secs_f64()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Resolve a path relative to the directory of the config file.
+    ///
+    /// Absolute paths are preserved. Either way, `.` and `..` components are
+    /// normalized.
+    pub fn resolve_path(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        Ok(resolve_relative_path(&self.cwd, path.as_ref())?)
+    }
+
+    /// Obtain the directory Python distributions are stored in.
+    ///
+    /// This is never derived from the build path, so changing the build path
+    /// doesn't cause distributions to be downloaded again.
     pub fn python_distributions_path(&self) -> Result<PathBuf, ValueError> {
-        Ok(self.env.python_distributions_dir())
+        Ok(match &self.python_distributions_path {
+            Some(path) => path.clone(),
+            None => self.env.python_distributions_dir(),
+        })
+    }
+
+    /// Set the directory to store Python distributions in.
+    ///
+    /// Relative paths are resolved against the directory of the config file.
+    /// This is independent of the build path.
+    pub fn set_python_distributions_path(&mut self, path: &Path) -> Result<()> {
+        self.python_distributions_path = Some(self.resolve_path(path)?);
+
+        Ok(())
     }
 
     pub fn get_output_path(
@@ -133,6 +683,115 @@ impl PyOxidizerEnvironmentContext {
 
         Ok(context.target_build_path(target))
     }
+
+    /// The path prefix to insert between the build path and per-target build paths.
+    ///
+    /// `None` if [Self::flat_build_layout] is set.
+    pub fn target_build_path_prefix(&self) -> Option<PathBuf> {
+        if self.flat_build_layout {
+            return None;
+        }
+
+        Some(
+            PathBuf::from(&self.build_target_triple).join(if self.build_release {
+                "release"
+            } else {
+                "debug"
+            }),
+        )
+    }
+
+    /// Resolve an environment variable on behalf of the Starlark environment.
+    ///
+    /// The lookup is recorded so the set of environment variables a config
+    /// depends on can be audited after evaluation.
+    pub fn resolve_env_var(&mut self, name: &str) -> Option<String> {
+        let value = std::env::var(name).ok();
+
+        if self.verbose {
+            let state = if value.is_some() { "set" } else { "not set" };
+            info!("env({}): environment variable is {}", name, state);
+        }
+
+        self.env_lookups.insert(name.to_string(), value.clone());
+
+        value
+    }
+
+    /// Environment variables that were looked up via `env()`.
+    pub fn env_lookups(&self) -> &BTreeMap<String, Option<String>> {
+        &self.env_lookups
+    }
+
+    /// Write a build lifecycle event, if events are enabled.
+    pub fn emit_event(&self, event: BuildEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink.emit(event);
+        }
+    }
+
+    /// Record a warning emitted during evaluation.
+    pub fn push_warning(&mut self, message: impl ToString) {
+        self.warnings.push(message.to_string());
+    }
+
+    /// Drain warnings recorded since the last call.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Drain `print()` output captured since the last call.
+    ///
+    /// Each element is the message of a `print()` call. Always empty unless
+    /// capturing is enabled via `print_capture`.
+    pub fn take_print_output(&self) -> Vec<String> {
+        self.print_capture
+            .as_ref()
+            .and_then(|capture| {
+                capture
+                    .lock()
+                    .ok()
+                    .map(|mut lines| std::mem::take(&mut *lines))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Set the optimization level, validating and normalizing it.
+    pub fn set_build_opt_level(&mut self, level: &str) -> Result<()> {
+        self.build_opt_level = normalize_opt_level(level)?;
+
+        Ok(())
+    }
+
+    /// The optimization level as an integer.
+    ///
+    /// Returns `None` for the size-oriented levels `s` and `z`.
+    pub fn build_opt_level_numeric(&self) -> Option<i64> {
+        self.build_opt_level.parse::<i64>().ok()
+    }
+
+    /// Set a user-defined build state value.
+    pub fn set_build_state(&mut self, key: impl ToString, value: impl ToString) {
+        self.build_state.insert(key.to_string(), value.to_string());
+    }
+
+    /// Resolve a build state value by key.
+    ///
+    /// `host_triple`, `target_triple`, and `opt_level` resolve to the
+    /// corresponding build settings. Other keys are looked up in user-defined
+    /// state set via `set_build_state()`.
+    pub fn get_build_state_string(&self, key: &str) -> Result<String> {
+        match key {
+            "host_triple" => Ok(self.build_host_triple.clone()),
+            "target_triple" => Ok(self.build_target_triple.clone()),
+            "opt_level" => Ok(self.build_opt_level.clone()),
+            _ => self
+                .build_state
+                .get(key)
+                .cloned()
+                .ok_or_else(|| anyhow!("invalid build state key: {}", key)),
+        }
+    }
 }
 
 impl TypedValue for PyOxidizerEnvironmentContext {
@@ -159,117 +818,1872 @@ impl TypedValue for PyOxidizerContext {
 
 /// Obtain the PyOxidizerContext for the Starlark execution environment.
 pub fn get_context(type_values: &TypeValues) -> ValueResult {
+    let placeholder = Value::new(PyOxidizerContext::default());
+
     type_values
-        .get_type_value(&Value::new(PyOxidizerContext::default()), "CONTEXT")
+        .get_type_value(&placeholder, "CONTEXT")
         .ok_or_else(|| {
-            ValueError::from(RuntimeError {
-                code: "PYOXIDIZER",
-                message: "Unable to resolve context (this should never happen)".to_string(),
-                label: "".to_string(),
-            })
+            StarlarkError::MissingContext {
+                available: type_values.list_type_value(&placeholder),
+            }
+            .into()
         })
 }
 
-/// Obtain a Starlark environment for evaluating PyOxidizer configurations.
-pub fn register_starlark_dialect(
-    env: &mut Environment,
-    type_values: &mut TypeValues,
-) -> Result<(), EnvironmentError> {
-    starlark_dialect_build_targets::register_starlark_dialect(env, type_values)?;
-    tugger::starlark::register_starlark_dialect(env, type_values)?;
-    super::file_resource::file_resource_env(env, type_values);
-    super::python_distribution::python_distribution_module(env, type_values);
-    super::python_embedded_resources::python_embedded_resources_module(env, type_values);
-    super::python_executable::python_executable_env(env, type_values);
-    super::python_packaging_policy::python_packaging_policy_module(env, type_values);
+/// env(name, default=None)
+fn starlark_env(type_values: &TypeValues, name: String, default: &Value) -> ValueResult {
+    let default = optional_str_arg("default", default)?;
 
-    Ok(())
-}
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let mut pyoxidizer_context = pyoxidizer_context_value
+        .downcast_mut::<PyOxidizerEnvironmentContext>()?
+        .ok_or(ValueError::IncorrectParameterType)?;
 
-pub fn populate_environment(
-    env: &mut Environment,
-    type_values: &mut TypeValues,
-    context: PyOxidizerEnvironmentContext,
-    resolve_targets: Option<Vec<String>>,
-    build_script_mode: bool,
-) -> Result<(), EnvironmentError> {
-    let mut build_targets_context = EnvironmentContext::new(context.cwd.clone());
+    Ok(pyoxidizer_context
+        .resolve_env_var(&name)
+        .or(default)
+        .to_value())
+}
 
-    if let Some(targets) = resolve_targets {
-        build_targets_context.set_resolve_targets(targets);
-    }
+/// warn(*args)
+fn starlark_warn(type_values: &TypeValues, args: &[Value]) -> ValueResult {
+    let message = args.iter().map(|arg| arg.to_string()).join(" ");
 
-    build_targets_context.build_script_mode = build_script_mode;
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let mut pyoxidizer_context = pyoxidizer_context_value
+        .downcast_mut::<PyOxidizerEnvironmentContext>()?
+        .ok_or(ValueError::IncorrectParameterType)?;
 
-    build_targets_context.set_target_build_path_prefix(Some(
-        PathBuf::from(&context.build_target_triple).join(if context.build_release {
-            "release"
-        } else {
-            "debug"
-        }),
-    ));
+    warn!("{}", message);
+    pyoxidizer_context.push_warning(message);
 
-    let tugger_context = TuggerContext::new();
+    Ok(Value::new(NoneType::None))
+}
 
-    starlark_dialect_build_targets::populate_environment(env, type_values, build_targets_context)?;
-    tugger::starlark::populate_environment(env, type_values, tugger_context)?;
+/// fail(message)
+fn starlark_fail(message: String) -> ValueResult {
+    error!("{}", message);
 
-    let mut vars = starlark::values::dict::Dictionary::default();
+    Err(ValueError::from(RuntimeError {
+        code: FAIL_ERROR_CODE,
+        message,
+        label: "fail()".to_string(),
+    }))
+}
 
-    for (k, v) in context.extra_vars.iter() {
-        vars.insert(
-            Value::from(k.as_str()),
-            match v {
-                Some(v) => Value::from(v.as_str()),
-                None => Value::from(NoneType::None),
-            },
-        )
-        .expect("error inserting variable; this should not happen");
-    }
+/// canonicalize_triple(triple)
+fn starlark_canonicalize_triple(triple: String) -> ValueResult {
+    Ok(Value::from(canonical_target_triple(&triple)))
+}
 
-    env.set("VARS", Value::try_from(vars.get_content().clone()).unwrap())?;
-    env.set("CWD", Value::from(context.cwd.display().to_string()))?;
-    env.set(
-        "CONFIG_PATH",
-        Value::from(context.config_path.display().to_string()),
-    )?;
-    env.set(
-        "BUILD_TARGET_TRIPLE",
-        Value::from(context.build_target_triple.clone()),
-    )?;
+/// supported_target_triples()
+fn starlark_supported_target_triples() -> ValueResult {
+    Ok(Value::from(
+        supported_target_triples()
+            .into_iter()
+            .map(Value::from)
+            .collect::<Vec<_>>(),
+    ))
+}
 
-    env.set("CONTEXT", Value::new(context))?;
+/// require_pyoxidizer_version(requirement) and require_version(requirement)
+fn starlark_require_pyoxidizer_version(requirement: String, label: &str) -> ValueResult {
+    let version = env!("CARGO_PKG_VERSION");
 
-    // We alias various globals as PyOxidizer.* attributes so they are
-    // available via the type object API. This is a bit hacky. But it allows
-    // Rust code with only access to the TypeValues dictionary to retrieve
-    // these globals.
-    for f in &["CONTEXT", "CWD", "CONFIG_PATH", "BUILD_TARGET_TRIPLE"] {
-        type_values.add_type_value(PyOxidizerContext::TYPE, f, env.get(f)?);
-    }
+    let message = match version_satisfies(version, &requirement) {
+        Ok(true) => return Ok(Value::new(NoneType::None)),
+        Ok(false) => format!(
+            "PyOxidizer {} does not satisfy version requirement {}",
+            version, requirement
+        ),
+        Err(e) => format!("{:#}", e),
+    };
 
-    Ok(())
+    Err(ValueError::from(RuntimeError {
+        code: "PYOXIDIZER_VERSION",
+        message,
+        label: label.to_string(),
+    }))
 }
 
-#[cfg(test)]
-pub mod tests {
-    use crate::{environment::default_target_triple, starlark::testutil::*};
+/// get_distribution_cache_stats()
+fn starlark_get_distribution_cache_stats(type_values: &TypeValues) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
 
-    #[test]
-    fn test_cwd() {
-        let cwd = starlark_ok("CWD");
-        let pwd = std::env::current_dir().unwrap();
-        assert_eq!(cwd.to_str(), pwd.display().to_string());
-    }
+    let stats = pyoxidizer_context.distribution_cache.stats();
 
-    #[test]
-    fn test_build_target() {
-        let target = starlark_ok("BUILD_TARGET_TRIPLE");
-        assert_eq!(target.to_str(), default_target_triple());
-    }
+    let mut res = Dictionary::new_typed();
+    res.insert(Value::from("hits"), Value::from(stats.hits as i64))?;
+    res.insert(Value::from("misses"), Value::from(stats.misses as i64))?;
 
-    #[test]
-    fn test_print() {
-        starlark_ok("print('hello, world')");
+    Ok(Value::new(res))
+}
+
+/// list_cached_distributions()
+fn starlark_list_cached_distributions(type_values: &TypeValues) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let keys = pyoxidizer_context
+        .distribution_cache
+        .cached_distribution_keys()
+        .map_err(|e| {
+            ValueError::from(RuntimeError {
+                code: "PYOXIDIZER_BUILD",
+                message: e.to_string(),
+                label: "list_cached_distributions()".to_string(),
+            })
+        })?;
+
+    Ok(Value::from(keys))
+}
+
+/// get_build_path()
+fn starlark_get_build_path(type_values: &TypeValues) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let build_path = pyoxidizer_context.build_path(type_values)?;
+
+    Ok(Value::from(build_path.display().to_string()))
+}
+
+/// dump_context_json()
+fn starlark_dump_context_json(type_values: &TypeValues) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let json = pyoxidizer_context.summary_json(type_values).map_err(|e| {
+        ValueError::from(RuntimeError {
+            code: "PYOXIDIZER_BUILD",
+            message: format!("{:#}", e),
+            label: "dump_context_json()".to_string(),
+        })
+    })?;
+
+    Ok(Value::from(json))
+}
+
+/// set_build_release(value)
+fn starlark_set_build_release(type_values: &TypeValues, value: &Value) -> ValueResult {
+    required_type_arg("value", "bool", value)?;
+    let value = value.to_bool();
+
+    // Limit lifetime of the borrow so we can obtain the build targets context below.
+    let prefix = {
+        let pyoxidizer_context_value = get_context(type_values)?;
+        let mut pyoxidizer_context = pyoxidizer_context_value
+            .downcast_mut::<PyOxidizerEnvironmentContext>()?
+            .ok_or(ValueError::IncorrectParameterType)?;
+
+        if pyoxidizer_context.build_release != value {
+            warn!(
+                "set_build_release() overriding release mode from {} to {}",
+                pyoxidizer_context.build_release, value
+            );
+        }
+
+        pyoxidizer_context.build_release = value;
+
+        pyoxidizer_context.target_build_path_prefix()
+    };
+
+    // Per-target build paths are segmented by release mode. So keep them in sync.
+    let build_targets_context_value = get_context_value(type_values)?;
+    let mut build_targets_context = build_targets_context_value
+        .downcast_mut::<EnvironmentContext>()?
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    build_targets_context.set_target_build_path_prefix(prefix);
+
+    Ok(Value::new(NoneType::None))
+}
+
+/// set_verbose(value)
+fn starlark_set_verbose(type_values: &TypeValues, value: &Value) -> ValueResult {
+    required_type_arg("value", "bool", value)?;
+    let value = value.to_bool();
+
+    {
+        let pyoxidizer_context_value = get_context(type_values)?;
+        let mut pyoxidizer_context = pyoxidizer_context_value
+            .downcast_mut::<PyOxidizerEnvironmentContext>()?
+            .ok_or(ValueError::IncorrectParameterType)?;
+
+        pyoxidizer_context.verbose = value;
+    }
+
+    // Target resolution logs according to its own copy of the setting.
+    let build_targets_context_value = get_context_value(type_values)?;
+    let mut build_targets_context = build_targets_context_value
+        .downcast_mut::<EnvironmentContext>()?
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    build_targets_context.verbose = value;
+
+    Ok(Value::new(NoneType::None))
+}
+
+/// include(path)
+fn starlark_include(type_values: &TypeValues, path: String) -> ValueResult {
+    let label = format!("include({})", path);
+
+    check_sandbox(type_values, &label)?;
+
+    // Limit lifetime of the context borrow, as the included file will
+    // likely need to access it.
+    let (path, mut env) = {
+        let pyoxidizer_context_value = get_context(type_values)?;
+        let mut pyoxidizer_context = pyoxidizer_context_value
+            .downcast_mut::<PyOxidizerEnvironmentContext>()?
+            .ok_or(ValueError::IncorrectParameterType)?;
+
+        pyoxidizer_context.check_timeout().map_err(|e| {
+            ValueError::from(RuntimeError {
+                code: TIMEOUT_ERROR_CODE,
+                message: e.to_string(),
+                label: label.clone(),
+            })
+        })?;
+
+        let path = pyoxidizer_context.resolve_path(&path).map_err(|e| {
+            ValueError::from(RuntimeError {
+                code: "PYOXIDIZER_INCLUDE",
+                message: e.to_string(),
+                label: label.clone(),
+            })
+        })?;
+
+        if pyoxidizer_context.include_stack.contains(&path) {
+            return Err(ValueError::from(RuntimeError {
+                code: "PYOXIDIZER_INCLUDE",
+                message: format!(
+                    "include cycle detected: {} is already being evaluated",
+                    path.display()
+                ),
+                label,
+            }));
+        }
+
+        let env = pyoxidizer_context
+            .include_env
+            .as_ref()
+            .ok_or_else(|| {
+                ValueError::from(RuntimeError {
+                    code: "PYOXIDIZER_INCLUDE",
+                    message: "include() is not available in this environment".to_string(),
+                    label: label.clone(),
+                })
+            })?
+            .0
+            .clone();
+
+        pyoxidizer_context.include_stack.push(path.clone());
+
+        (path, env)
+    };
+
+    let map = Arc::new(Mutex::new(CodeMap::new()));
+    let file_loader = ConfigFileLoader::new(&map, env.clone());
+
+    let res = starlark::eval::eval_file(
+        &map,
+        &path.display().to_string(),
+        Dialect::Bzl,
+        &mut env,
+        type_values,
+        file_loader,
+    );
+
+    {
+        let pyoxidizer_context_value = get_context(type_values)?;
+        let mut pyoxidizer_context = pyoxidizer_context_value
+            .downcast_mut::<PyOxidizerEnvironmentContext>()?
+            .ok_or(ValueError::IncorrectParameterType)?;
+
+        pyoxidizer_context.include_stack.pop();
+    }
+
+    res.map_err(|e| {
+        ValueError::from(RuntimeError {
+            code: "PYOXIDIZER_INCLUDE",
+            message: e.message,
+            label: format!("include({})", path.display()),
+        })
+    })?;
+
+    Ok(Value::new(NoneType::None))
+}
+
+/// read_file(path, encoding="utf-8")
+fn starlark_read_file(type_values: &TypeValues, path: String, encoding: String) -> ValueResult {
+    let label = format!("read_file({})", path);
+
+    check_sandbox(type_values, &label)?;
+
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let error = |message: String| {
+        ValueError::from(RuntimeError {
+            code: "PYOXIDIZER_READ_FILE",
+            message,
+            label: label.clone(),
+        })
+    };
+
+    let path = pyoxidizer_context
+        .resolve_path(&path)
+        .map_err(|e| error(e.to_string()))?;
+
+    let size = std::fs::metadata(&path)
+        .map_err(|e| error(format!("unable to stat {}: {}", path.display(), e)))?
+        .len();
+
+    if size > pyoxidizer_context.read_file_max_size {
+        return Err(error(format!(
+            "{} is {} bytes, which exceeds the maximum of {} bytes",
+            path.display(),
+            size,
+            pyoxidizer_context.read_file_max_size
+        )));
+    }
+
+    let data = std::fs::read(&path)
+        .map_err(|e| error(format!("unable to read {}: {}", path.display(), e)))?;
+
+    let text = match encoding.to_lowercase().as_str() {
+        "utf-8" | "utf8" => String::from_utf8(data)
+            .map_err(|e| error(format!("{} is not valid UTF-8: {}", path.display(), e)))?,
+        "latin-1" | "latin1" | "iso-8859-1" => data.into_iter().map(char::from).collect(),
+        _ => {
+            return Err(error(format!(
+                "unsupported encoding {}; must be utf-8 or latin-1",
+                encoding
+            )))
+        }
+    };
+
+    Ok(Value::from(text))
+}
+
+/// sha256_file(path)
+fn starlark_sha256_file(type_values: &TypeValues, path: String) -> ValueResult {
+    let label = format!("sha256_file({})", path);
+
+    check_sandbox(type_values, &label)?;
+
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let error = |message: String| {
+        ValueError::from(RuntimeError {
+            code: "PYOXIDIZER_SHA256_FILE",
+            message,
+            label: label.clone(),
+        })
+    };
+
+    let path = pyoxidizer_context
+        .resolve_path(&path)
+        .map_err(|e| error(e.to_string()))?;
+
+    let mut fh = std::fs::File::open(&path)
+        .map_err(|e| error(format!("unable to open {}: {}", path.display(), e)))?;
+
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut fh, &mut hasher)
+        .map_err(|e| error(format!("unable to read {}: {}", path.display(), e)))?;
+
+    Ok(Value::from(hex::encode(hasher.finalize())))
+}
+
+/// prefetch_distributions(triples)
+fn starlark_prefetch_distributions(type_values: &TypeValues, triples: &Value) -> ValueResult {
+    required_list_arg("triples", "string", triples)?;
+
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let dest_dir = pyoxidizer_context.python_distributions_path()?;
+
+    let mut errors = vec![];
+
+    for triple in triples.iter()?.iter().map(|x| x.to_string()) {
+        info!("prefetching Python distribution for {}", triple);
+
+        if let Err(e) =
+            default_distribution_location(&DistributionFlavor::Standalone, &triple, None).and_then(
+                |location| {
+                    pyoxidizer_context
+                        .distribution_cache
+                        .resolve_distribution(&location, Some(&dest_dir))
+                },
+            )
+        {
+            errors.push(format!("{}: {:?}", triple, e));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Value::new(NoneType::None))
+    } else {
+        Err(ValueError::from(RuntimeError {
+            code: "PYOXIDIZER_BUILD",
+            message: format!(
+                "error prefetching Python distributions:\n{}",
+                errors.join("\n")
+            ),
+            label: "prefetch_distributions()".to_string(),
+        }))
+    }
+}
+
+/// get_resolve_targets()
+fn starlark_get_resolve_targets(type_values: &TypeValues) -> ValueResult {
+    let build_targets_context_value = get_context_value(type_values)?;
+    let build_targets_context = build_targets_context_value
+        .downcast_ref::<EnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    Ok(build_targets_context.resolve_targets().cloned().to_value())
+}
+
+/// cpu_count()
+fn starlark_cpu_count(type_values: &TypeValues) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    Ok(Value::from(pyoxidizer_context.cpu_count as i64))
+}
+
+/// current_target()
+fn starlark_current_target(type_values: &TypeValues) -> ValueResult {
+    let build_targets_context_value = get_context_value(type_values)?;
+    let build_targets_context = build_targets_context_value
+        .downcast_ref::<EnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    Ok(build_targets_context
+        .current_target()
+        .map(|target| target.to_string())
+        .to_value())
+}
+
+/// set_python_distributions_path(path)
+fn starlark_set_python_distributions_path(type_values: &TypeValues, path: String) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let mut pyoxidizer_context = pyoxidizer_context_value
+        .downcast_mut::<PyOxidizerEnvironmentContext>()?
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    pyoxidizer_context
+        .set_python_distributions_path(&PathBuf::from(&path))
+        .map_err(StarlarkError::DistributionsPath)?;
+
+    Ok(Value::new(NoneType::None))
+}
+
+/// build_opt_level_numeric()
+fn starlark_build_opt_level_numeric(type_values: &TypeValues) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    // Size-oriented levels ("s" and "z") have no numeric equivalent.
+    Ok(Value::from(
+        pyoxidizer_context.build_opt_level_numeric().unwrap_or(-1),
+    ))
+}
+
+/// build_optimizes_for_size()
+fn starlark_build_optimizes_for_size(type_values: &TypeValues) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    Ok(Value::from(matches!(
+        pyoxidizer_context.build_opt_level.as_str(),
+        "s" | "z"
+    )))
+}
+
+/// is_target_windows(), is_target_macos(), is_target_linux()
+fn starlark_is_target_os_family(type_values: &TypeValues, family: TargetOsFamily) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    Ok(Value::from(
+        TargetOsFamily::from_triple(&pyoxidizer_context.build_target_triple) == family,
+    ))
+}
+
+/// is_target_musl()
+fn starlark_is_target_musl(type_values: &TypeValues) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    Ok(Value::from(target_triple_is_musl(
+        &pyoxidizer_context.build_target_triple,
+    )))
+}
+
+/// set_build_state(key, value)
+fn starlark_set_build_state(type_values: &TypeValues, key: String, value: String) -> ValueResult {
+    if matches!(key.as_str(), "host_triple" | "target_triple" | "opt_level") {
+        return Err(ValueError::from(RuntimeError {
+            code: "PYOXIDIZER_BUILD",
+            message: format!("build state key {} is reserved", key),
+            label: "set_build_state()".to_string(),
+        }));
+    }
+
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let mut pyoxidizer_context = pyoxidizer_context_value
+        .downcast_mut::<PyOxidizerEnvironmentContext>()?
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    pyoxidizer_context.set_build_state(key, value);
+
+    Ok(Value::new(NoneType::None))
+}
+
+/// get_build_state(key)
+fn starlark_get_build_state(type_values: &TypeValues, key: String) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let value = pyoxidizer_context
+        .get_build_state_string(&key)
+        .map_err(|e| {
+            ValueError::from(RuntimeError {
+                code: "PYOXIDIZER_BUILD",
+                message: e.to_string(),
+                label: "get_build_state()".to_string(),
+            })
+        })?;
+
+    Ok(Value::from(value))
+}
+
+/// set_build_opt_level(level)
+fn starlark_set_build_opt_level(type_values: &TypeValues, level: String) -> ValueResult {
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let mut pyoxidizer_context = pyoxidizer_context_value
+        .downcast_mut::<PyOxidizerEnvironmentContext>()?
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    pyoxidizer_context
+        .set_build_opt_level(&level)
+        .map_err(|e| {
+            ValueError::from(RuntimeError {
+                code: "PYOXIDIZER_BUILD",
+                message: e.to_string(),
+                label: "set_build_opt_level()".to_string(),
+            })
+        })?;
+
+    Ok(Value::new(NoneType::None))
+}
+
+starlark_module! { global_module =>
+    build_opt_level_numeric(env env) {
+        starlark_build_opt_level_numeric(env)
+    }
+
+    build_optimizes_for_size(env env) {
+        starlark_build_optimizes_for_size(env)
+    }
+
+    canonicalize_triple(triple: String) {
+        starlark_canonicalize_triple(triple)
+    }
+
+    cpu_count(env env) {
+        starlark_cpu_count(env)
+    }
+
+    current_target(env env) {
+        starlark_current_target(env)
+    }
+
+    dump_context_json(env env) {
+        starlark_dump_context_json(env)
+    }
+
+    env(env env, name: String, default=NoneType::None) {
+        starlark_env(env, name, &default)
+    }
+
+    fail(message: String) {
+        starlark_fail(message)
+    }
+
+    get_build_path(env env) {
+        starlark_get_build_path(env)
+    }
+
+    get_build_state(env env, key: String) {
+        starlark_get_build_state(env, key)
+    }
+
+    get_distribution_cache_stats(env env) {
+        starlark_get_distribution_cache_stats(env)
+    }
+
+    get_env(env env, name: String, default=NoneType::None) {
+        starlark_env(env, name, &default)
+    }
+
+    include(env env, path: String) {
+        starlark_include(env, path)
+    }
+
+    is_target_linux(env env) {
+        starlark_is_target_os_family(env, TargetOsFamily::Linux)
+    }
+
+    is_target_macos(env env) {
+        starlark_is_target_os_family(env, TargetOsFamily::MacOs)
+    }
+
+    is_target_musl(env env) {
+        starlark_is_target_musl(env)
+    }
+
+    is_target_windows(env env) {
+        starlark_is_target_os_family(env, TargetOsFamily::Windows)
+    }
+
+    list_cached_distributions(env env) {
+        starlark_list_cached_distributions(env)
+    }
+
+    prefetch_distributions(env env, triples) {
+        starlark_prefetch_distributions(env, &triples)
+    }
+
+    read_file(env env, path: String, encoding: String = "utf-8".to_string()) {
+        starlark_read_file(env, path, encoding)
+    }
+
+    sha256_file(env env, path: String) {
+        starlark_sha256_file(env, path)
+    }
+
+    require_pyoxidizer_version(requirement: String) {
+        starlark_require_pyoxidizer_version(requirement, "require_pyoxidizer_version()")
+    }
+
+    require_version(requirement: String) {
+        starlark_require_pyoxidizer_version(requirement, "require_version()")
+    }
+
+    get_resolve_targets(env env) {
+        starlark_get_resolve_targets(env)
+    }
+
+    set_build_opt_level(env env, level: String) {
+        starlark_set_build_opt_level(env, level)
+    }
+
+    set_build_state(env env, key: String, value: String) {
+        starlark_set_build_state(env, key, value)
+    }
+
+    set_build_release(env env, value) {
+        starlark_set_build_release(env, &value)
+    }
+
+    set_python_distributions_path(env env, path: String) {
+        starlark_set_python_distributions_path(env, path)
+    }
+
+    set_verbose(env env, value) {
+        starlark_set_verbose(env, &value)
+    }
+
+    supported_target_triples() {
+        starlark_supported_target_triples()
+    }
+
+    warn(env env, *args) {
+        starlark_warn(env, &args)
+    }
+}
+
+/// Errors constructing a Starlark environment for evaluating PyOxidizer configurations.
+///
+/// Variants identify the dialect whose registration or population failed.
+#[derive(Debug)]
+pub enum EnvironmentSetupError {
+    /// The build targets dialect could not be registered or populated.
+    BuildTargets(EnvironmentError),
+    /// The tugger dialect could not be registered or populated.
+    Tugger(EnvironmentError),
+    /// PyOxidizer's own symbols could not be registered or populated.
+    PyOxidizer(EnvironmentError),
+}
+
+impl EnvironmentSetupError {
+    /// The underlying Starlark error.
+    pub fn environment_error(&self) -> &EnvironmentError {
+        match self {
+            Self::BuildTargets(e) | Self::Tugger(e) | Self::PyOxidizer(e) => e,
+        }
+    }
+}
+
+impl std::fmt::Display for EnvironmentSetupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dialect = match self {
+            Self::BuildTargets(_) => "build targets",
+            Self::Tugger(_) => "tugger",
+            Self::PyOxidizer(_) => "PyOxidizer",
+        };
+
+        write!(
+            f,
+            "error setting up {} Starlark dialect: {:?}",
+            dialect,
+            self.environment_error()
+        )
+    }
+}
+
+impl std::error::Error for EnvironmentSetupError {}
+
+impl From<EnvironmentError> for EnvironmentSetupError {
+    fn from(e: EnvironmentError) -> Self {
+        Self::PyOxidizer(e)
+    }
+}
+
+/// Obtain a Starlark environment for evaluating PyOxidizer configurations.
+pub fn register_starlark_dialect(
+    env: &mut Environment,
+    type_values: &mut TypeValues,
+) -> Result<(), EnvironmentSetupError> {
+    starlark_dialect_build_targets::register_starlark_dialect(env, type_values)
+        .map_err(EnvironmentSetupError::BuildTargets)?;
+    global_module(env, type_values);
+    tugger::starlark::register_starlark_dialect(env, type_values)
+        .map_err(EnvironmentSetupError::Tugger)?;
+    super::file_resource::file_resource_env(env, type_values);
+    super::path::path_module(env, type_values);
+    super::python_distribution::python_distribution_module(env, type_values);
+    super::python_embedded_resources::python_embedded_resources_module(env, type_values);
+    super::python_executable::python_executable_env(env, type_values);
+    super::python_packaging_policy::python_packaging_policy_module(env, type_values);
+
+    Ok(())
+}
+
+/// The parts of a Starlark environment that are the same for every evaluation.
+///
+/// Registering the Starlark dialect creates hundreds of values. Constructing
+/// an instance once and calling [Self::instantiate] for each evaluation avoids
+/// repeating that work.
+#[derive(Clone)]
+pub struct EnvironmentTemplate {
+    /// Frozen environment holding the registered dialect.
+    env: Environment,
+    type_values: TypeValues,
+}
+
+impl EnvironmentTemplate {
+    pub fn new() -> Result<Self, EnvironmentSetupError> {
+        let (env, type_values) = starlark::stdlib::global_environment();
+
+        Self::from_environment(env, type_values)
+    }
+
+    /// Construct an instance layering the dialect on top of an existing environment.
+    ///
+    /// This allows embedders to register their own globals before PyOxidizer's.
+    /// The environment must not be frozen. Globals with the same name as
+    /// PyOxidizer's are replaced by them.
+    pub fn from_environment(
+        mut env: Environment,
+        mut type_values: TypeValues,
+    ) -> Result<Self, EnvironmentSetupError> {
+        register_starlark_dialect(&mut env, &mut type_values)?;
+        env.freeze();
+
+        Ok(Self { env, type_values })
+    }
+
+    /// Obtain the environments and type values for a new evaluation.
+    ///
+    /// Returns the frozen parent environment, a child environment to populate
+    /// via [populate_environment], and type values independent of any other
+    /// instantiation.
+    pub fn instantiate(&self) -> (Environment, Environment, TypeValues) {
+        (
+            self.env.clone(),
+            self.env.child("pyoxidizer"),
+            self.type_values.clone(),
+        )
+    }
+}
+
+pub fn populate_environment(
+    env: &mut Environment,
+    type_values: &mut TypeValues,
+    context: PyOxidizerEnvironmentContext,
+    resolve_targets: Option<Vec<String>>,
+    build_script_mode: bool,
+) -> Result<(), EnvironmentSetupError> {
+    let mut build_targets_context = EnvironmentContext::new(context.cwd.clone());
+
+    if let Some(targets) = resolve_targets {
+        build_targets_context.set_resolve_targets(targets);
+    }
+
+    build_targets_context.build_script_mode = build_script_mode;
+    build_targets_context.verbose = context.verbose;
+    build_targets_context.confine_build_path = context.confine_build_path;
+    build_targets_context.sandbox = context.sandbox;
+    build_targets_context.print_capture = context.print_capture.clone();
+
+    build_targets_context.set_target_build_path_prefix(context.target_build_path_prefix());
+
+    let tugger_context = TuggerContext::new();
+
+    starlark_dialect_build_targets::populate_environment(env, type_values, build_targets_context)
+        .map_err(EnvironmentSetupError::BuildTargets)?;
+    tugger::starlark::populate_environment(env, type_values, tugger_context)
+        .map_err(EnvironmentSetupError::Tugger)?;
+
+    let mut vars = starlark::values::dict::Dictionary::default();
+
+    for (k, v) in context.extra_vars.iter() {
+        vars.insert(
+            Value::from(k.as_str()),
+            match v {
+                Some(v) => Value::from(v.as_str()),
+                None => Value::from(NoneType::None),
+            },
+        )
+        .expect("error inserting variable; this should not happen");
+    }
+
+    env.set("VARS", Value::try_from(vars.get_content().clone()).unwrap())?;
+    env.set("CWD", Value::from(context.cwd.display().to_string()))?;
+    env.set(
+        "CONFIG_PATH",
+        Value::from(context.config_path.display().to_string()),
+    )?;
+    env.set(
+        "CONFIG_DIR",
+        Value::from(
+            context
+                .config_path
+                .parent()
+                .unwrap_or(&context.cwd)
+                .display()
+                .to_string(),
+        ),
+    )?;
+    env.set(
+        "BUILD_HOST_TRIPLE",
+        Value::from(context.build_host_triple.clone()),
+    )?;
+    env.set(
+        "BUILD_TARGET_TRIPLE",
+        Value::from(context.build_target_triple.clone()),
+    )?;
+    env.set("BUILD_RELEASE", Value::from(context.build_release))?;
+    env.set(
+        "BUILD_OPT_LEVEL",
+        Value::from(context.build_opt_level.clone()),
+    )?;
+    env.set("BUILD_SCRIPT_MODE", Value::from(build_script_mode))?;
+    env.set("PYOXIDIZER_VERSION", Value::from(env!("CARGO_PKG_VERSION")))?;
+
+    // Exposes PyOxidizer.* type values to configuration files.
+    env.set(
+        PyOxidizerContext::TYPE,
+        Value::new(PyOxidizerContext::default()),
+    )?;
+
+    env.set("CONTEXT", Value::new(context))?;
+
+    // We alias various globals as PyOxidizer.* attributes so they are
+    // available via the type object API. This is a bit hacky. But it allows
+    // Rust code with only access to the TypeValues dictionary to retrieve
+    // these globals.
+    for f in &[
+        "CONTEXT",
+        "CWD",
+        "CONFIG_PATH",
+        "CONFIG_DIR",
+        "BUILD_HOST_TRIPLE",
+        "BUILD_TARGET_TRIPLE",
+        "BUILD_RELEASE",
+        "BUILD_OPT_LEVEL",
+        "BUILD_SCRIPT_MODE",
+        "PYOXIDIZER_VERSION",
+        "cpu_count",
+        "current_target",
+        "get_build_path",
+    ] {
+        type_values.add_type_value(PyOxidizerContext::TYPE, f, env.get(f)?);
+    }
+
+    // PyOxidizer.version allows configs to branch on the running version.
+    type_values.add_type_value(
+        PyOxidizerContext::TYPE,
+        "version",
+        env.get("PYOXIDIZER_VERSION")?,
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use {
+        super::*,
+        crate::{
+            environment::default_target_triple,
+            starlark::{eval::EvaluationContext, testutil::*},
+        },
+    };
+
+    #[test]
+    fn test_cwd() {
+        let cwd = starlark_ok("CWD");
+        let pwd = std::env::current_dir().unwrap();
+        assert_eq!(cwd.to_str(), pwd.display().to_string());
+    }
+
+    #[test]
+    fn test_config_dir() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("set_build_path('build-dir')")?;
+
+        let config_dir = eval.eval("CONFIG_DIR")?;
+        let config_path = eval.eval("CONFIG_PATH")?;
+        assert_eq!(
+            PathBuf::from(config_dir.to_str()),
+            Path::new(&config_path.to_str()).parent().unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_target() {
+        let target = starlark_ok("BUILD_TARGET_TRIPLE");
+        assert_eq!(target.to_str(), default_target_triple());
+    }
+
+    #[test]
+    fn test_validate_target_triple() -> Result<()> {
+        validate_target_triple(default_target_triple())?;
+
+        let err = validate_target_triple("x86_64-unknwon-linux-gnu").unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("x86_64-unknwon-linux-gnu"));
+        assert!(message.contains("x86_64-unknown-linux-gnu"));
+
+        let err = test_evaluation_context_builder()?
+            .build_target_triple("x86_64-unknwon-linux-gnu")
+            .into_context()
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        for triple in supported_target_triples() {
+            assert!(message.contains(&triple), "{}", triple);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_target_triple() -> Result<()> {
+        for (alias, canonical) in [
+            ("x86_64-pc-windows-msvc", "x86_64-pc-windows-msvc"),
+            ("x86_64-windows-msvc", "x86_64-pc-windows-msvc"),
+            ("x64-windows-msvc", "x86_64-pc-windows-msvc"),
+            ("x86-windows-msvc", "i686-pc-windows-msvc"),
+            ("amd64-linux-gnu", "x86_64-unknown-linux-gnu"),
+            ("x86_64_v3-linux-musl", "x86_64_v3-unknown-linux-musl"),
+            ("arm64-apple-darwin", "aarch64-apple-darwin"),
+            ("x86_64-macos", "x86_64-apple-darwin"),
+            (" X86_64-Unknown-Linux-GNU ", "x86_64-unknown-linux-gnu"),
+            ("wasm32-wasi", "wasm32-wasi"),
+        ] {
+            assert_eq!(canonical_target_triple(alias), canonical, "{}", alias);
+        }
+
+        let value = starlark_ok("canonicalize_triple('x86_64-windows-msvc')");
+        assert_eq!(value.to_str(), "x86_64-pc-windows-msvc");
+
+        let mut eval = test_evaluation_context_builder()?
+            .build_target_triple("amd64-linux-gnu")
+            .into_context()?;
+        assert_eq!(
+            eval.eval("BUILD_TARGET_TRIPLE")?.to_str(),
+            "x86_64-unknown-linux-gnu"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_host_triple() -> Result<()> {
+        let host = starlark_ok("BUILD_HOST_TRIPLE");
+        assert_eq!(host.to_str(), default_target_triple());
+
+        assert_eq!(resolve_host_triple(None), default_target_triple());
+        assert_eq!(
+            resolve_host_triple(Some("aarch64-apple-darwin")),
+            "aarch64-apple-darwin"
+        );
+
+        let eval = test_evaluation_context_builder()?
+            .build_host_triple(Some("aarch64-apple-darwin"))
+            .into_context()?;
+        assert_eq!(
+            eval.get_var("BUILD_HOST_TRIPLE").unwrap().to_str(),
+            "aarch64-apple-darwin"
+        );
+
+        // Unsupported hosts only produce a warning.
+        let eval = test_evaluation_context_builder()?
+            .build_host_triple(Some("riscv64gc-unknown-linux-gnu"))
+            .into_context()?;
+        assert_eq!(
+            eval.get_var("BUILD_HOST_TRIPLE").unwrap().to_str(),
+            "riscv64gc-unknown-linux-gnu"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_release() {
+        let release = starlark_ok("BUILD_RELEASE");
+        assert_eq!(release.get_type(), "bool");
+        assert!(!release.to_bool());
+    }
+
+    #[test]
+    fn test_supported_target_triples() {
+        let triples = supported_target_triples();
+        assert!(!triples.is_empty());
+        assert!(triples.windows(2).all(|w| w[0] < w[1]));
+        assert!(validate_target_triple(&triples[0]).is_ok());
+
+        let value = starlark_ok("supported_target_triples()");
+        assert_eq!(value.get_type(), "list");
+        assert_eq!(
+            value
+                .iter()
+                .unwrap()
+                .iter()
+                .map(|v| v.to_str())
+                .collect::<Vec<_>>(),
+            triples
+        );
+    }
+
+    #[test]
+    fn test_pyoxidizer_version() {
+        let value = starlark_ok("PYOXIDIZER_VERSION");
+        assert_eq!(value.get_type(), "string");
+        assert_eq!(value.to_str(), env!("CARGO_PKG_VERSION"));
+
+        let value = starlark_ok("PyOxidizer.version");
+        assert_eq!(value.to_str(), env!("CARGO_PKG_VERSION"));
+
+        let value = starlark_ok("'new' if PyOxidizer.version >= '0.1.0' else 'old'");
+        assert_eq!(value.to_str(), "new");
+    }
+
+    #[test]
+    fn test_build_script_mode() -> Result<()> {
+        let value = starlark_ok("BUILD_SCRIPT_MODE");
+        assert_eq!(value.get_type(), "bool");
+        assert!(!value.to_bool());
+
+        let mut eval = test_evaluation_context_builder()?
+            .build_script_mode(true)
+            .into_context()?;
+        assert!(eval.eval("BUILD_SCRIPT_MODE")?.to_bool());
+
+        let mut eval = test_evaluation_context_builder()?
+            .build_script_mode(false)
+            .into_context()?;
+        assert!(!eval.eval("BUILD_SCRIPT_MODE")?.to_bool());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_opt_level() {
+        let opt_level = starlark_ok("BUILD_OPT_LEVEL");
+        assert_eq!(opt_level.to_str(), "0");
+    }
+
+    #[test]
+    fn test_get_build_path() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+
+        let value = eval.eval("get_build_path()")?;
+        assert_eq!(
+            value.to_str(),
+            eval.build_path().unwrap().display().to_string()
+        );
+
+        eval.eval("set_build_path('custom')")?;
+        let value = eval.eval("get_build_path()")?;
+        assert_eq!(
+            value.to_str(),
+            std::env::current_dir()?
+                .join("custom")
+                .display()
+                .to_string()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefetch_distributions() {
+        let value = starlark_ok("prefetch_distributions([BUILD_TARGET_TRIPLE])");
+        assert_eq!(value.get_type(), "NoneType");
+
+        let err = starlark_nok("prefetch_distributions(['bogus-triple', 'other-bogus-triple'])");
+        assert!(err.message.contains("bogus-triple"));
+        assert!(err.message.contains("other-bogus-triple"));
+    }
+
+    #[test]
+    fn test_get_resolve_targets() -> Result<()> {
+        let value = starlark_ok("get_resolve_targets()");
+        assert_eq!(value.get_type(), "NoneType");
+
+        let mut eval = test_evaluation_context_builder()?
+            .resolve_targets(vec!["b".to_string(), "a".to_string()])
+            .into_context()?;
+
+        let value = eval.eval("get_resolve_targets()")?;
+        assert_eq!(value.get_type(), "list");
+        assert_eq!(
+            value
+                .iter()
+                .unwrap()
+                .iter()
+                .map(|x| x.to_str())
+                .collect::<Vec<_>>(),
+            vec!["b".to_string(), "a".to_string()]
+        );
+
+        Ok(())
+    }
+
+    fn python_distributions_path(eval: &EvaluationContext) -> PathBuf {
+        let context_value = eval.pyoxidizer_context_value().unwrap();
+        let context = context_value
+            .downcast_ref::<PyOxidizerEnvironmentContext>()
+            .unwrap();
+
+        context.python_distributions_path().unwrap()
+    }
+
+    #[test]
+    fn test_version_satisfies() -> Result<()> {
+        assert!(version_satisfies("0.23.0", ">=0.22")?);
+        assert!(version_satisfies("0.23.0", "=0.23.0")?);
+        assert!(version_satisfies("0.23.0", ">0.22, <0.24")?);
+        assert!(!version_satisfies("0.23.0", "<0.23")?);
+        assert!(!version_satisfies("0.23.0", "=0.22.1")?);
+        assert!(version_satisfies("0.23.0-pre", ">=0.23")?);
+        assert!(version_satisfies("0.23.0", "not a requirement").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_require_pyoxidizer_version() -> Result<()> {
+        starlark_ok("require_pyoxidizer_version('>=0.1')");
+
+        let err = starlark_nok("require_pyoxidizer_version('>=1000')");
+        assert!(err
+            .message
+            .contains("does not satisfy version requirement >=1000"));
+
+        let err = starlark_nok("require_pyoxidizer_version('bogus')");
+        assert!(err.message.contains("parsing version requirement bogus"));
+
+        // require_version() is an alias.
+        starlark_ok("require_version('>=0.1')");
+
+        let err = starlark_nok("require_version('>=1000')");
+        assert!(err.message.contains(&format!(
+            "PyOxidizer {} does not satisfy version requirement >=1000",
+            env!("CARGO_PKG_VERSION")
+        )));
+
+        let err = starlark_nok("require_version('>=')");
+        assert!(err.message.contains("parsing version requirement >="));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cpu_count() -> Result<()> {
+        let count = starlark_ok("cpu_count()");
+        assert_eq!(count.get_type(), "int");
+        assert!(count.to_int().unwrap() >= 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_current_target() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        assert_eq!(eval.eval("current_target()")?.get_type(), "NoneType");
+
+        eval.eval("def get_name(): return current_target()")?;
+        eval.eval("def get_other(): return [resolve_target('a'), current_target()]")?;
+        eval.eval("register_target('a', get_name)")?;
+        eval.eval("register_target('b', get_other)")?;
+
+        assert_eq!(eval.eval("resolve_target('a')")?.to_str(), "a");
+        assert_eq!(eval.eval("resolve_target('b')")?.to_str(), "[\"a\", \"b\"]");
+        assert_eq!(eval.eval("current_target()")?.get_type(), "NoneType");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fail() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("def check(x):\n    if x > 1:\n        fail('x must be at most 1')")?;
+        eval.eval("check(1)")?;
+
+        let err = starlark_nok("fail('unsupported target')");
+        assert_eq!(err.code.as_deref(), Some(FAIL_ERROR_CODE));
+        assert!(err.message.contains("unsupported target"));
+
+        let err = eval.eval("check(2)").unwrap_err();
+        assert!(err.to_string().contains("x must be at most 1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_json() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("set_build_path('summary-build')")?;
+
+        let json = eval.context_summary_json()?;
+        let value: serde_json::Value = serde_json::from_str(&json)?;
+
+        assert_eq!(
+            value["build_path"].as_str().unwrap(),
+            std::env::current_dir()?
+                .join("summary-build")
+                .display()
+                .to_string()
+        );
+        assert_eq!(
+            value["build_target_triple"].as_str().unwrap(),
+            default_target_triple()
+        );
+        assert_eq!(value["build_release"].as_bool(), Some(false));
+
+        let keys = value.as_object().unwrap().keys().collect::<Vec<_>>();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+
+        let starlark_json = eval.eval("dump_context_json()")?.to_str();
+        assert_eq!(starlark_json, json);
+        let value: serde_json::Value = serde_json::from_str(&starlark_json)?;
+        assert_eq!(value["build_opt_level"].as_str().unwrap(), "0");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_python_distributions_path() -> Result<()> {
+        let cwd = std::env::current_dir()?;
+
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("set_python_distributions_path('dists')")?;
+        eval.eval("set_build_path('build-dir')")?;
+        assert_eq!(python_distributions_path(&eval), cwd.join("dists"));
+        assert_eq!(eval.build_path().unwrap(), cwd.join("build-dir"));
+
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("set_build_path('build-dir')")?;
+        eval.eval("set_python_distributions_path('other/../dists')")?;
+        assert_eq!(python_distributions_path(&eval), cwd.join("dists"));
+        assert_eq!(eval.build_path().unwrap(), cwd.join("build-dir"));
+
+        // The default location doesn't follow the build path either.
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        let default_path = python_distributions_path(&eval);
+        eval.eval("set_build_path('build-dir')")?;
+        assert_eq!(python_distributions_path(&eval), default_path);
+        assert!(!default_path.starts_with(cwd.join("build-dir")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_opt_level() -> Result<()> {
+        assert_eq!(normalize_opt_level("2")?, "2");
+        assert_eq!(normalize_opt_level(" Z ")?, "z");
+
+        let err = normalize_opt_level("O2").unwrap_err();
+        assert!(err.to_string().contains("invalid optimization level O2"));
+        assert!(err.to_string().contains("0, 1, 2, 3, s, z"));
+
+        let env = crate::testutil::get_env()?;
+        let config_path = std::env::current_dir()?.join("pyoxidizer.bzl");
+
+        let mut context = PyOxidizerEnvironmentContext::new(
+            &env,
+            false,
+            &config_path,
+            None,
+            None,
+            default_target_triple(),
+            DistributionOptions::default(),
+        )?;
+        assert_eq!(context.build_opt_level, "0");
+
+        context.set_build_opt_level("S")?;
+        assert_eq!(context.build_opt_level, "s");
+
+        let err = context.set_build_opt_level("O2").unwrap_err();
+        assert!(err.to_string().contains("O2"));
+        assert_eq!(context.build_opt_level, "s");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_build_opt_level() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("set_build_opt_level('s')")?;
+
+        {
+            let context_value = eval.pyoxidizer_context_value().unwrap();
+            let context = context_value
+                .downcast_ref::<PyOxidizerEnvironmentContext>()
+                .unwrap();
+            assert_eq!(context.build_opt_level, "s");
+        }
+
+        let err = starlark_nok("set_build_opt_level('4')");
+        assert!(err.message.contains("invalid optimization level 4"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_opt_level_numeric() -> Result<()> {
+        for (level, numeric, size) in [
+            ("0", 0, false),
+            ("1", 1, false),
+            ("2", 2, false),
+            ("3", 3, false),
+            ("s", -1, true),
+            ("z", -1, true),
+        ] {
+            let mut eval = test_evaluation_context_builder()?.into_context()?;
+            eval.eval(&format!("set_build_opt_level('{}')", level))?;
+
+            assert_eq!(
+                eval.eval("build_opt_level_numeric()")?.to_int().unwrap(),
+                numeric
+            );
+            assert_eq!(eval.eval("build_optimizes_for_size()")?.to_bool(), size);
+
+            let context_value = eval.pyoxidizer_context_value().unwrap();
+            let context = context_value
+                .downcast_ref::<PyOxidizerEnvironmentContext>()
+                .unwrap();
+            assert_eq!(
+                context.build_opt_level_numeric(),
+                if size { None } else { Some(numeric) }
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_identifier() {
+        for name in ["APP_VERSION", "_private", "x1"] {
+            assert!(validate_identifier(name).is_ok(), "{}", name);
+        }
+
+        for name in ["", "1x", "GIT-SHA", "with space", "def", "None"] {
+            assert!(validate_identifier(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_target_os_family() {
+        for (triple, family, musl) in [
+            ("x86_64-unknown-linux-gnu", TargetOsFamily::Linux, false),
+            ("x86_64-unknown-linux-musl", TargetOsFamily::Linux, true),
+            ("aarch64-unknown-linux-gnu", TargetOsFamily::Linux, false),
+            (
+                "armv7-unknown-linux-musleabihf",
+                TargetOsFamily::Linux,
+                true,
+            ),
+            ("x86_64-apple-darwin", TargetOsFamily::MacOs, false),
+            ("aarch64-apple-darwin", TargetOsFamily::MacOs, false),
+            ("x86_64-pc-windows-msvc", TargetOsFamily::Windows, false),
+            ("i686-pc-windows-msvc", TargetOsFamily::Windows, false),
+            ("x86_64-pc-windows-gnu", TargetOsFamily::Windows, false),
+            ("wasm32-unknown-unknown", TargetOsFamily::Other, false),
+        ] {
+            assert_eq!(TargetOsFamily::from_triple(triple), family, "{}", triple);
+            assert_eq!(target_triple_is_musl(triple), musl, "{}", triple);
+        }
+    }
+
+    #[test]
+    fn test_is_target_os_family() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+
+        let family = TargetOsFamily::from_triple(default_target_triple());
+
+        for (function, expected) in [
+            ("is_target_linux()", family == TargetOsFamily::Linux),
+            ("is_target_macos()", family == TargetOsFamily::MacOs),
+            ("is_target_windows()", family == TargetOsFamily::Windows),
+            (
+                "is_target_musl()",
+                target_triple_is_musl(default_target_triple()),
+            ),
+        ] {
+            assert_eq!(eval.eval(function)?.to_bool(), expected, "{}", function);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_build_release() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("set_build_release(True)")?;
+
+        {
+            let context_value = eval.pyoxidizer_context_value().unwrap();
+            let context = context_value
+                .downcast_ref::<PyOxidizerEnvironmentContext>()
+                .unwrap();
+            assert!(context.build_release);
+        }
+
+        assert!(eval
+            .target_build_path("foo")?
+            .ends_with(PathBuf::from("release").join("foo")));
+
+        starlark_nok("set_build_release('yes')");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_verbose() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+
+        let verbose = |eval: &EvaluationContext| {
+            let context_value = eval.pyoxidizer_context_value().unwrap();
+            let context = context_value
+                .downcast_ref::<PyOxidizerEnvironmentContext>()
+                .unwrap();
+            context.verbose
+        };
+
+        assert!(!verbose(&eval));
+        eval.eval("set_verbose(True)")?;
+        assert!(verbose(&eval));
+        eval.eval("set_verbose(False)")?;
+        assert!(!verbose(&eval));
+
+        starlark_nok("set_verbose('yes')");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_state() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("set_build_state('codegen_mode', 'fast')")?;
+
+        assert_eq!(
+            eval.eval("get_build_state('codegen_mode')")?.to_str(),
+            "fast"
+        );
+        assert_eq!(
+            eval.eval("get_build_state('target_triple')")?.to_str(),
+            default_target_triple()
+        );
+        assert!(eval.eval("get_build_state('missing')").is_err());
+        assert!(eval.eval("set_build_state('opt_level', '3')").is_err());
+
+        let context_value = eval.pyoxidizer_context_value().unwrap();
+        let context = context_value
+            .downcast_ref::<PyOxidizerEnvironmentContext>()
+            .unwrap();
+
+        assert_eq!(context.get_build_state_string("codegen_mode")?, "fast");
+        assert_eq!(context.get_build_state_string("opt_level")?, "0");
+        let err = context.get_build_state_string("missing").unwrap_err();
+        assert!(err.to_string().contains("invalid build state key: missing"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_environment_template() -> Result<()> {
+        let template = EnvironmentTemplate::new()?;
+
+        // Evaluations sharing a template don't see each other's state.
+        let mut a = test_evaluation_context_builder()?
+            .environment_template(template.clone())
+            .into_context()?;
+        let mut b = test_evaluation_context_builder()?
+            .environment_template(template)
+            .release(true)
+            .into_context()?;
+
+        a.eval("value = 1")?;
+        assert!(b.eval("value").is_err());
+        assert!(!a.eval("BUILD_RELEASE")?.to_bool());
+        assert!(b.eval("BUILD_RELEASE")?.to_bool());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_populate_environment_error() -> Result<()> {
+        let env = crate::testutil::get_env()?;
+        let context = PyOxidizerEnvironmentContext::new(
+            &env,
+            false,
+            &std::env::current_dir()?.join("pyoxidizer.bzl"),
+            None,
+            None,
+            default_target_triple(),
+            DistributionOptions::default(),
+        )?;
+
+        let (mut starlark_env, mut type_values) = starlark::stdlib::global_environment();
+        register_starlark_dialect(&mut starlark_env, &mut type_values)?;
+
+        // Populating a frozen environment fails in the first dialect to set a value.
+        starlark_env.freeze();
+
+        let err = populate_environment(&mut starlark_env, &mut type_values, context, None, false)
+            .unwrap_err();
+        assert!(matches!(err, EnvironmentSetupError::BuildTargets(_)));
+        assert!(err.to_string().contains("build targets"));
+
+        assert!(matches!(
+            EnvironmentSetupError::from(EnvironmentError::TryingToMutateFrozenEnvironment),
+            EnvironmentSetupError::PyOxidizer(_)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_environment_template_from_environment() -> Result<()> {
+        let (mut env, type_values) = starlark::stdlib::global_environment();
+        env.set("EMBEDDER_VALUE", Value::from(42)).unwrap();
+
+        let template = EnvironmentTemplate::from_environment(env, type_values)?;
+
+        let mut eval = test_evaluation_context_builder()?
+            .environment_template(template)
+            .into_context()?;
+
+        // The pre-registered global survives alongside PyOxidizer's globals.
+        assert_eq!(eval.eval("EMBEDDER_VALUE")?.to_int().unwrap(), 42);
+        assert_eq!(
+            eval.eval("canonicalize_triple('amd64-linux-gnu')")?
+                .to_str(),
+            canonical_target_triple("amd64-linux-gnu")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_context_missing() {
+        let (mut env, mut type_values) = starlark::stdlib::global_environment();
+        register_starlark_dialect(&mut env, &mut type_values).unwrap();
+
+        match get_context(&type_values) {
+            Err(ValueError::Runtime(e)) => {
+                assert_eq!(e.code, NO_CONTEXT_ERROR_CODE);
+                assert!(e.message.contains("populate_environment()"));
+                assert!(e.message.contains("<none>"));
+            }
+            _ => panic!("expected runtime error"),
+        }
+    }
+
+    #[test]
+    fn test_starlark_error() {
+        let err =
+            StarlarkError::DistributionsPath(anyhow!("inner").context("bad distributions path"));
+        assert_eq!(err.code(), BUILD_ERROR_CODE);
+        assert_eq!(err.to_string(), "bad distributions path: inner");
+        assert!(std::error::Error::source(&err).is_some());
+
+        match ValueError::from(err) {
+            ValueError::Runtime(e) => {
+                assert_eq!(e.code, BUILD_ERROR_CODE);
+                assert_eq!(e.message, "bad distributions path: inner");
+                assert_eq!(e.label, "set_python_distributions_path()");
+            }
+            _ => panic!("expected runtime error"),
+        }
+    }
+
+    #[test]
+    fn test_list_cached_distributions() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("default_python_distribution().make_python_packaging_policy()")?;
+
+        let keys = eval.eval("list_cached_distributions()")?;
+        assert_eq!(keys.get_type(), "list");
+
+        let keys = keys
+            .iter()
+            .unwrap()
+            .iter()
+            .map(|v| v.to_str())
+            .collect::<Vec<_>>();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+
+        let location = default_distribution_location(
+            &DistributionFlavor::Standalone,
+            default_target_triple(),
+            None,
+        )?;
+        assert!(keys.contains(&location.sha256().to_lowercase()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_distribution_cache_stats() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        let stats = eval.eval("get_distribution_cache_stats()")?;
+        assert_eq!(stats.get_type(), "dict");
+
+        let before = eval
+            .eval("get_distribution_cache_stats()['hits']")?
+            .to_int()
+            .unwrap();
+        eval.eval("default_python_distribution().make_python_packaging_policy()")?;
+        eval.eval("default_python_distribution().make_python_packaging_policy()")?;
+        let after = eval
+            .eval("get_distribution_cache_stats()['hits']")?
+            .to_int()
+            .unwrap();
+        assert!(after >= before + 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_warn() {
+        let res = starlark_ok("warn('hello', 'world', 42)");
+        assert_eq!(res.get_type(), "NoneType");
+    }
+
+    #[test]
+    fn test_warn_collected() -> Result<()> {
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("warn('first')")?;
+        eval.eval("warn('second', 2)")?;
+        eval.eval("print('not a warning')")?;
+
+        let context_value = eval.pyoxidizer_context_value().unwrap();
+        let mut context = context_value
+            .downcast_mut::<PyOxidizerEnvironmentContext>()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            context.take_warnings(),
+            vec!["first".to_string(), "second 2".to_string()]
+        );
+        assert!(context.take_warnings().is_empty());
+
+        context.push_warning("third");
+        assert_eq!(context.take_warnings(), vec!["third".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_file() -> Result<()> {
+        let env = crate::testutil::get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        std::fs::write(temp_dir.path().join("notice.txt"), "hello\n")?;
+        std::fs::write(temp_dir.path().join("binary"), b"\xff\xfe")?;
+        std::fs::write(temp_dir.path().join("large"), vec![b'a'; 32])?;
+
+        let mut eval = test_evaluation_context_builder()?
+            .config_path(temp_dir.path().join("pyoxidizer.bzl"))
+            .read_file_max_size(16)
+            .into_context()?;
+
+        assert_eq!(eval.eval("read_file('notice.txt')")?.to_str(), "hello\n");
+        assert_eq!(
+            eval.eval("read_file('binary', encoding='latin-1')")?
+                .to_str(),
+            "\u{ff}\u{fe}"
+        );
+
+        let err = eval.eval("read_file('binary')").unwrap_err();
+        assert!(err.to_string().contains("not valid UTF-8"));
+
+        let err = eval.eval("read_file('large')").unwrap_err();
+        assert!(err.to_string().contains("exceeds the maximum of 16 bytes"));
+
+        assert!(eval.eval("read_file('missing')").is_err());
+        assert!(eval
+            .eval("read_file('notice.txt', encoding='utf-16')")
+            .is_err());
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sha256_file() -> Result<()> {
+        let env = crate::testutil::get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        std::fs::write(temp_dir.path().join("hello.txt"), "hello world")?;
+        std::fs::write(temp_dir.path().join("empty"), "")?;
+
+        let mut eval = test_evaluation_context_builder()?
+            .config_path(temp_dir.path().join("pyoxidizer.bzl"))
+            .into_context()?;
+
+        assert_eq!(
+            eval.eval("sha256_file('hello.txt')")?.to_str(),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        assert_eq!(
+            eval.eval("sha256_file('empty')")?.to_str(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let err = eval.eval("sha256_file('missing')").unwrap_err();
+        assert!(err.to_string().contains("missing"));
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_print() {
+        starlark_ok("print('hello, world')");
+    }
+
+    #[test]
+    fn test_env() {
+        std::env::set_var("PYOXIDIZER_TEST_ENV_SET", "value");
+        std::env::remove_var("PYOXIDIZER_TEST_ENV_UNSET");
+
+        let value = starlark_ok("env('PYOXIDIZER_TEST_ENV_SET')");
+        assert_eq!(value.to_str(), "value");
+
+        let value = starlark_ok("env('PYOXIDIZER_TEST_ENV_UNSET')");
+        assert_eq!(value.get_type(), "NoneType");
+
+        let value = starlark_ok("env('PYOXIDIZER_TEST_ENV_UNSET', default='fallback')");
+        assert_eq!(value.to_str(), "fallback");
+    }
+
+    #[test]
+    fn test_get_env() -> Result<()> {
+        std::env::set_var("PYOXIDIZER_TEST_GET_ENV_SET", "value");
+        std::env::remove_var("PYOXIDIZER_TEST_GET_ENV_UNSET");
+
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+
+        assert_eq!(
+            eval.eval("get_env('PYOXIDIZER_TEST_GET_ENV_SET')")?
+                .to_str(),
+            "value"
+        );
+        assert_eq!(
+            eval.eval("get_env('PYOXIDIZER_TEST_GET_ENV_UNSET', 'fallback')")?
+                .to_str(),
+            "fallback"
+        );
+        assert_eq!(
+            eval.eval("get_env('PYOXIDIZER_TEST_GET_ENV_UNSET')")?
+                .get_type(),
+            "NoneType"
+        );
+
+        // Lookups are recorded like those made via env().
+        let value = eval.pyoxidizer_context_value().unwrap();
+        let context = value
+            .downcast_ref::<PyOxidizerEnvironmentContext>()
+            .unwrap();
+        assert_eq!(
+            context.env_lookups().get("PYOXIDIZER_TEST_GET_ENV_SET"),
+            Some(&Some("value".to_string()))
+        );
+        assert_eq!(
+            context.env_lookups().get("PYOXIDIZER_TEST_GET_ENV_UNSET"),
+            Some(&None)
+        );
+
+        Ok(())
     }
 }
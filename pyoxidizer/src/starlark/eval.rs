@@ -4,17 +4,22 @@
 
 use {
     crate::{
-        environment::default_target_triple,
-        py_packaging::distribution::DistributionCache,
-        starlark::env::{
-            populate_environment, register_starlark_dialect, PyOxidizerContext,
-            PyOxidizerEnvironmentContext,
+        events::{duration_ms, BuildEvent, EventSink},
+        logging::ScopedLogFields,
+        py_packaging::distribution::{CancellationToken, DistributionCache},
+        starlark::{
+            env::{
+                load_config_toml, populate_environment, validate_identifier, DistributionOptions,
+                EnvironmentTemplate, IncludeEnvironment, PyOxidizerContext,
+                PyOxidizerEnvironmentContext, CONFIG_TOML_FILENAME, DEFAULT_READ_FILE_MAX_SIZE,
+            },
+            loader::ConfigFileLoader,
         },
     },
-    anyhow::{anyhow, Result},
+    anyhow::{anyhow, Context, Result},
     codemap::CodeMap,
     codemap_diagnostic::{Diagnostic, Emitter},
-    log::error,
+    log::{error, info},
     starlark::{
         environment::{Environment, EnvironmentError, TypeValues},
         eval::call_stack::CallStack,
@@ -25,27 +30,53 @@ use {
         },
     },
     starlark_dialect_build_targets::{
-        build_target, run_target, EnvironmentContext, ResolvedTarget,
+        build_target, resolve_target, run_target, EnvironmentContext, PrintCapture, ResolvedTarget,
+        RunMode,
     },
     std::{
-        collections::HashMap,
+        collections::{BTreeMap, BTreeSet, HashMap},
         path::{Path, PathBuf},
         sync::{Arc, Mutex},
+        time::{Duration, Instant},
     },
 };
 
+/// Function validating a populated Starlark environment.
+pub type EnvironmentValidatorFn = dyn FnMut(&Environment, &TypeValues) -> Result<()>;
+
 /// Builder type to construct `EvaluationContext` instances.
 pub struct EvaluationContextBuilder {
     env: crate::environment::Environment,
     config_path: PathBuf,
+    working_dir: Option<PathBuf>,
+    build_host_triple: Option<String>,
     build_target_triple: String,
     release: bool,
     verbose: bool,
     resolve_targets: Option<Vec<String>>,
     build_script_mode: bool,
     build_opt_level: String,
+    offline: bool,
+    ca_bundle: Option<PathBuf>,
+    content_addressed_distributions: bool,
+    local_distributions: Option<PathBuf>,
+    cancellation: Option<CancellationToken>,
+    python_distributions_path: Option<PathBuf>,
+    confine_build_path: bool,
+    flat_build_layout: bool,
+    sandbox: bool,
+    allowed_loads: Option<BTreeSet<PathBuf>>,
+    read_file_max_size: u64,
+    dry_run: bool,
+    event_sink: Option<Arc<EventSink>>,
+    capture_print: bool,
+    timeout: Option<Duration>,
     distribution_cache: Option<Arc<DistributionCache>>,
+    environment_template: Option<EnvironmentTemplate>,
     extra_vars: HashMap<String, Option<String>>,
+    config_toml: bool,
+    global_vars: BTreeMap<String, Value>,
+    validator: Option<Box<EnvironmentValidatorFn>>,
 }
 
 impl EvaluationContextBuilder {
@@ -57,14 +88,35 @@ impl EvaluationContextBuilder {
         Self {
             env: env.clone(),
             config_path: config_path.as_ref().to_path_buf(),
+            working_dir: None,
+            build_host_triple: None,
             build_target_triple: build_target_triple.to_string(),
             release: false,
             verbose: false,
             resolve_targets: None,
             build_script_mode: false,
             build_opt_level: "0".to_string(),
+            offline: false,
+            ca_bundle: None,
+            content_addressed_distributions: false,
+            local_distributions: None,
+            cancellation: None,
+            python_distributions_path: None,
+            confine_build_path: false,
+            flat_build_layout: false,
+            sandbox: false,
+            allowed_loads: None,
+            read_file_max_size: DEFAULT_READ_FILE_MAX_SIZE,
+            dry_run: false,
+            event_sink: None,
+            capture_print: false,
+            timeout: None,
             distribution_cache: None,
+            environment_template: None,
             extra_vars: HashMap::new(),
+            config_toml: false,
+            global_vars: BTreeMap::new(),
+            validator: None,
         }
     }
 
@@ -79,6 +131,25 @@ impl EvaluationContextBuilder {
         self
     }
 
+    /// Set the directory to evaluate from instead of the config file's directory.
+    ///
+    /// Use this when evaluating source code via `EvaluationContext::evaluate_source()`
+    /// so the config path doesn't need to exist on the filesystem.
+    #[must_use]
+    pub fn working_dir(mut self, path: impl AsRef<Path>) -> Self {
+        self.working_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the triple of the machine we are building on.
+    ///
+    /// `None`, the default, uses the triple this binary was built for.
+    #[must_use]
+    pub fn build_host_triple(mut self, value: Option<impl ToString>) -> Self {
+        self.build_host_triple = value.map(|x| x.to_string());
+        self
+    }
+
     #[must_use]
     pub fn build_target_triple(mut self, value: impl ToString) -> Self {
         self.build_target_triple = value.to_string();
@@ -97,6 +168,140 @@ impl EvaluationContextBuilder {
         self
     }
 
+    #[must_use]
+    pub fn offline(mut self, value: bool) -> Self {
+        self.offline = value;
+        self
+    }
+
+    /// Set whether to store Python distribution archives once per content hash.
+    ///
+    /// Has no effect if a distribution cache is set via `distribution_cache()`.
+    #[must_use]
+    pub fn content_addressed_distributions(mut self, value: bool) -> Self {
+        self.content_addressed_distributions = value;
+        self
+    }
+
+    /// Set a directory of Python distribution archives to use instead of downloading.
+    ///
+    /// Has no effect if a distribution cache is set via `distribution_cache()`.
+    #[must_use]
+    pub fn local_distributions(mut self, path: Option<impl AsRef<Path>>) -> Self {
+        self.local_distributions = path.map(|p| p.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set a token for cancelling downloading and extracting Python distributions.
+    ///
+    /// Once the token is set, resolving a distribution that isn't cached fails.
+    /// Has no effect if a distribution cache is set via `distribution_cache()`.
+    #[must_use]
+    pub fn cancellation(mut self, token: Option<CancellationToken>) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Set a PEM bundle of additional certificates to trust when downloading.
+    #[must_use]
+    pub fn ca_bundle(mut self, path: Option<impl AsRef<Path>>) -> Self {
+        self.ca_bundle = path.map(|p| p.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the directory to store Python distributions in.
+    ///
+    /// Relative paths are resolved against the directory of the config file.
+    /// The config file can still override this via `set_python_distributions_path()`.
+    #[must_use]
+    pub fn python_distributions_path(mut self, path: Option<impl AsRef<Path>>) -> Self {
+        self.python_distributions_path = path.map(|p| p.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set whether the build path must stay within the config file's directory.
+    #[must_use]
+    pub fn confine_build_path(mut self, value: bool) -> Self {
+        self.confine_build_path = value;
+        self
+    }
+
+    /// Set whether targets are built directly beneath the build path.
+    ///
+    /// By default, target build paths are nested beneath directories for the
+    /// target triple and release profile so builds for different
+    /// configurations don't collide.
+    #[must_use]
+    pub fn flat_build_layout(mut self, value: bool) -> Self {
+        self.flat_build_layout = value;
+        self
+    }
+
+    /// Set whether to disable Starlark functions that access the filesystem.
+    ///
+    /// Use this when evaluating untrusted configuration files.
+    #[must_use]
+    pub fn sandbox(mut self, value: bool) -> Self {
+        self.sandbox = value;
+        self
+    }
+
+    /// Set the paths `load()` is permitted to load.
+    ///
+    /// Loading any other path is an error. `None`, the default, permits
+    /// loading any path.
+    #[must_use]
+    pub fn allowed_loads(mut self, paths: Option<BTreeSet<PathBuf>>) -> Self {
+        self.allowed_loads = paths;
+        self
+    }
+
+    /// Set the maximum size in bytes of files `read_file()` will read.
+    #[must_use]
+    pub fn read_file_max_size(mut self, value: u64) -> Self {
+        self.read_file_max_size = value;
+        self
+    }
+
+    /// Set whether to validate the config without downloading or building anything.
+    ///
+    /// Implies offline mode.
+    #[must_use]
+    pub fn dry_run(mut self, value: bool) -> Self {
+        self.dry_run = value;
+        self
+    }
+
+    /// Set where to write build lifecycle events as JSON lines.
+    ///
+    /// `None`, the default, disables events.
+    #[must_use]
+    pub fn event_sink(mut self, sink: Option<Arc<EventSink>>) -> Self {
+        self.event_sink = sink;
+        self
+    }
+
+    /// Set whether to capture `print()` output for retrieval after evaluation.
+    ///
+    /// Output is still logged. Retrieve it via
+    /// `PyOxidizerEnvironmentContext::take_print_output()`.
+    #[must_use]
+    pub fn capture_print(mut self, value: bool) -> Self {
+        self.capture_print = value;
+        self
+    }
+
+    /// Set the maximum wall-clock time evaluation may take.
+    ///
+    /// The Starlark interpreter can't be interrupted. So the timeout is
+    /// checked when including files, resolving Python distributions, and
+    /// building targets. `None`, the default, disables the timeout.
+    #[must_use]
+    pub fn timeout(mut self, value: Option<Duration>) -> Self {
+        self.timeout = value;
+        self
+    }
+
     #[must_use]
     pub fn resolve_targets_optional(mut self, targets: Option<Vec<impl ToString>>) -> Self {
         self.resolve_targets =
@@ -134,11 +339,57 @@ impl EvaluationContextBuilder {
         self
     }
 
+    /// Set the template to create the Starlark environment from.
+    ///
+    /// Reusing a template across evaluations avoids registering the Starlark
+    /// dialect each time. By default, a new template is constructed.
+    #[must_use]
+    pub fn environment_template(mut self, template: EnvironmentTemplate) -> Self {
+        self.environment_template = Some(template);
+        self
+    }
+
     #[must_use]
     pub fn extra_vars(mut self, extra_vars: HashMap<String, Option<String>>) -> Self {
         self.extra_vars = extra_vars;
         self
     }
+
+    /// Set whether to define top-level keys of a `pyoxidizer.toml` next to the
+    /// config file as globals.
+    ///
+    /// Disabled by default. Keys must be valid Starlark identifiers that don't
+    /// conflict with existing globals.
+    #[must_use]
+    pub fn config_toml(mut self, value: bool) -> Self {
+        self.config_toml = value;
+        self
+    }
+
+    /// Set variables to define as globals before the config file is evaluated.
+    ///
+    /// Names must be valid Starlark identifiers that don't conflict with
+    /// existing globals. Variables are also available as `PyOxidizer.*`
+    /// type values.
+    #[must_use]
+    pub fn global_vars(mut self, vars: BTreeMap<String, Value>) -> Self {
+        self.global_vars = vars;
+        self
+    }
+
+    /// Set a function to validate the Starlark environment.
+    ///
+    /// The function is called once the environment is fully populated, before
+    /// any configuration file is evaluated. An error aborts construction of
+    /// the `EvaluationContext`.
+    #[must_use]
+    pub fn validator(
+        mut self,
+        f: impl FnMut(&Environment, &TypeValues) -> Result<()> + 'static,
+    ) -> Self {
+        self.validator = Some(Box::new(f));
+        self
+    }
 }
 
 /// Interface to evaluate Starlark configuration files.
@@ -152,6 +403,8 @@ pub struct EvaluationContext {
     parent_env: Environment,
     child_env: Environment,
     type_values: TypeValues,
+    /// Log fields attributing records to this evaluation while it is alive.
+    _log_fields: ScopedLogFields,
 }
 
 impl TryFrom<EvaluationContextBuilder> for EvaluationContext {
@@ -164,26 +417,58 @@ impl TryFrom<EvaluationContextBuilder> for EvaluationContext {
 
 impl EvaluationContext {
     pub fn from_builder(builder: EvaluationContextBuilder) -> Result<Self> {
-        let context = PyOxidizerEnvironmentContext::new(
+        let mut context = PyOxidizerEnvironmentContext::new(
             &builder.env,
             builder.verbose,
             &builder.config_path,
-            default_target_triple(),
+            builder.working_dir.as_deref(),
+            builder.build_host_triple.as_deref(),
             &builder.build_target_triple,
-            builder.release,
-            &builder.build_opt_level,
-            builder.distribution_cache,
-            builder.extra_vars,
+            DistributionOptions {
+                offline: builder.offline || builder.dry_run,
+                ca_bundle: builder.ca_bundle,
+                content_addressed: builder.content_addressed_distributions,
+                local_distributions: builder.local_distributions,
+                cancellation: builder.cancellation,
+                cache: builder.distribution_cache,
+            },
         )?;
+        context.build_release = builder.release;
+        context.set_build_opt_level(&builder.build_opt_level)?;
+        context.extra_vars = builder.extra_vars;
+        context.confine_build_path = builder.confine_build_path;
+        context.flat_build_layout = builder.flat_build_layout;
+        context.sandbox = builder.sandbox;
+        context.allowed_loads = builder.allowed_loads;
+        context.read_file_max_size = builder.read_file_max_size;
+        context.dry_run = builder.dry_run;
+        context.event_sink = builder.event_sink;
+        if builder.capture_print {
+            context.print_capture = Some(PrintCapture::default());
+        }
+        context.evaluation_deadline = builder
+            .timeout
+            .map(|timeout| (Instant::now() + timeout, timeout));
+        if let Some(path) = &builder.python_distributions_path {
+            context.set_python_distributions_path(path)?;
+        }
+
+        // Allow structured log output to be attributed to this evaluation.
+        let mut log_fields = ScopedLogFields::default();
+        log_fields.set("config_path", context.config_path.display());
+        log_fields.set("target_triple", &context.build_target_triple);
 
-        let (mut parent_env, mut type_values) = starlark::stdlib::global_environment();
+        let config_dir = context.cwd.clone();
 
-        register_starlark_dialect(&mut parent_env, &mut type_values)
-            .map_err(|e| anyhow!("error creating Starlark environment: {:?}", e))?;
+        let template = match builder.environment_template {
+            Some(template) => template,
+            None => EnvironmentTemplate::new()
+                .map_err(|e| anyhow!("error creating Starlark environment: {:?}", e))?,
+        };
 
-        // All variables go in a child environment. Upon calling child(), the parent
-        // environment is frozen and no new changes are allowed.
-        let mut child_env = parent_env.child("pyoxidizer");
+        // All variables go in a child environment. The parent environment is
+        // frozen and no new changes are allowed.
+        let (parent_env, mut child_env, mut type_values) = template.instantiate();
 
         populate_environment(
             &mut child_env,
@@ -194,11 +479,77 @@ impl EvaluationContext {
         )
         .map_err(|e| anyhow!("error populating Starlark environment: {:?}", e))?;
 
-        Ok(Self {
+        // Values from a TOML file in the evaluation directory become globals. They
+        // are set before the config file is evaluated, so assignments in Starlark
+        // take precedence.
+        let config_toml = if builder.config_toml {
+            load_config_toml(&config_dir)?
+        } else {
+            BTreeMap::new()
+        };
+
+        for (key, value) in config_toml {
+            validate_identifier(&key)
+                .with_context(|| format!("loading {}", CONFIG_TOML_FILENAME))?;
+
+            if child_env.get(&key).is_ok() {
+                return Err(anyhow!(
+                    "{} in {} conflicts with a built-in global",
+                    key,
+                    CONFIG_TOML_FILENAME
+                ));
+            }
+
+            child_env
+                .set(&key, value)
+                .map_err(|e| anyhow!("error setting {}: {:?}", key, e))?;
+        }
+
+        for (name, value) in builder.global_vars {
+            validate_identifier(&name)?;
+
+            if child_env.get(&name).is_ok() {
+                return Err(anyhow!("{} conflicts with an existing global", name));
+            }
+
+            child_env
+                .set(&name, value.clone())
+                .map_err(|e| anyhow!("error setting {}: {:?}", name, e))?;
+            type_values.add_type_value(PyOxidizerContext::TYPE, &name, value);
+        }
+
+        if let Some(mut validator) = builder.validator {
+            validator(&child_env, &type_values).context("validating Starlark environment")?;
+        }
+
+        let res = Self {
             parent_env,
             child_env,
             type_values,
-        })
+            _log_fields: log_fields,
+        };
+
+        res.pyoxidizer_context_mut(|context| {
+            context.include_env = Some(IncludeEnvironment(res.child_env.clone()));
+        })?;
+
+        Ok(res)
+    }
+
+    /// Run a function with a mutable reference to the `PyOxidizerEnvironmentContext`.
+    fn pyoxidizer_context_mut<T>(
+        &self,
+        f: impl FnOnce(&mut PyOxidizerEnvironmentContext) -> T,
+    ) -> Result<T> {
+        let value = self
+            .pyoxidizer_context_value()
+            .map_err(|_| anyhow!("could not obtain PyOxidizer context"))?;
+        let mut context = value
+            .downcast_mut::<PyOxidizerEnvironmentContext>()
+            .map_err(|_| anyhow!("unable to obtain mutable PyOxidizer context"))?
+            .ok_or_else(|| anyhow!("context has incorrect type"))?;
+
+        Ok(f(&mut context))
     }
 
     /// Obtain a named variable from the Starlark environment.
@@ -214,15 +565,15 @@ impl EvaluationContext {
     /// Evaluate a Starlark configuration file, returning a Diagnostic on error.
     pub fn evaluate_file_diagnostic(&mut self, config_path: &Path) -> Result<(), Diagnostic> {
         let map = Arc::new(Mutex::new(CodeMap::new()));
-        let file_loader_env = self.parent_env.clone();
+        let file_loader = ConfigFileLoader::new(&map, self.parent_env.clone());
 
-        starlark::eval::simple::eval_file(
+        starlark::eval::eval_file(
             &map,
             &config_path.display().to_string(),
             Dialect::Bzl,
             &mut self.child_env,
             &self.type_values,
-            file_loader_env,
+            file_loader,
         )
         .map_err(|e| {
             let mut msg = Vec::new();
@@ -246,6 +597,48 @@ impl EvaluationContext {
             .map_err(|d| anyhow!(d.message))
     }
 
+    /// Evaluate Starlark configuration source code, returning a Diagnostic on error.
+    ///
+    /// `config_path` is only used to label errors and doesn't need to exist.
+    pub fn evaluate_source_diagnostic(
+        &mut self,
+        config_path: &Path,
+        source: &str,
+    ) -> Result<(), Diagnostic> {
+        let map = Arc::new(Mutex::new(CodeMap::new()));
+        let file_loader = ConfigFileLoader::new(&map, self.parent_env.clone());
+
+        starlark::eval::eval(
+            &map,
+            &config_path.display().to_string(),
+            source,
+            Dialect::Bzl,
+            &mut self.child_env,
+            &self.type_values,
+            file_loader,
+        )
+        .map_err(|e| {
+            let mut msg = Vec::new();
+            let raw_map = map.lock().unwrap();
+            {
+                let mut emitter = codemap_diagnostic::Emitter::vec(&mut msg, Some(&raw_map));
+                emitter.emit(&[e.clone()]);
+            }
+
+            error!("{}", String::from_utf8_lossy(&msg));
+
+            e
+        })?;
+
+        Ok(())
+    }
+
+    /// Evaluate Starlark configuration source code, returning an anyhow Result.
+    pub fn evaluate_source(&mut self, config_path: &Path, source: &str) -> Result<()> {
+        self.evaluate_source_diagnostic(config_path, source)
+            .map_err(|d| anyhow!(d.message))
+    }
+
     /// Evaluate code, returning a `Diagnostic` on error.
     pub fn eval_diagnostic(
         &mut self,
@@ -253,16 +646,16 @@ impl EvaluationContext {
         path: &str,
         code: &str,
     ) -> Result<Value, Diagnostic> {
-        let file_loader_env = self.child_env.clone();
+        let file_loader = ConfigFileLoader::new(map, self.child_env.clone());
 
-        starlark::eval::simple::eval(
+        starlark::eval::eval(
             map,
             path,
             code,
             Dialect::Bzl,
             &mut self.child_env,
             &self.type_values,
-            file_loader_env,
+            file_loader,
         )
     }
 
@@ -319,6 +712,30 @@ impl EvaluationContext {
         pyoxidizer_context.build_path(&self.type_values)
     }
 
+    /// Serialize the settings the build context resolved to as JSON.
+    pub fn context_summary_json(&self) -> Result<String> {
+        let pyoxidizer_context_value = self
+            .pyoxidizer_context_value()
+            .map_err(|e| anyhow!("{:?}", e))?;
+        let pyoxidizer_context = pyoxidizer_context_value
+            .downcast_ref::<PyOxidizerEnvironmentContext>()
+            .ok_or_else(|| anyhow!("context has incorrect type"))?;
+
+        pyoxidizer_context.summary_json(&self.type_values)
+    }
+
+    /// Whether the context is in dry run mode.
+    pub fn dry_run(&self) -> Result<bool> {
+        let pyoxidizer_context_value = self
+            .pyoxidizer_context_value()
+            .map_err(|e| anyhow!("{:?}", e))?;
+        let pyoxidizer_context = pyoxidizer_context_value
+            .downcast_ref::<PyOxidizerEnvironmentContext>()
+            .ok_or_else(|| anyhow!("context has incorrect type"))?;
+
+        Ok(pyoxidizer_context.dry_run)
+    }
+
     pub fn target_build_path(&self, target: &str) -> Result<PathBuf> {
         let context_value = self.build_targets_context_value()?;
         let context = context_value.downcast_ref::<EnvironmentContext>().unwrap();
@@ -349,24 +766,72 @@ impl EvaluationContext {
     }
 
     /// Obtain targets that should be resolved.
+    ///
+    /// Targets are ordered so they come after any other returned targets they
+    /// depend on.
     pub fn targets_to_resolve(&self) -> Result<Vec<String>> {
         let raw_context = self.build_targets_context_value()?;
         let context = raw_context
             .downcast_ref::<EnvironmentContext>()
             .ok_or_else(|| anyhow!("context has incorrect type"))?;
 
-        Ok(context.targets_to_resolve())
+        let targets = context.targets_to_resolve();
+
+        Ok(context
+            .resolution_order(&targets)?
+            .into_iter()
+            .filter(|target| targets.contains(target))
+            .collect())
     }
 
     pub fn build_resolved_target(&mut self, target: &str) -> Result<ResolvedTarget> {
+        self.pyoxidizer_context_mut(|context| context.check_timeout())??;
+
+        if self.dry_run()? {
+            // Target functions are still called so errors in them are
+            // reported. Only building the resolved value is skipped.
+            let mut call_stack = CallStack::default();
+            resolve_target(&self.type_values, &mut call_stack, target)?;
+
+            info!("dry run: not building target {}", target);
+
+            return Ok(ResolvedTarget {
+                run_mode: RunMode::None,
+                output_path: self.target_build_path(target)?,
+            });
+        }
+
+        let event = BuildEvent::TargetStarted {
+            target: target.to_string(),
+        };
+        self.pyoxidizer_context_mut(|context| context.emit_event(event))?;
+        let start = Instant::now();
+
         let mut call_stack = CallStack::default();
 
-        build_target(
+        let res = build_target(
             &mut self.child_env,
             &self.type_values,
             &mut call_stack,
             target,
-        )
+        );
+
+        let duration_ms = duration_ms(start.elapsed());
+        let event = match &res {
+            Ok(resolved) => BuildEvent::TargetFinished {
+                target: target.to_string(),
+                duration_ms,
+                output_path: resolved.output_path.clone(),
+            },
+            Err(e) => BuildEvent::TargetFailed {
+                target: target.to_string(),
+                duration_ms,
+                error: format!("{:?}", e),
+            },
+        };
+        self.pyoxidizer_context_mut(|context| context.emit_event(event))?;
+
+        res
     }
 
     pub fn run_target(&mut self, target: Option<&str>) -> Result<()> {
@@ -381,9 +846,26 @@ impl EvaluationContext {
     }
 }
 
+impl Drop for EvaluationContext {
+    fn drop(&mut self) {
+        // The context holds a reference to the environment holding it. Break
+        // the reference cycle.
+        let _ = self.pyoxidizer_context_mut(|context| {
+            context.include_env = None;
+        });
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use {super::*, crate::testutil::*, starlark::values::dict::Dictionary};
+    use {
+        super::*,
+        crate::{
+            environment::default_target_triple,
+            starlark::testutil::test_evaluation_context_builder, testutil::*,
+        },
+        starlark::values::dict::Dictionary,
+    };
 
     #[test]
     fn test_load() -> Result<()> {
@@ -420,6 +902,628 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_include() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        std::fs::write(
+            temp_dir.path().join("common.bzl"),
+            "COMMON_VALUE = 'common'\n".as_bytes(),
+        )?;
+
+        let config_path = temp_dir.path().join("pyoxidizer.bzl");
+        std::fs::write(
+            &config_path,
+            "include('common.bzl')\nvalue = COMMON_VALUE\n".as_bytes(),
+        )?;
+
+        let mut context = EvaluationContextBuilder::new(
+            &env,
+            config_path.clone(),
+            default_target_triple().to_string(),
+        )
+        .into_context()?;
+        context.evaluate_file(&config_path)?;
+
+        assert_eq!(context.get_var("value").unwrap().to_str(), "common");
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_fields() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+        let first_path = temp_dir.path().join("first.bzl");
+        let second_path = temp_dir.path().join("second.bzl");
+
+        let first = EvaluationContextBuilder::new(&env, &first_path, default_target_triple())
+            .into_context()?;
+        let second = EvaluationContextBuilder::new(&env, &second_path, default_target_triple())
+            .into_context()?;
+
+        assert_eq!(
+            crate::logging::log_fields()["config_path"],
+            second_path.display().to_string()
+        );
+
+        drop(first);
+        assert_eq!(
+            crate::logging::log_fields()["config_path"],
+            second_path.display().to_string()
+        );
+
+        drop(second);
+        assert!(!crate::logging::log_fields().contains_key("config_path"));
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_toml() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        std::fs::write(
+            temp_dir.path().join(CONFIG_TOML_FILENAME),
+            "version = \"1.2.3\"\nbuild_number = 42\noverridden = \"toml\"\n".as_bytes(),
+        )?;
+
+        let config_path = temp_dir.path().join("pyoxidizer.bzl");
+        std::fs::write(
+            &config_path,
+            "v = version\nn = build_number + 1\noverridden = 'starlark'\n".as_bytes(),
+        )?;
+
+        // Loading the TOML file is opt-in.
+        let mut context = EvaluationContextBuilder::new(
+            &env,
+            config_path.clone(),
+            default_target_triple().to_string(),
+        )
+        .into_context()?;
+        assert!(context.evaluate_file(&config_path).is_err());
+
+        let mut context = EvaluationContextBuilder::new(
+            &env,
+            config_path.clone(),
+            default_target_triple().to_string(),
+        )
+        .config_toml(true)
+        .into_context()?;
+        context.evaluate_file(&config_path)?;
+
+        assert_eq!(context.get_var("v").unwrap().to_str(), "1.2.3");
+        assert_eq!(context.get_var("n").unwrap().to_int().unwrap(), 43);
+        assert_eq!(context.get_var("overridden").unwrap().to_str(), "starlark");
+
+        for content in ["CWD = \"nope\"\n", "my-key = \"nope\"\n"] {
+            std::fs::write(temp_dir.path().join(CONFIG_TOML_FILENAME), content)?;
+
+            assert!(EvaluationContextBuilder::new(
+                &env,
+                config_path.clone(),
+                default_target_triple().to_string(),
+            )
+            .config_toml(true)
+            .into_context()
+            .is_err());
+
+            // Invalid files are ignored unless loading is enabled.
+            EvaluationContextBuilder::new(
+                &env,
+                config_path.clone(),
+                default_target_triple().to_string(),
+            )
+            .into_context()?;
+        }
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate_source() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let config_path = temp_dir.path().join("virtual").join("pyoxidizer.bzl");
+
+        let mut context = EvaluationContextBuilder::new(
+            &env,
+            config_path.clone(),
+            default_target_triple().to_string(),
+        )
+        .working_dir(temp_dir.path())
+        .into_context()?;
+        context.evaluate_source(&config_path, "print('x')\nvalue = CONFIG_PATH\n")?;
+
+        assert_eq!(
+            context.get_var("value").unwrap().to_str(),
+            config_path.display().to_string()
+        );
+        assert_eq!(
+            context.get_var("CWD").unwrap().to_str(),
+            temp_dir.path().display().to_string()
+        );
+        assert!(!config_path.exists());
+        assert!(!temp_dir.path().join("virtual").exists());
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_python_distributions_path() -> Result<()> {
+        let cwd = std::env::current_dir()?;
+        let shared = std::env::temp_dir().join("shared-distributions");
+
+        let mut context = test_evaluation_context_builder()?
+            .python_distributions_path(Some(&shared))
+            .into_context()?;
+        context.eval("set_build_path('build-dir')")?;
+
+        let path = context.pyoxidizer_context_mut(|context| context.python_distributions_path())?;
+        assert_eq!(path.unwrap(), shared);
+        assert_eq!(context.build_path().unwrap(), cwd.join("build-dir"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_addressed_distributions() -> Result<()> {
+        let env = get_env()?;
+
+        for enabled in [false, true] {
+            let context = EvaluationContextBuilder::new(
+                &env,
+                std::env::current_dir()?.join("dummy"),
+                default_target_triple(),
+            )
+            .content_addressed_distributions(enabled)
+            .into_context()?;
+
+            context.pyoxidizer_context_mut(|context| {
+                assert_eq!(context.distribution_options.content_addressed, enabled);
+                assert_eq!(context.distribution_cache.is_content_addressed(), enabled);
+            })?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_distributions() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let context = EvaluationContextBuilder::new(
+            &env,
+            std::env::current_dir()?.join("dummy"),
+            default_target_triple(),
+        )
+        .local_distributions(Some(temp_dir.path()))
+        .into_context()?;
+
+        context.pyoxidizer_context_mut(|context| {
+            assert_eq!(
+                context.distribution_options.local_distributions.as_deref(),
+                Some(temp_dir.path())
+            );
+        })?;
+
+        // The directory is indexed when the context is constructed.
+        assert!(EvaluationContextBuilder::new(
+            &env,
+            std::env::current_dir()?.join("dummy"),
+            default_target_triple(),
+        )
+        .local_distributions(Some(temp_dir.path().join("missing")))
+        .into_context()
+        .is_err());
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancellation() -> Result<()> {
+        let env = get_env()?;
+        let token = CancellationToken::default();
+
+        let mut context = EvaluationContextBuilder::new(
+            &env,
+            std::env::current_dir()?.join("dummy"),
+            default_target_triple(),
+        )
+        .cancellation(Some(token.clone()))
+        .into_context()?;
+
+        context.pyoxidizer_context_mut(|context| {
+            assert!(context.distribution_options.cancellation.is_some());
+        })?;
+
+        token.store(true, std::sync::atomic::Ordering::Relaxed);
+
+        let err = context
+            .eval("default_python_distribution().python_major_minor_version")
+            .unwrap_err();
+        assert!(err.to_string().contains("cancelled"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_target_triples() -> Result<()> {
+        let env = get_env()?;
+        let cache = Arc::new(DistributionCache::new(Some(
+            &env.python_distributions_dir(),
+        )));
+
+        let contexts = ["x86_64-unknown-linux-gnu", "x86_64-pc-windows-msvc"]
+            .iter()
+            .map(|triple| {
+                EvaluationContextBuilder::new(&env, std::env::current_dir()?.join("dummy"), triple)
+                    .distribution_cache(cache.clone())
+                    .into_context()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let triples = contexts
+            .iter()
+            .map(|context| context.get_var("BUILD_TARGET_TRIPLE").unwrap().to_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            triples,
+            vec!["x86_64-unknown-linux-gnu", "x86_64-pc-windows-msvc"]
+        );
+
+        for context in &contexts {
+            context.pyoxidizer_context_mut(|context| {
+                assert!(Arc::ptr_eq(&context.distribution_cache, &cache));
+            })?;
+        }
+
+        // Each triple builds into its own directory.
+        let prefixes = contexts
+            .iter()
+            .map(|context| {
+                context.pyoxidizer_context_mut(|context| context.target_build_path_prefix())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_ne!(prefixes[0], prefixes[1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_build_path_layout() -> Result<()> {
+        let build_path = std::env::current_dir()?.join("build");
+
+        let mut context = test_evaluation_context_builder()?
+            .build_target_triple("x86_64-unknown-linux-gnu")
+            .into_context()?;
+        context.eval("set_build_release(True)")?;
+        for target in ["a", "b"] {
+            assert_eq!(
+                context.target_build_path(target)?,
+                build_path
+                    .join("x86_64-unknown-linux-gnu")
+                    .join("release")
+                    .join(target)
+            );
+        }
+
+        let context = test_evaluation_context_builder()?
+            .build_target_triple("x86_64-unknown-linux-gnu")
+            .flat_build_layout(true)
+            .into_context()?;
+        for target in ["a", "b"] {
+            assert_eq!(context.target_build_path(target)?, build_path.join(target));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeout() -> Result<()> {
+        let mut context = test_evaluation_context_builder()?
+            .timeout(Some(Duration::from_secs(3600)))
+            .into_context()?;
+        context.eval("dist = default_python_distribution()")?;
+
+        let mut context = test_evaluation_context_builder()?
+            .timeout(Some(Duration::ZERO))
+            .into_context()?;
+        let err = context
+            .eval("dist = default_python_distribution()\ndist.python_resources()")
+            .unwrap_err();
+        let message = format!("{:?}", err);
+        assert!(message.contains("exceeded timeout of 0s"));
+        assert!(message.contains("dummy"));
+
+        context.eval("def make_foo():\n    return None\nregister_target('foo', make_foo)")?;
+        let err = context.build_resolved_target("foo").unwrap_err();
+        assert!(err.to_string().contains("exceeded timeout of 0s"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validator() -> Result<()> {
+        test_evaluation_context_builder()?
+            .validator(|env, _| {
+                env.get("CONFIG_PATH")
+                    .map(|_| ())
+                    .map_err(|_| anyhow!("CONFIG_PATH not defined"))
+            })
+            .into_context()?;
+
+        let err = test_evaluation_context_builder()?
+            .validator(|env, _| {
+                env.get("REQUIRED_GLOBAL")
+                    .map(|_| ())
+                    .map_err(|_| anyhow!("REQUIRED_GLOBAL not defined"))
+            })
+            .into_context()
+            .err()
+            .unwrap();
+        assert!(format!("{:?}", err).contains("REQUIRED_GLOBAL not defined"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_cycle() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        std::fs::write(
+            temp_dir.path().join("other.bzl"),
+            "include('pyoxidizer.bzl')\n".as_bytes(),
+        )?;
+
+        let config_path = temp_dir.path().join("pyoxidizer.bzl");
+        std::fs::write(&config_path, "include('other.bzl')\n".as_bytes())?;
+
+        let mut context = EvaluationContextBuilder::new(
+            &env,
+            config_path.clone(),
+            default_target_triple().to_string(),
+        )
+        .into_context()?;
+
+        let err = context.evaluate_file(&config_path).unwrap_err();
+        assert!(err.to_string().contains("include cycle detected"));
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_confine_build_path() -> Result<()> {
+        let mut context = test_evaluation_context_builder()?
+            .confine_build_path(true)
+            .into_context()?;
+
+        context.eval("set_build_path('build/inner')")?;
+        assert!(context.eval("set_build_path('../outside')").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sandbox() -> Result<()> {
+        let mut context = test_evaluation_context_builder()?
+            .sandbox(true)
+            .into_context()?;
+
+        assert_eq!(context.eval("1 + 2")?.to_int().unwrap(), 3);
+        assert_eq!(context.eval("'a' + 'b'")?.to_str(), "ab");
+
+        for code in [
+            "set_build_path('foo')",
+            "include('other.bzl')",
+            "glob(['*.bzl'])",
+            "read_file('pyoxidizer.bzl')",
+        ] {
+            let err = context.eval(code).unwrap_err();
+            assert!(
+                format!("{}", err).contains("PYOXIDIZER_SANDBOX"),
+                "{}",
+                code
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_build() -> Result<()> {
+        let mut context = test_evaluation_context_builder()?
+            .dry_run(true)
+            .into_context()?;
+
+        context.eval("def make_files(): return FileManifest()")?;
+        context.eval("register_target('files', make_files)")?;
+        context.eval("resolve_targets()")?;
+
+        let resolved = context.build_resolved_target("files")?;
+        assert_eq!(resolved.output_path, context.target_build_path("files")?);
+        assert!(!resolved.output_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_build_unresolved() -> Result<()> {
+        let mut context = test_evaluation_context_builder()?
+            .dry_run(true)
+            .into_context()?;
+
+        context.eval("def make_files(): return FileManifest()")?;
+        context.eval("def make_broken(): return FileManifest() + 1")?;
+        context.eval("register_target('files', make_files)")?;
+        context.eval("register_target('broken', make_broken)")?;
+
+        // Target functions run even if the config doesn't resolve targets.
+        context.build_resolved_target("files")?;
+
+        let err = context.build_resolved_target("broken").unwrap_err();
+        assert!(format!("{:?}", err).contains("error resolving target broken"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capture_print() -> Result<()> {
+        let mut context = test_evaluation_context_builder()?
+            .capture_print(true)
+            .into_context()?;
+
+        context.eval("print('hello, world')")?;
+        context.eval("print('a', 'b', sep = '-')")?;
+
+        let value = context.pyoxidizer_context_value().unwrap();
+        let pyoxidizer_context = value
+            .downcast_ref::<PyOxidizerEnvironmentContext>()
+            .unwrap();
+        assert_eq!(
+            pyoxidizer_context.take_print_output(),
+            vec!["hello, world".to_string(), "a-b".to_string()]
+        );
+        assert!(pyoxidizer_context.take_print_output().is_empty());
+
+        // Capturing is disabled by default.
+        let mut context = test_evaluation_context_builder()?.into_context()?;
+        context.eval("print('hello, world')")?;
+        let value = context.pyoxidizer_context_value().unwrap();
+        assert!(value
+            .downcast_ref::<PyOxidizerEnvironmentContext>()
+            .unwrap()
+            .take_print_output()
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_sink() -> Result<()> {
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let buffer = SharedBuffer::default();
+        let mut context = test_evaluation_context_builder()?
+            .config_path(temp_dir.path().join("pyoxidizer.bzl"))
+            .event_sink(Some(Arc::new(EventSink::new(Box::new(buffer.clone())))))
+            .into_context()?;
+
+        // Targets are evaluated by resolve_targets(). So the distribution is
+        // resolved before the target is built.
+        context.eval(
+            "def make_files():\n    default_python_distribution().make_python_packaging_policy()\n    return FileManifest()",
+        )?;
+        context.eval("register_target('files', make_files)")?;
+        context.eval("resolve_targets()")?;
+        let resolved = context.build_resolved_target("files")?;
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        let events = output
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| event["type"].as_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["distribution_resolved", "target_started", "target_finished"]
+        );
+        assert!(events.iter().all(|event| event["timestamp"].is_u64()));
+        assert_eq!(events[1]["target"], "files");
+        assert_eq!(events[2]["target"], "files");
+        assert!(events[2]["duration_ms"].is_u64());
+        assert_eq!(
+            events[2]["output_path"],
+            resolved.output_path.display().to_string()
+        );
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_config_file() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        // The URL is never fetched in dry run mode.
+        let config_path = temp_dir.path().join("pyoxidizer.bzl");
+        std::fs::write(
+            &config_path,
+            format!(
+                "dist = PythonDistribution('{}', url='https://pyoxidizer.invalid/python.tar.zst')\n\
+                 policy = dist.make_python_packaging_policy()\n\
+                 config = dist.make_python_interpreter_config()\n\
+                 resources = dist.python_resources()\n",
+                "0".repeat(64)
+            ),
+        )?;
+
+        let mut context = EvaluationContextBuilder::new(
+            &env,
+            config_path.clone(),
+            default_target_triple().to_string(),
+        )
+        .dry_run(true)
+        .into_context()?;
+        context.eval(&format!(
+            "set_python_distributions_path('{}')",
+            temp_dir
+                .path()
+                .join("dists")
+                .display()
+                .to_string()
+                .escape_default()
+        ))?;
+        context.evaluate_file(&config_path)?;
+
+        let context_value = context.pyoxidizer_context_value().unwrap();
+        let mut pyoxidizer_context = context_value
+            .downcast_mut::<PyOxidizerEnvironmentContext>()
+            .unwrap()
+            .unwrap();
+        assert_eq!(pyoxidizer_context.take_warnings().len(), 3);
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_register_target() -> Result<()> {
         let env = get_env()?;
@@ -473,4 +1577,118 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_allowed_loads() -> Result<()> {
+        let env = get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let permitted = temp_dir.path().join("permitted.bzl");
+        let denied = temp_dir.path().join("denied.bzl");
+        std::fs::write(&permitted, "VALUE = 42\n")?;
+        std::fs::write(&denied, "VALUE = 43\n")?;
+
+        let load_source = |path: &Path| {
+            format!(
+                "load('{}', 'VALUE')\nvalue = VALUE\n",
+                path.display().to_string().replace('\\', "/")
+            )
+        };
+
+        let config_path = temp_dir.path().join("pyoxidizer.bzl");
+
+        let mut context =
+            EvaluationContextBuilder::new(&env, &config_path, default_target_triple())
+                .allowed_loads(Some([permitted.clone()].into_iter().collect()))
+                .into_context()?;
+        context.evaluate_source(&config_path, &load_source(&permitted))?;
+        assert_eq!(context.get_var("value").unwrap().to_int().unwrap(), 42);
+
+        let mut context =
+            EvaluationContextBuilder::new(&env, &config_path, default_target_triple())
+                .allowed_loads(Some([permitted].into_iter().collect()))
+                .into_context()?;
+        let err = context
+            .evaluate_source(&config_path, &load_source(&denied))
+            .unwrap_err();
+        assert!(err.to_string().contains("is not permitted"));
+
+        // Without an allowlist, any path can be loaded.
+        let mut context =
+            EvaluationContextBuilder::new(&env, &config_path, default_target_triple())
+                .into_context()?;
+        context.evaluate_source(&config_path, &load_source(&denied))?;
+        assert_eq!(context.get_var("value").unwrap().to_int().unwrap(), 43);
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pyoxidizer_version_type_value() -> Result<()> {
+        let context = test_evaluation_context_builder()?.into_context()?;
+
+        let placeholder = Value::new(PyOxidizerContext::default());
+        let value = context
+            .type_values
+            .get_type_value(&placeholder, "PYOXIDIZER_VERSION")
+            .unwrap();
+        assert_eq!(value.to_str(), env!("CARGO_PKG_VERSION"));
+
+        let value = context
+            .type_values
+            .get_type_value(&placeholder, "version")
+            .unwrap();
+        assert_eq!(value.to_str(), env!("CARGO_PKG_VERSION"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_global_vars() -> Result<()> {
+        let env = get_env()?;
+
+        let mut vars = BTreeMap::new();
+        vars.insert("APP_VERSION".to_string(), Value::from("1.2.3"));
+        vars.insert("BUILD_NUMBER".to_string(), Value::from(42));
+
+        let mut context = EvaluationContextBuilder::new(
+            &env,
+            std::env::current_dir()?.join("dummy"),
+            default_target_triple(),
+        )
+        .global_vars(vars)
+        .into_context()?;
+
+        assert_eq!(
+            context
+                .eval("'%s-%d' % (APP_VERSION, BUILD_NUMBER)")?
+                .to_str(),
+            "1.2.3-42"
+        );
+
+        let placeholder = Value::new(PyOxidizerContext::default());
+        let value = context
+            .type_values
+            .get_type_value(&placeholder, "APP_VERSION")
+            .unwrap();
+        assert_eq!(value.to_str(), "1.2.3");
+
+        for name in ["GIT-SHA", "BUILD_TARGET_TRIPLE"] {
+            let mut vars = BTreeMap::new();
+            vars.insert(name.to_string(), Value::from("value"));
+
+            assert!(EvaluationContextBuilder::new(
+                &env,
+                std::env::current_dir()?.join("dummy"),
+                default_target_triple(),
+            )
+            .global_vars(vars)
+            .into_context()
+            .is_err());
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Resolution of Starlark `load()` statements. */
+
+use {
+    super::{
+        env::{get_context, PyOxidizerEnvironmentContext},
+        path::resolve_relative_path,
+    },
+    codemap::CodeMap,
+    codemap_diagnostic::{Diagnostic, Level},
+    starlark::{
+        environment::{Environment, TypeValues},
+        eval::{EvalException, FileLoader},
+        syntax::dialect::Dialect,
+    },
+    std::{
+        collections::HashMap,
+        path::Path,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Error code for `load()` of a path not in the allowed set.
+pub const LOAD_DENIED_ERROR_CODE: &str = "PYOXIDIZER_LOAD_DENIED";
+
+/// A `FileLoader` enforcing `PyOxidizerEnvironmentContext::allowed_loads`.
+///
+/// Aside from the allowlist check, this behaves like starlark's
+/// `SimpleFileLoader`: each path is evaluated at most once, in a child of
+/// `parent_env`.
+#[derive(Clone)]
+pub struct ConfigFileLoader {
+    map: Arc<Mutex<CodeMap>>,
+    loaded: Arc<Mutex<HashMap<String, Environment>>>,
+    parent_env: Environment,
+}
+
+impl ConfigFileLoader {
+    pub fn new(map: &Arc<Mutex<CodeMap>>, parent_env: Environment) -> Self {
+        Self {
+            map: map.clone(),
+            loaded: Arc::new(Mutex::new(HashMap::new())),
+            parent_env,
+        }
+    }
+
+    /// Whether the evaluation context permits loading `path`.
+    ///
+    /// Paths are compared after resolving them against the process's current
+    /// directory, which is how `load()` paths are opened.
+    fn check_allowed(&self, path: &str, type_values: &TypeValues) -> Result<(), Diagnostic> {
+        // Environments without a PyOxidizer context have no restrictions.
+        let pyoxidizer_context_value = match get_context(type_values) {
+            Ok(value) => value,
+            Err(_) => return Ok(()),
+        };
+        let pyoxidizer_context =
+            match pyoxidizer_context_value.downcast_ref::<PyOxidizerEnvironmentContext>() {
+                Some(context) => context,
+                None => return Ok(()),
+            };
+
+        let allowed = match &pyoxidizer_context.allowed_loads {
+            Some(allowed) => allowed,
+            None => return Ok(()),
+        };
+
+        let denied = |message: String| Diagnostic {
+            level: Level::Error,
+            message,
+            code: Some(LOAD_DENIED_ERROR_CODE.to_string()),
+            spans: vec![],
+        };
+
+        let cwd = std::env::current_dir()
+            .map_err(|e| denied(format!("unable to resolve current directory: {}", e)))?;
+        let resolve = |p: &Path| {
+            resolve_relative_path(&cwd, p)
+                .map_err(|e| denied(format!("unable to resolve {}: {}", p.display(), e)))
+        };
+
+        let resolved = resolve(Path::new(path))?;
+
+        for candidate in allowed {
+            if resolve(candidate)? == resolved {
+                return Ok(());
+            }
+        }
+
+        Err(denied(format!(
+            "load({}) is not permitted; it is not in the set of allowed load paths",
+            path
+        )))
+    }
+}
+
+impl FileLoader for ConfigFileLoader {
+    fn load(&self, path: &str, type_values: &TypeValues) -> Result<Environment, EvalException> {
+        self.check_allowed(path, type_values)
+            .map_err(EvalException::DiagnosedError)?;
+
+        if let Some(env) = self.loaded.lock().unwrap().get(path) {
+            return Ok(env.clone());
+        }
+
+        let mut env = self.parent_env.child(path);
+
+        starlark::eval::eval_file(
+            &self.map,
+            path,
+            Dialect::Bzl,
+            &mut env,
+            type_values,
+            self.clone(),
+        )
+        .map_err(EvalException::DiagnosedError)?;
+
+        env.freeze();
+        self.loaded
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), env.clone());
+
+        Ok(env)
+    }
+}
@@ -12,6 +12,8 @@ pub mod env;
 pub mod eval;
 pub mod file;
 pub mod file_resource;
+pub mod loader;
+pub mod path;
 pub mod python_distribution;
 pub mod python_embedded_resources;
 pub mod python_executable;
@@ -0,0 +1,307 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Starlark functions for manipulating filesystem paths. */
+
+use {
+    super::env::{get_context, PyOxidizerEnvironmentContext},
+    path_dedot::ParseDot,
+    starlark::{
+        environment::TypeValues,
+        values::{
+            error::{RuntimeError, ValueError},
+            none::NoneType,
+            {Value, ValueResult},
+        },
+        {
+            starlark_fun, starlark_module, starlark_parse_param_type, starlark_signature,
+            starlark_signature_extraction, starlark_signatures,
+        },
+    },
+    starlark_dialect_build_targets::{
+        check_sandbox, optional_list_arg, optional_str_arg, required_list_arg,
+    },
+    std::{
+        collections::BTreeSet,
+        path::{Path, PathBuf},
+    },
+    tugger_common::glob::evaluate_globs,
+};
+
+/// Resolve a path relative to a directory, normalizing `.` and `..` components.
+///
+/// Absolute paths are normalized but otherwise returned as-is.
+pub fn resolve_relative_path(base: &Path, path: &Path) -> std::io::Result<PathBuf> {
+    Ok(base.join(path).parse_dot()?.to_path_buf())
+}
+
+/// config_relative_path(path, must_exist=False)
+fn starlark_config_relative_path(
+    type_values: &TypeValues,
+    path: String,
+    must_exist: bool,
+) -> ValueResult {
+    const LABEL: &str = "config_relative_path()";
+
+    if must_exist {
+        check_sandbox(type_values, LABEL)?;
+    }
+
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let resolved = pyoxidizer_context.resolve_path(&path).map_err(|e| {
+        ValueError::from(RuntimeError {
+            code: "PYOXIDIZER_PATH",
+            message: format!("unable to resolve {}: {}", path, e),
+            label: LABEL.to_string(),
+        })
+    })?;
+
+    if must_exist && !resolved.exists() {
+        return Err(ValueError::from(RuntimeError {
+            code: "PYOXIDIZER_PATH",
+            message: format!("{} does not exist", resolved.display()),
+            label: LABEL.to_string(),
+        }));
+    }
+
+    Ok(Value::from(resolved.display().to_string()))
+}
+
+/// glob_paths(include, exclude=None, strip_prefix=None, allow_outside_cwd=False)
+fn starlark_glob_paths(
+    type_values: &TypeValues,
+    include: &Value,
+    exclude: &Value,
+    strip_prefix: &Value,
+    allow_outside_cwd: bool,
+) -> ValueResult {
+    const LABEL: &str = "glob_paths()";
+
+    check_sandbox(type_values, LABEL)?;
+
+    required_list_arg("include", "string", include)?;
+    optional_list_arg("exclude", "string", exclude)?;
+    let strip_prefix = optional_str_arg("strip_prefix", strip_prefix)?;
+
+    let include = include
+        .iter()?
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>();
+
+    let exclude = match exclude.get_type() {
+        "list" => exclude.iter()?.iter().map(|x| x.to_string()).collect(),
+        _ => Vec::new(),
+    };
+
+    let pyoxidizer_context_value = get_context(type_values)?;
+    let pyoxidizer_context = pyoxidizer_context_value
+        .downcast_ref::<PyOxidizerEnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+    let cwd = &pyoxidizer_context.cwd;
+
+    let error = |message: String| {
+        ValueError::from(RuntimeError {
+            code: "PYOXIDIZER_PATH",
+            message,
+            label: LABEL.to_string(),
+        })
+    };
+    let resolve = |path: &Path| {
+        pyoxidizer_context
+            .resolve_path(path)
+            .map_err(|e| error(format!("unable to resolve {}: {}", path.display(), e)))
+    };
+
+    // Matching is shared with glob(). Only the handling of matches differs.
+    let mut paths = BTreeSet::new();
+
+    for path in evaluate_globs(cwd, &include, &exclude).map_err(|e| error(format!("{:?}", e)))? {
+        let path = resolve(&path)?;
+
+        if !allow_outside_cwd && !path.starts_with(cwd) {
+            return Err(error(format!(
+                "{} is outside {}; pass allow_outside_cwd=True to allow this",
+                path.display(),
+                cwd.display()
+            )));
+        }
+
+        paths.insert(path);
+    }
+
+    let strip_prefix = strip_prefix
+        .map(|prefix| resolve(Path::new(&prefix)))
+        .transpose()?;
+
+    let values = paths
+        .into_iter()
+        .map(|path| {
+            let path = match &strip_prefix {
+                Some(prefix) => path
+                    .strip_prefix(prefix)
+                    .map_err(|_| {
+                        error(format!(
+                            "{} does not begin with {}",
+                            path.display(),
+                            prefix.display()
+                        ))
+                    })?
+                    .to_path_buf(),
+                None => path,
+            };
+
+            Ok(Value::from(path.display().to_string()))
+        })
+        .collect::<Result<Vec<_>, ValueError>>()?;
+
+    Ok(Value::from(values))
+}
+
+starlark_module! { path_module =>
+    config_relative_path(env env, path: String, must_exist: bool = false) {
+        starlark_config_relative_path(env, path, must_exist)
+    }
+
+    glob_paths(
+        env env,
+        include,
+        exclude=NoneType::None,
+        strip_prefix=NoneType::None,
+        allow_outside_cwd: bool = false
+    ) {
+        starlark_glob_paths(env, &include, &exclude, &strip_prefix, allow_outside_cwd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::super::testutil::*, super::*, anyhow::Result};
+
+    #[test]
+    fn test_resolve_relative_path() -> Result<()> {
+        let base = std::env::current_dir()?;
+
+        assert_eq!(
+            resolve_relative_path(&base, Path::new("assets/icon.png"))?,
+            base.join("assets").join("icon.png")
+        );
+        assert_eq!(
+            resolve_relative_path(&base, Path::new("./assets/../other/./file"))?,
+            base.join("other").join("file")
+        );
+        assert_eq!(
+            resolve_relative_path(&base, Path::new("../sibling"))?,
+            base.parent().unwrap().join("sibling")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_relative_path() -> Result<()> {
+        let cwd = std::env::current_dir()?;
+
+        let value = starlark_ok("config_relative_path('assets/../icons/app.ico')");
+        assert_eq!(
+            value.to_str(),
+            cwd.join("icons").join("app.ico").display().to_string()
+        );
+
+        let value = starlark_ok("config_relative_path('.', must_exist = True)");
+        assert_eq!(value.to_str(), cwd.display().to_string());
+
+        let err = starlark_nok("config_relative_path('does-not-exist', must_exist = True)");
+        assert!(err.message.contains("does not exist"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_paths() -> Result<()> {
+        let env = crate::testutil::get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+        let project = temp_dir.path().join("project");
+
+        std::fs::create_dir_all(project.join("sub").join("deep"))?;
+        std::fs::write(project.join("a.txt"), "a")?;
+        std::fs::write(project.join("sub").join("b.txt"), "b")?;
+        std::fs::write(project.join("sub").join("deep").join("c.py"), "c")?;
+        std::fs::write(temp_dir.path().join("outside.txt"), "outside")?;
+
+        let mut eval = test_evaluation_context_builder()?
+            .config_path(project.join("pyoxidizer.bzl"))
+            .into_context()?;
+
+        let strings = |value: Value| {
+            value
+                .iter()
+                .unwrap()
+                .to_vec()
+                .iter()
+                .map(|v| v.to_str())
+                .collect::<Vec<_>>()
+        };
+
+        // Recursive matching.
+        assert_eq!(
+            strings(eval.eval("glob_paths(['**/*.txt'])")?),
+            vec![
+                project.join("a.txt").display().to_string(),
+                project.join("sub").join("b.txt").display().to_string(),
+            ]
+        );
+
+        // Exclusion and prefix stripping.
+        assert_eq!(
+            strings(eval.eval("glob_paths(['**/*'], exclude=['**/*.txt'], strip_prefix='sub')")?),
+            vec![Path::new("deep").join("c.py").display().to_string()]
+        );
+        assert_eq!(
+            strings(eval.eval("glob_paths(['sub/*'], strip_prefix='.')")?),
+            vec![Path::new("sub").join("b.txt").display().to_string()]
+        );
+        assert!(eval
+            .eval("glob_paths(['a.txt'], strip_prefix='sub')")
+            .is_err());
+
+        // Matches outside the config directory require opting in.
+        let err = eval.eval("glob_paths(['../*.txt'])").unwrap_err();
+        assert!(err.to_string().contains("allow_outside_cwd"));
+        assert_eq!(
+            strings(eval.eval("glob_paths(['../*.txt'], allow_outside_cwd=True)")?),
+            vec![temp_dir.path().join("outside.txt").display().to_string()]
+        );
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_path() -> Result<()> {
+        let cwd = std::env::current_dir()?;
+        let absolute = std::env::temp_dir().join("other");
+
+        let context = test_evaluation_context_builder()?.into_context()?;
+        let value = context.pyoxidizer_context_value().unwrap();
+        let context = value
+            .downcast_ref::<PyOxidizerEnvironmentContext>()
+            .unwrap();
+        assert_eq!(
+            context.resolve_path("sub/./dir/../file")?,
+            cwd.join("sub").join("file")
+        );
+        assert_eq!(
+            context.resolve_path(absolute.join("..").join("other"))?,
+            absolute
+        );
+
+        Ok(())
+    }
+}
@@ -4,17 +4,22 @@
 
 use {
     super::{
-        env::{get_context, PyOxidizerEnvironmentContext},
+        env::{get_context, PyOxidizerEnvironmentContext, TIMEOUT_ERROR_CODE},
         python_executable::PythonExecutableValue,
         python_interpreter_config::PythonInterpreterConfigValue,
         python_packaging_policy::PythonPackagingPolicyValue,
         python_resource::{add_context_for_value, python_resource_to_value},
     },
-    crate::py_packaging::{
-        distribution::BinaryLibpythonLinkMode,
-        distribution::{
-            default_distribution_location, DistributionFlavor, PythonDistribution,
-            PythonDistributionLocation,
+    crate::{
+        events::{duration_ms, BuildEvent},
+        py_packaging::{
+            config::PyembedPythonInterpreterConfig,
+            distribution::BinaryLibpythonLinkMode,
+            distribution::{
+                default_distribution_location, ensure_distribution_available_offline,
+                DistributionFlavor, PythonDistribution, PythonDistributionLocation,
+            },
+            placeholder_builder::PlaceholderPythonExecutableBuilder,
         },
     },
     anyhow::{anyhow, Result},
@@ -27,7 +32,9 @@ use {
         environment::TypeValues,
         eval::call_stack::CallStack,
         values::{
-            error::{RuntimeError, ValueError, INCORRECT_PARAMETER_TYPE_ERROR_CODE},
+            error::{
+                RuntimeError, UnsupportedOperation, ValueError, INCORRECT_PARAMETER_TYPE_ERROR_CODE,
+            },
             none::NoneType,
             {Mutable, TypedValue, Value, ValueResult},
         },
@@ -37,25 +44,50 @@ use {
         },
     },
     starlark_dialect_build_targets::{optional_str_arg, optional_type_arg},
-    std::{ops::Deref, sync::Arc},
+    std::{ops::Deref, sync::Arc, time::Instant},
 };
 
+/// Error code for operations that need a distribution unavailable in dry run mode.
+const DRY_RUN_ERROR_CODE: &str = "PYOXIDIZER_DRY_RUN";
+
 /// A Starlark Value wrapper for `PythonDistribution` traits.
 pub struct PythonDistributionValue {
     /// Where the distribution should be obtained from.
     pub source: PythonDistributionLocation,
 
+    /// Upstream release tag the distribution is labeled with.
+    ///
+    /// Only used to label in-memory cache entries. The tag isn't checked
+    /// against the archive, which is identified by its SHA-256 alone.
+    pub build_tag: Option<String>,
+
+    /// Flavor of the distribution, as requested by the config.
+    pub flavor: String,
+
     /// The actual distribution.
     ///
     /// Populated on first read.
     pub distribution: Option<Arc<dyn PythonDistribution>>,
+
+    /// The `PyOxidizerEnvironmentContext` the value was created in.
+    ///
+    /// Attribute access doesn't have access to the environment, so this is
+    /// retained to resolve the distribution when metadata is read.
+    context: Value,
 }
 
 impl PythonDistributionValue {
-    fn from_location(location: PythonDistributionLocation) -> PythonDistributionValue {
+    fn from_location(
+        location: PythonDistributionLocation,
+        flavor: String,
+        context: Value,
+    ) -> PythonDistributionValue {
         PythonDistributionValue {
             source: location,
+            build_tag: None,
+            flavor,
             distribution: None,
+            context,
         }
     }
 
@@ -66,29 +98,114 @@ impl PythonDistributionValue {
     ) -> Result<Arc<dyn PythonDistribution>, ValueError> {
         if self.distribution.is_none() {
             let pyoxidizer_context_value = get_context(type_values)?;
-            let pyoxidizer_context = pyoxidizer_context_value
-                .downcast_mut::<PyOxidizerEnvironmentContext>()?
-                .ok_or(ValueError::IncorrectParameterType)?;
-
-            let dest_dir = pyoxidizer_context.python_distributions_path()?;
-
-            self.distribution = Some(
-                pyoxidizer_context
-                    .distribution_cache
-                    .resolve_distribution(&self.source, Some(&dest_dir))
-                    .map_err(|e| {
-                        ValueError::from(RuntimeError {
-                            code: "PYOXIDIZER_BUILD",
-                            message: format!("{:?}", e),
-                            label: label.to_string(),
-                        })
-                    })?
-                    .clone_trait(),
-            );
+            self.distribution = Some(self.resolve_in_context(&pyoxidizer_context_value, label)?);
         }
 
         Ok(self.distribution.as_ref().unwrap().clone())
     }
+
+    /// Resolve the distribution without caching it on this instance.
+    ///
+    /// The distribution cache retains resolved distributions, so repeated
+    /// calls are cheap.
+    fn resolve_in_context(
+        &self,
+        pyoxidizer_context_value: &Value,
+        label: &str,
+    ) -> Result<Arc<dyn PythonDistribution>, ValueError> {
+        let pyoxidizer_context = pyoxidizer_context_value
+            .downcast_mut::<PyOxidizerEnvironmentContext>()?
+            .ok_or(ValueError::IncorrectParameterType)?;
+
+        let check_timeout = |context: &PyOxidizerEnvironmentContext| {
+            context.check_timeout().map_err(|e| {
+                ValueError::from(RuntimeError {
+                    code: TIMEOUT_ERROR_CODE,
+                    message: e.to_string(),
+                    label: label.to_string(),
+                })
+            })
+        };
+
+        check_timeout(&*pyoxidizer_context)?;
+
+        let dest_dir = pyoxidizer_context.python_distributions_path()?;
+
+        // Dry run mode never downloads. Distributions already available
+        // locally are still resolved so configs can be validated against them.
+        if pyoxidizer_context.dry_run {
+            ensure_distribution_available_offline(&self.source, &dest_dir).map_err(|e| {
+                ValueError::from(RuntimeError {
+                    code: DRY_RUN_ERROR_CODE,
+                    message: format!(
+                        "Python distribution is not available locally and dry run mode does not download it: {:?}",
+                        e
+                    ),
+                    label: label.to_string(),
+                })
+            })?;
+        }
+
+        let start = Instant::now();
+
+        let distribution = pyoxidizer_context
+            .distribution_cache
+            .resolve_distribution_with_build_tag(
+                &self.source,
+                Some(&dest_dir),
+                self.build_tag.as_deref(),
+            )
+            .map_err(|e| {
+                ValueError::from(RuntimeError {
+                    code: "PYOXIDIZER_BUILD",
+                    message: format!("{:?}", e),
+                    label: label.to_string(),
+                })
+            })?
+            .clone_trait();
+
+        pyoxidizer_context.emit_event(BuildEvent::DistributionResolved {
+            location: match &self.source {
+                PythonDistributionLocation::Local { local_path, .. } => local_path.clone(),
+                PythonDistributionLocation::Url { url, .. } => url.clone(),
+            },
+            sha256: self.source.sha256().to_string(),
+            duration_ms: duration_ms(start.elapsed()),
+        });
+
+        // Obtaining a distribution can take a while. Don't let evaluation
+        // continue past the deadline.
+        check_timeout(&*pyoxidizer_context)?;
+
+        Ok(distribution)
+    }
+
+    /// Resolve the distribution, or `None` if it is unavailable in dry run mode.
+    ///
+    /// Callers substitute placeholder values when `None` is returned.
+    fn resolve_distribution_or_stub(
+        &mut self,
+        type_values: &TypeValues,
+        label: &str,
+    ) -> Result<Option<Arc<dyn PythonDistribution>>, ValueError> {
+        match self.resolve_distribution(type_values, label) {
+            Ok(dist) => Ok(Some(dist)),
+            Err(ValueError::Runtime(e)) if e.code == DRY_RUN_ERROR_CODE => {
+                let message = format!("{}: using placeholder value: {}", label, e.message);
+                warn!("{}", message);
+
+                // Record the warning so callers validating configs can report it.
+                let pyoxidizer_context_value = get_context(type_values)?;
+                let mut pyoxidizer_context = pyoxidizer_context_value
+                    .downcast_mut::<PyOxidizerEnvironmentContext>()?
+                    .ok_or(ValueError::IncorrectParameterType)?;
+                pyoxidizer_context.push_warning(message);
+
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
 }
 
 impl TypedValue for PythonDistributionValue {
@@ -102,6 +219,47 @@ impl TypedValue for PythonDistributionValue {
     fn to_str(&self) -> String {
         format!("PythonDistribution<{:#?}>", self.source)
     }
+
+    fn get_attr(&self, attribute: &str) -> ValueResult {
+        if attribute == "flavor" {
+            return Ok(Value::from(self.flavor.clone()));
+        }
+
+        if !self.has_attr(attribute)? {
+            return Err(ValueError::OperationNotSupported {
+                op: UnsupportedOperation::GetAttr(attribute.to_string()),
+                left: Self::TYPE.to_string(),
+                right: None,
+            });
+        }
+
+        // Metadata comes from the distribution's PYTHON.json, so reading it
+        // requires resolving the distribution.
+        let dist = match &self.distribution {
+            Some(dist) => dist.clone(),
+            None => self
+                .resolve_in_context(&self.context, &format!("PythonDistribution.{}", attribute))?,
+        };
+
+        Ok(Value::from(match attribute {
+            "python_implementation" => dist.python_implementation().to_string(),
+            "python_major_minor_version" => dist.python_major_minor_version(),
+            "python_version" => dist.python_version().to_string(),
+            "target_triple" => dist.target_triple().to_string(),
+            _ => unreachable!(),
+        }))
+    }
+
+    fn has_attr(&self, attribute: &str) -> Result<bool, ValueError> {
+        Ok(matches!(
+            attribute,
+            "flavor"
+                | "python_implementation"
+                | "python_major_minor_version"
+                | "python_version"
+                | "target_triple"
+        ))
+    }
 }
 
 // Starlark functions.
@@ -126,7 +284,7 @@ impl PythonDistributionValue {
             None => pyoxidizer_context.build_target_triple.clone(),
         };
 
-        let flavor = DistributionFlavor::try_from(flavor.as_str()).map_err(|e| {
+        let distribution_flavor = DistributionFlavor::try_from(flavor.as_str()).map_err(|e| {
             ValueError::from(RuntimeError {
                 code: "PYOXIDIZER_BUILD",
                 message: e,
@@ -136,27 +294,40 @@ impl PythonDistributionValue {
 
         let python_version_str = python_version.as_deref();
 
-        let location = default_distribution_location(&flavor, &build_target, python_version_str)
-            .map_err(|e| {
-                ValueError::from(RuntimeError {
-                    code: "PYOXIDIZER_BUILD",
-                    message: format!("{:?}", e),
-                    label: "default_python_distribution()".to_string(),
-                })
-            })?;
+        let location =
+            default_distribution_location(&distribution_flavor, &build_target, python_version_str)
+                .map_err(|e| {
+                    ValueError::from(RuntimeError {
+                        code: "PYOXIDIZER_BUILD",
+                        message: format!("{:?}", e),
+                        label: "default_python_distribution()".to_string(),
+                    })
+                })?;
 
         warn!(
             "target Python distribution for {} resolves to: {}",
             build_target, location
         );
 
-        Ok(Value::new(PythonDistributionValue::from_location(location)))
+        Ok(Value::new(PythonDistributionValue::from_location(
+            location,
+            flavor,
+            pyoxidizer_context_value.clone(),
+        )))
     }
 
     /// PythonDistribution()
-    fn from_args(sha256: String, local_path: &Value, url: &Value, flavor: String) -> ValueResult {
-        optional_str_arg("local_path", local_path)?;
+    fn from_args(
+        type_values: &TypeValues,
+        sha256: String,
+        local_path: &Value,
+        url: &Value,
+        flavor: String,
+        build_tag: &Value,
+    ) -> ValueResult {
+        let local_path_str = optional_str_arg("local_path", local_path)?;
         optional_str_arg("url", url)?;
+        let build_tag = optional_str_arg("build_tag", build_tag)?;
 
         if local_path.get_type() != "NoneType" && url.get_type() != "NoneType" {
             return Err(ValueError::from(RuntimeError {
@@ -166,11 +337,27 @@ impl PythonDistributionValue {
             }));
         }
 
-        let distribution = if local_path.get_type() != "NoneType" {
-            PythonDistributionLocation::Local {
-                local_path: local_path.to_string(),
-                sha256,
-            }
+        let pyoxidizer_context_value = get_context(type_values)?;
+
+        let distribution = if let Some(local_path) = local_path_str {
+            let pyoxidizer_context = pyoxidizer_context_value
+                .downcast_ref::<PyOxidizerEnvironmentContext>()
+                .ok_or(ValueError::IncorrectParameterType)?;
+
+            // Relative paths are relative to the config file, not the process.
+            let local_path = pyoxidizer_context
+                .resolve_path(local_path)
+                .map_err(|e| {
+                    ValueError::from(RuntimeError {
+                        code: "PYOXIDIZER_BUILD",
+                        message: format!("unable to normalize local_path: {}", e),
+                        label: "PythonDistribution()".to_string(),
+                    })
+                })?
+                .display()
+                .to_string();
+
+            PythonDistributionLocation::Local { local_path, sha256 }
         } else {
             PythonDistributionLocation::Url {
                 url: url.to_string(),
@@ -189,14 +376,23 @@ impl PythonDistributionValue {
             }
         }
 
-        Ok(Value::new(PythonDistributionValue::from_location(
-            distribution,
-        )))
+        let mut value =
+            PythonDistributionValue::from_location(distribution, flavor, pyoxidizer_context_value);
+        value.build_tag = build_tag;
+
+        Ok(Value::new(value))
     }
 
     /// PythonDistribution.make_python_packaging_policy()
     fn make_python_packaging_policy_starlark(&mut self, type_values: &TypeValues) -> ValueResult {
-        let dist = self.resolve_distribution(type_values, "resolve_distribution")?;
+        let dist = match self.resolve_distribution_or_stub(type_values, "resolve_distribution")? {
+            Some(dist) => dist,
+            None => {
+                return Ok(Value::new(PythonPackagingPolicyValue::new(
+                    PythonPackagingPolicy::default(),
+                )))
+            }
+        };
 
         let policy = dist.create_packaging_policy().map_err(|e| {
             ValueError::from(RuntimeError {
@@ -211,7 +407,14 @@ impl PythonDistributionValue {
 
     /// PythonDistribution.make_python_interpreter_config()
     fn make_python_interpreter_config_starlark(&mut self, type_values: &TypeValues) -> ValueResult {
-        let dist = self.resolve_distribution(type_values, "resolve_distribution()")?;
+        let dist = match self.resolve_distribution_or_stub(type_values, "resolve_distribution()")? {
+            Some(dist) => dist,
+            None => {
+                return Ok(Value::new(PythonInterpreterConfigValue::new(
+                    PyembedPythonInterpreterConfig::default(),
+                )))
+            }
+        };
 
         let config = dist.create_python_interpreter_config().map_err(|e| {
             ValueError::from(RuntimeError {
@@ -247,7 +450,31 @@ impl PythonDistributionValue {
         )?;
         optional_type_arg("config", "PythonInterpreterConfig", config)?;
 
-        let dist = self.resolve_distribution(type_values, "resolve_distribution()")?;
+        let dist = match self.resolve_distribution_or_stub(type_values, "resolve_distribution()")? {
+            Some(dist) => dist,
+            None => {
+                let policy = match packaging_policy.downcast_ref::<PythonPackagingPolicyValue>() {
+                    Some(policy) => policy.clone(),
+                    None => PythonPackagingPolicyValue::new(PythonPackagingPolicy::default()),
+                };
+
+                let pyoxidizer_context_value = get_context(type_values)?;
+                let pyoxidizer_context = pyoxidizer_context_value
+                    .downcast_ref::<PyOxidizerEnvironmentContext>()
+                    .ok_or(ValueError::IncorrectParameterType)?;
+
+                let builder = PlaceholderPythonExecutableBuilder::new(
+                    &name,
+                    &pyoxidizer_context.build_target_triple,
+                    policy.inner(LABEL)?.clone(),
+                );
+
+                return Ok(Value::new(PythonExecutableValue::new(
+                    Box::new(builder),
+                    policy,
+                )));
+            }
+        };
 
         let policy = if packaging_policy.get_type() == "NoneType" {
             Ok(PythonPackagingPolicyValue::new(
@@ -386,6 +613,33 @@ impl PythonDistributionValue {
         Ok(Value::new(PythonExecutableValue::new(builder, policy)))
     }
 
+    /// PythonDistribution.is_cached()
+    fn is_cached_starlark(&self, type_values: &TypeValues) -> ValueResult {
+        if self.distribution.is_some() {
+            return Ok(Value::from(true));
+        }
+
+        let pyoxidizer_context_value = get_context(type_values)?;
+        let pyoxidizer_context = pyoxidizer_context_value
+            .downcast_ref::<PyOxidizerEnvironmentContext>()
+            .ok_or(ValueError::IncorrectParameterType)?;
+
+        let dest_dir = pyoxidizer_context.python_distributions_path()?;
+
+        let cached = pyoxidizer_context
+            .distribution_cache
+            .is_cached(&self.source, Some(&dest_dir), self.build_tag.as_deref())
+            .map_err(|e| {
+                ValueError::from(RuntimeError {
+                    code: "PYTHON_DISTRIBUTION",
+                    message: format!("{:?}", e),
+                    label: "PythonDistribution.is_cached()".to_string(),
+                })
+            })?;
+
+        Ok(Value::from(cached))
+    }
+
     pub fn python_resources_starlark(
         &mut self,
         type_values: &TypeValues,
@@ -393,7 +647,10 @@ impl PythonDistributionValue {
     ) -> ValueResult {
         const LABEL: &str = "PythonDistribution.python_resources()";
 
-        let dist = self.resolve_distribution(type_values, "resolve_distribution")?;
+        let dist = match self.resolve_distribution_or_stub(type_values, "resolve_distribution")? {
+            Some(dist) => dist,
+            None => return Ok(Value::from(Vec::<Value>::new())),
+        };
         let policy =
             PythonPackagingPolicyValue::new(dist.create_packaging_policy().map_err(|e| {
                 ValueError::from(RuntimeError {
@@ -417,8 +674,13 @@ impl PythonDistributionValue {
 
 starlark_module! { python_distribution_module =>
     #[allow(non_snake_case)]
-    PythonDistribution(sha256: String, local_path=NoneType::None, url=NoneType::None, flavor: String = "standalone".to_string()) {
-        PythonDistributionValue::from_args(sha256, &local_path, &url, flavor)
+    PythonDistribution(env env, sha256: String, local_path=NoneType::None, url=NoneType::None, flavor: String = "standalone".to_string(), build_tag=NoneType::None) {
+        PythonDistributionValue::from_args(env, sha256, &local_path, &url, flavor, &build_tag)
+    }
+
+    PythonDistribution.is_cached(env env, this) {
+        let this = this.downcast_ref::<PythonDistributionValue>().unwrap();
+        this.is_cached_starlark(env)
     }
 
     PythonDistribution.make_python_packaging_policy(env env, this) {
@@ -606,11 +868,38 @@ mod tests {
         assert_eq!(x.source, wanted);
     }
 
+    #[test]
+    fn test_python_distribution_build_tag() {
+        let dist = starlark_ok("PythonDistribution('sha256', url='some_url')");
+        let x = dist.downcast_ref::<PythonDistributionValue>().unwrap();
+        assert_eq!(x.build_tag, None);
+
+        let dist =
+            starlark_ok("PythonDistribution('sha256', url='some_url', build_tag='20211017')");
+        let x = dist.downcast_ref::<PythonDistributionValue>().unwrap();
+        assert_eq!(x.build_tag, Some("20211017".to_string()));
+    }
+
     #[test]
     fn test_python_distribution_local_path() {
+        let cwd = std::env::current_dir().unwrap();
+
         let dist = starlark_ok("PythonDistribution('sha256', local_path='some_path')");
         let wanted = PythonDistributionLocation::Local {
-            local_path: "some_path".to_string(),
+            local_path: cwd.join("some_path").display().to_string(),
+            sha256: "sha256".to_string(),
+        };
+
+        let x = dist.downcast_ref::<PythonDistributionValue>().unwrap();
+        assert_eq!(x.source, wanted);
+
+        let absolute = cwd.join("dists").join("python.tar.zst");
+        let dist = starlark_ok(&format!(
+            "PythonDistribution('sha256', local_path='{}')",
+            absolute.display().to_string().escape_default()
+        ));
+        let wanted = PythonDistributionLocation::Local {
+            local_path: absolute.display().to_string(),
             sha256: "sha256".to_string(),
         };
 
@@ -618,6 +907,39 @@ mod tests {
         assert_eq!(x.source, wanted);
     }
 
+    #[test]
+    fn test_python_distribution_metadata() -> Result<()> {
+        let mut env = test_evaluation_context_builder()?.into_context()?;
+
+        env.eval("dist = default_python_distribution(python_version='3.10')")?;
+
+        assert_eq!(env.eval("dist.flavor")?.to_str(), "standalone");
+        assert_eq!(
+            env.eval("dist.python_major_minor_version")?.to_str(),
+            "3.10"
+        );
+        assert!(env
+            .eval("dist.python_version")?
+            .to_str()
+            .starts_with("3.10."));
+        assert_eq!(env.eval("dist.python_implementation")?.to_str(), "cpython");
+        assert_eq!(
+            env.eval("dist.target_triple")?.to_str(),
+            default_target_triple()
+        );
+
+        let version = env
+            .eval("default_python_distribution().python_major_minor_version")?
+            .to_str();
+        let parts = version.split('.').collect::<Vec<_>>();
+        assert_eq!(parts.len(), 2);
+        assert!(parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())));
+
+        Ok(())
+    }
+
     #[test]
     fn test_make_python_packaging_policy() {
         let policy = starlark_ok("default_python_distribution().make_python_packaging_policy()");
@@ -662,4 +984,63 @@ mod tests {
             .filter(|v| v.get_type() == PythonPackageResourceValue::TYPE)
             .all(|v| v.get_attr("is_stdlib").unwrap().to_bool()));
     }
+
+    #[test]
+    fn test_is_cached() -> Result<()> {
+        let env = crate::testutil::get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval(&format!(
+            "set_python_distributions_path('{}')",
+            temp_dir.path().display().to_string().escape_default()
+        ))?;
+        eval.eval("dist = default_python_distribution()")?;
+        assert!(!eval.eval("dist.is_cached()")?.to_bool());
+
+        let mut eval = test_evaluation_context_builder()?.into_context()?;
+        eval.eval("dist = default_python_distribution()")?;
+        eval.eval("dist.python_resources()")?;
+        assert!(eval.eval("dist.is_cached()")?.to_bool());
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run() -> Result<()> {
+        let env = crate::testutil::get_env()?;
+        let temp_dir = env.temporary_directory("pyoxidizer-test")?;
+        let dists_path = temp_dir.path().join("dists");
+
+        let mut eval = test_evaluation_context_builder()?
+            .dry_run(true)
+            .into_context()?;
+        eval.eval(&format!(
+            "set_python_distributions_path('{}')",
+            dists_path.display().to_string().escape_default()
+        ))?;
+        eval.eval("dist = default_python_distribution()")?;
+
+        let policy = eval.eval("dist.make_python_packaging_policy()")?;
+        assert_eq!(policy.get_type(), "PythonPackagingPolicy");
+        let config = eval.eval("dist.make_python_interpreter_config()")?;
+        assert_eq!(config.get_type(), "PythonInterpreterConfig");
+        let resources = eval.eval("dist.python_resources()")?;
+        assert!(resources.iter().unwrap().to_vec().is_empty());
+
+        let exe = eval.eval("dist.to_python_executable('myapp')")?;
+        assert_eq!(exe.get_type(), "PythonExecutable");
+        eval.eval("exe = dist.to_python_executable('myapp', packaging_policy = dist.make_python_packaging_policy())")?;
+        eval.eval("exe.add_python_resources(exe.pip_install(['foo']))")?;
+        assert_eq!(eval.eval("exe.windows_subsystem")?.to_string(), "console");
+
+        // Nothing was downloaded.
+        assert!(!dists_path.exists() || std::fs::read_dir(&dists_path)?.next().is_none());
+
+        temp_dir.close()?;
+
+        Ok(())
+    }
 }
@@ -5,7 +5,10 @@
 use {
     crate::{
         environment::default_target_triple,
-        starlark::eval::{EvaluationContext, EvaluationContextBuilder},
+        starlark::{
+            env::EnvironmentTemplate,
+            eval::{EvaluationContext, EvaluationContextBuilder},
+        },
         testutil::{get_env, DISTRIBUTION_CACHE},
     },
     anyhow::{anyhow, Result},
@@ -14,6 +17,14 @@ use {
     starlark::values::Value,
 };
 
+thread_local! {
+    /// Starlark environment template shared by tests on the same thread.
+    ///
+    /// Starlark values aren't `Send`. So this can't be shared across threads.
+    static ENVIRONMENT_TEMPLATE: EnvironmentTemplate =
+        EnvironmentTemplate::new().expect("unable to construct Starlark environment template");
+}
+
 /// Construct a new `EvaluationContextBuilder` suitable for the test environment.
 pub fn test_evaluation_context_builder() -> Result<EvaluationContextBuilder> {
     let env = get_env()?;
@@ -21,7 +32,8 @@ pub fn test_evaluation_context_builder() -> Result<EvaluationContextBuilder> {
     let config_path = cwd.join("dummy");
 
     let builder = EvaluationContextBuilder::new(&env, config_path, default_target_triple())
-        .distribution_cache(DISTRIBUTION_CACHE.clone());
+        .distribution_cache(DISTRIBUTION_CACHE.clone())
+        .environment_template(ENVIRONMENT_TEMPLATE.with(|template| template.clone()));
 
     Ok(builder)
 }
@@ -5,9 +5,9 @@
 pub mod testutil;
 
 use {
-    anyhow::{anyhow, Result},
+    anyhow::{anyhow, Context, Result},
     linked_hash_map::LinkedHashMap,
-    log::warn,
+    log::{log, warn, Level},
     path_dedot::ParseDot,
     starlark::{
         environment::{Environment, EnvironmentError, TypeValues},
@@ -27,9 +27,15 @@ use {
         collections::{BTreeMap, HashMap},
         os::raw::c_ulong,
         path::{Path, PathBuf},
+        sync::{Arc, Mutex},
     },
 };
 
+/// A buffer receiving messages passed to `print()`.
+///
+/// Shared so embedders can retrieve output after evaluation.
+pub type PrintCapture = Arc<Mutex<Vec<String>>>;
+
 /// How a resolved target can be run.
 #[derive(Debug, Clone, PartialEq)]
 pub enum RunMode {
@@ -114,6 +120,11 @@ pub struct EnvironmentContext {
     /// Default output directory.
     build_path: PathBuf,
 
+    /// Build path the context was constructed with.
+    ///
+    /// Used by [Self::reset_build_path].
+    default_build_path: PathBuf,
+
     /// Optional path prefix to insert between the build path and the target name.
     target_build_path_prefix: Option<PathBuf>,
 
@@ -131,6 +142,9 @@ pub struct EnvironmentContext {
     /// List of targets to resolve.
     resolve_targets: Option<Vec<String>>,
 
+    /// Targets whose functions are currently being called, innermost last.
+    resolving_targets: Vec<String>,
+
     // TODO figure out a generic way to express build script mode.
     /// Name of default target to resolve in build script mode.
     pub default_build_script_target: Option<String>,
@@ -139,6 +153,24 @@ pub struct EnvironmentContext {
     ///
     /// This will change the default target to resolve.
     pub build_script_mode: bool,
+
+    /// Whether we are operating in verbose mode.
+    ///
+    /// `print()` output is only emitted at info level in verbose mode.
+    pub verbose: bool,
+
+    /// Whether the build path must be a descendant of the current working directory.
+    ///
+    /// When set, [Self::set_build_path] rejects paths outside `cwd`.
+    pub confine_build_path: bool,
+
+    /// Whether functions that access the filesystem are disabled.
+    ///
+    /// Used when evaluating untrusted configuration files. See [check_sandbox].
+    pub sandbox: bool,
+
+    /// Receives `print()` messages in addition to them being logged.
+    pub print_capture: Option<PrintCapture>,
 }
 
 impl EnvironmentContext {
@@ -147,14 +179,20 @@ impl EnvironmentContext {
 
         Self {
             cwd,
-            build_path,
+            build_path: build_path.clone(),
+            default_build_path: build_path,
             target_build_path_prefix: None,
             targets: BTreeMap::new(),
             targets_order: vec![],
             default_target: None,
             resolve_targets: None,
+            resolving_targets: vec![],
             default_build_script_target: None,
             build_script_mode: false,
+            verbose: false,
+            confine_build_path: false,
+            sandbox: false,
+            print_capture: None,
         }
     }
 
@@ -178,11 +216,50 @@ impl EnvironmentContext {
         .parse_dot()?
         .to_path_buf();
 
+        if self.confine_build_path && !path.starts_with(self.cwd.parse_dot()?) {
+            return Err(anyhow!(
+                "build path {} is outside of {}",
+                path.display(),
+                self.cwd.display()
+            ));
+        }
+
         self.build_path = path;
 
         Ok(())
     }
 
+    /// Create the build path directory and any missing parents.
+    pub fn create_build_path(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.build_path)
+            .with_context(|| format!("creating build path {}", self.build_path.display()))
+    }
+
+    /// Restore the build path the context was constructed with.
+    pub fn reset_build_path(&mut self) {
+        self.build_path = self.default_build_path.clone();
+    }
+
+    /// Describe the build path being on a different filesystem than the cwd.
+    ///
+    /// Returns `None` if both are on the same filesystem or if this can't be
+    /// determined. Files can't be hard linked across filesystems, so builds
+    /// may need to copy files instead.
+    pub fn build_path_filesystem_warning(&self) -> Option<String> {
+        let build_fs = filesystem_id(&self.build_path)?;
+        let cwd_fs = filesystem_id(&self.cwd)?;
+
+        if build_fs == cwd_fs {
+            None
+        } else {
+            Some(format!(
+                "build path {} is on a different filesystem than {}; files may be copied instead of linked, slowing builds",
+                self.build_path.display(),
+                self.cwd.display()
+            ))
+        }
+    }
+
     /// Resolve an absolute filesystem path from a path input.
     ///
     /// If the incoming path is absolute, it is returned. Otherwise relative
@@ -214,6 +291,30 @@ impl EnvironmentContext {
         }
     }
 
+    /// Obtain the primary output path of a resolved target.
+    ///
+    /// If the target has been built, this is the output path its build
+    /// reported. Otherwise it is the directory the target will be built in.
+    /// Errors if the target does not exist or has not been resolved.
+    pub fn resolved_target_path(&self, target: &str) -> Result<PathBuf> {
+        let entry = self
+            .get_target(target)
+            .ok_or_else(|| anyhow!("target {} does not exist", target))?;
+
+        if let Some(built) = &entry.built_target {
+            Ok(built.output_path.clone())
+        } else if entry.resolved_value.is_some() {
+            Ok(self.target_build_path(target))
+        } else {
+            Err(anyhow!("target {} has not been resolved", target))
+        }
+    }
+
+    /// Obtain the name of the target whose function is currently being called.
+    pub fn current_target(&self) -> Option<&str> {
+        self.resolving_targets.last().map(|x| x.as_str())
+    }
+
     /// Obtain all registered targets.
     pub fn targets(&self) -> &BTreeMap<String, Target> {
         &self.targets
@@ -239,6 +340,13 @@ impl EnvironmentContext {
         self.resolve_targets = Some(targets);
     }
 
+    /// Obtain the explicit list of targets to resolve, if set.
+    ///
+    /// Targets are in the order they were passed to [Self::set_resolve_targets].
+    pub fn resolve_targets(&self) -> Option<&Vec<String>> {
+        self.resolve_targets.as_ref()
+    }
+
     /// Obtain the order that targets were registered in.
     pub fn targets_order(&self) -> &Vec<String> {
         &self.targets_order
@@ -276,6 +384,56 @@ impl EnvironmentContext {
         }
     }
 
+    /// Order targets so each comes after the targets it depends on.
+    ///
+    /// The returned list contains `targets` and their transitive dependencies.
+    /// Otherwise, targets are kept in the order given. An error naming the
+    /// members of the cycle is returned if dependencies are cyclic.
+    pub fn resolution_order(&self, targets: &[String]) -> Result<Vec<String>> {
+        fn visit(
+            context: &EnvironmentContext,
+            target: &str,
+            stack: &mut Vec<String>,
+            order: &mut Vec<String>,
+        ) -> Result<()> {
+            if order.iter().any(|t| t == target) {
+                return Ok(());
+            }
+
+            if let Some(index) = stack.iter().position(|t| t == target) {
+                let mut cycle = stack[index..].to_vec();
+                cycle.push(target.to_string());
+
+                return Err(anyhow!(
+                    "targets have a dependency cycle: {}",
+                    cycle.join(" -> ")
+                ));
+            }
+
+            let entry = context
+                .get_target(target)
+                .ok_or_else(|| anyhow!("target {} does not exist", target))?;
+
+            stack.push(target.to_string());
+            for depend in &entry.depends {
+                visit(context, depend, stack, order)?;
+            }
+            stack.pop();
+
+            order.push(target.to_string());
+
+            Ok(())
+        }
+
+        let mut order = vec![];
+
+        for target in targets {
+            visit(self, target, &mut vec![], &mut order)?;
+        }
+
+        Ok(order)
+    }
+
     /// Determine what targets should be resolved.
     ///
     /// This isn't the full list of targets that will be resolved, only the main
@@ -724,6 +882,125 @@ impl TryToOptional<Vec<StringHashMap>> for Value {
     }
 }
 
+/// Expand references to environment variables in a string.
+///
+/// Both `$VAR` and `${VAR}` forms are recognized. A `$` not followed by a
+/// variable name is preserved as-is. An error occurs if a referenced
+/// environment variable is not set.
+pub fn expand_env_vars(s: &str) -> Result<String> {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            res.push(c);
+            continue;
+        }
+
+        let mut name = String::new();
+
+        if chars.peek() == Some(&'{') {
+            chars.next();
+
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => name.push(c),
+                    None => return Err(anyhow!("unterminated variable reference in {}", s)),
+                }
+            }
+
+            if name.is_empty() {
+                return Err(anyhow!("empty variable reference in {}", s));
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_alphanumeric() || c == '_' {
+                    name.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+
+            if name.is_empty() {
+                res.push('$');
+                continue;
+            }
+        }
+
+        let value = std::env::var(&name)
+            .map_err(|_| anyhow!("environment variable {} is not set", name))?;
+        res.push_str(&value);
+    }
+
+    Ok(res)
+}
+
+/// Obtain the home directory of the current user.
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Expand a leading `~` in a path to the current user's home directory.
+///
+/// Only `~` and `~/...` are expanded. A `~` elsewhere in the path is preserved
+/// as-is. `~user` forms are not supported and result in an error, as does
+/// failure to determine the home directory.
+pub fn expand_home_dir(s: &str) -> Result<String> {
+    let rest = match s.strip_prefix('~') {
+        Some(rest) => rest,
+        None => return Ok(s.to_string()),
+    };
+
+    if !(rest.is_empty() || rest.starts_with('/') || rest.starts_with(std::path::MAIN_SEPARATOR)) {
+        return Err(anyhow!(
+            "cannot expand {}: ~user paths are not supported",
+            s
+        ));
+    }
+
+    let home = home_dir()
+        .ok_or_else(|| anyhow!("cannot expand {}: unable to determine home directory", s))?;
+
+    Ok(format!("{}{}", home.display(), rest))
+}
+
+#[cfg(unix)]
+fn existing_path_filesystem_id(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    path.metadata()
+        .ok()
+        .map(|metadata| metadata.dev().to_string())
+}
+
+#[cfg(windows)]
+fn existing_path_filesystem_id(path: &Path) -> Option<String> {
+    match path.canonicalize().ok()?.components().next()? {
+        std::path::Component::Prefix(prefix) => {
+            Some(prefix.as_os_str().to_string_lossy().to_uppercase())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn existing_path_filesystem_id(_path: &Path) -> Option<String> {
+    None
+}
+
+/// Obtain an identifier for the filesystem a path is on.
+///
+/// This is the device ID on Unix and the volume prefix (e.g. the drive letter)
+/// on Windows. The path doesn't need to exist: its nearest existing ancestor
+/// is used. Returns `None` if the filesystem can't be determined.
+pub fn filesystem_id(path: &Path) -> Option<String> {
+    existing_path_filesystem_id(path.ancestors().find(|p| p.exists())?)
+}
+
 const ENVIRONMENT_CONTEXT_SYMBOL: &str = "BUILD_CONTEXT";
 
 /// Obtain the `Value` holding the `EnvironmentContext` for a Starlark environment.
@@ -745,19 +1022,71 @@ pub fn get_context_value(type_values: &TypeValues) -> ValueResult {
         })
 }
 
-/// print(*args)
-fn starlark_print(args: &[Value]) -> ValueResult {
-    let mut parts = Vec::new();
-    let mut first = true;
-    for arg in args {
-        if !first {
-            parts.push(" ".to_string());
-        }
-        first = false;
-        parts.push(arg.to_string());
+/// Error code for failures setting the build path.
+pub const BUILD_PATH_ERROR_CODE: &str = "BUILD_TARGETS";
+
+/// Error code for functions rejected in sandbox mode.
+pub const SANDBOX_ERROR_CODE: &str = "PYOXIDIZER_SANDBOX";
+
+/// Reject a filesystem-accessing function call if the environment is sandboxed.
+///
+/// `label` identifies the rejected function in the returned error.
+pub fn check_sandbox(type_values: &TypeValues, label: &str) -> Result<(), ValueError> {
+    let raw_context = get_context_value(type_values)?;
+    let context = raw_context
+        .downcast_ref::<EnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    if context.sandbox {
+        Err(ValueError::from(RuntimeError {
+            code: SANDBOX_ERROR_CODE,
+            message: format!("{} is not allowed in sandbox mode", label),
+            label: label.to_string(),
+        }))
+    } else {
+        Ok(())
+    }
+}
+
+/// Format the arguments to `print()` as a message.
+fn format_print_args(args: &[Value], sep: &str, end: &str) -> String {
+    let mut message = args
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect::<Vec<_>>()
+        .join(sep);
+    message.push_str(end);
+
+    message
+}
+
+/// The level `print()` output is logged at.
+///
+/// Output is only shown by default in verbose mode.
+fn print_log_level(verbose: bool) -> Level {
+    if verbose {
+        Level::Info
+    } else {
+        Level::Debug
     }
+}
 
-    warn!("{}", parts.join(""));
+/// print(*args, sep=" ", end="")
+fn starlark_print(type_values: &TypeValues, args: &[Value], sep: &str, end: &str) -> ValueResult {
+    let message = format_print_args(args, sep, end);
+
+    let raw_context = get_context_value(type_values)?;
+    let context = raw_context
+        .downcast_ref::<EnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    log!(print_log_level(context.verbose), "{}", message);
+
+    if let Some(capture) = &context.print_capture {
+        if let Ok(mut capture) = capture.lock() {
+            capture.push(message);
+        }
+    }
 
     Ok(Value::new(NoneType::None))
 }
@@ -823,14 +1152,25 @@ fn starlark_resolve_target(
 
         warn!("resolving target {}", target);
 
-        match context.get_target(&target) {
+        let entry = match context.get_target(&target) {
             Some(v) => Ok((*v).clone()),
             None => Err(ValueError::from(RuntimeError {
                 code: "BUILD_TARGETS",
                 message: format!("target {} does not exist", target),
                 label: "resolve_target()".to_string(),
             })),
-        }?
+        }?;
+
+        // Catch cycles before recursing into dependencies.
+        context.resolution_order(&[target.clone()]).map_err(|e| {
+            ValueError::from(RuntimeError {
+                code: "BUILD_TARGETS",
+                message: e.to_string(),
+                label: "resolve_target()".to_string(),
+            })
+        })?;
+
+        entry
     };
 
     // Resolve target dependencies.
@@ -844,6 +1184,14 @@ fn starlark_resolve_target(
         )?);
     }
 
+    {
+        let raw_context = get_context_value(type_values)?;
+        let mut context = raw_context
+            .downcast_mut::<EnvironmentContext>()?
+            .ok_or(ValueError::IncorrectParameterType)?;
+        context.resolving_targets.push(target.clone());
+    }
+
     let res = target_entry.callable.call(
         call_stack,
         type_values,
@@ -851,7 +1199,17 @@ fn starlark_resolve_target(
         LinkedHashMap::new(),
         None,
         None,
-    )?;
+    );
+
+    {
+        let raw_context = get_context_value(type_values)?;
+        let mut context = raw_context
+            .downcast_mut::<EnvironmentContext>()?
+            .ok_or(ValueError::IncorrectParameterType)?;
+        context.resolving_targets.pop();
+    }
+
+    let res = res?;
 
     // TODO consider replacing the target's callable with a new function that returns the
     // resolved value. This will ensure a target function is only ever called once.
@@ -894,7 +1252,15 @@ fn starlark_resolve_targets(type_values: &TypeValues, call_stack: &mut CallStack
         let targets = context.targets_to_resolve();
         warn!("resolving {} targets", targets.len());
 
-        targets
+        // Dependencies are resolved first, so targets are resolved in dependency
+        // order regardless of the order they were requested in.
+        context.resolution_order(&targets).map_err(|e| {
+            ValueError::from(RuntimeError {
+                code: "BUILD_TARGETS",
+                message: e.to_string(),
+                label: "resolve_targets()".to_string(),
+            })
+        })?
     };
 
     for target in targets {
@@ -911,27 +1277,107 @@ fn starlark_resolve_targets(type_values: &TypeValues, call_stack: &mut CallStack
     Ok(Value::new(NoneType::None))
 }
 
-/// set_build_path(path)
-fn starlark_set_build_path(type_values: &TypeValues, path: String) -> ValueResult {
+/// set_build_path(path, create=False)
+fn starlark_set_build_path(type_values: &TypeValues, path: String, create: bool) -> ValueResult {
+    check_sandbox(type_values, "set_build_path()")?;
+
+    let context_value = get_context_value(type_values)?;
+    let mut context = context_value
+        .downcast_mut::<EnvironmentContext>()?
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let build_path_error = |e: anyhow::Error| {
+        ValueError::from(RuntimeError {
+            code: BUILD_PATH_ERROR_CODE,
+            message: format!("{:#}", e),
+            label: "set_build_path()".to_string(),
+        })
+    };
+
+    expand_home_dir(&path)
+        .and_then(|path| expand_env_vars(&path))
+        .and_then(|path| context.set_build_path(&PathBuf::from(path)))
+        .map_err(build_path_error)?;
+
+    if create {
+        context.create_build_path().map_err(build_path_error)?;
+    }
+
+    // This is advisory only and never fails.
+    if context.verbose {
+        if let Some(message) = context.build_path_filesystem_warning() {
+            warn!("{}", message);
+        }
+    }
+
+    Ok(Value::new(NoneType::None))
+}
+
+/// reset_build_path()
+fn starlark_reset_build_path(type_values: &TypeValues) -> ValueResult {
     let context_value = get_context_value(type_values)?;
     let mut context = context_value
         .downcast_mut::<EnvironmentContext>()?
         .ok_or(ValueError::IncorrectParameterType)?;
 
-    context.set_build_path(&PathBuf::from(&path)).map_err(|e| {
+    context.reset_build_path();
+
+    Ok(Value::new(NoneType::None))
+}
+
+/// get_target_build_path(target=None)
+fn starlark_get_target_build_path(type_values: &TypeValues, target: &Value) -> ValueResult {
+    let target = optional_str_arg("target", target)?;
+
+    let raw_context = get_context_value(type_values)?;
+    let context = raw_context
+        .downcast_ref::<EnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let target = match target {
+        Some(target) => target,
+        None => context
+            .current_target()
+            .ok_or_else(|| {
+                ValueError::from(RuntimeError {
+                    code: "BUILD_TARGETS",
+                    message: "no target is being resolved; pass a target name".to_string(),
+                    label: "get_target_build_path()".to_string(),
+                })
+            })?
+            .to_string(),
+    };
+
+    Ok(Value::from(
+        context.target_build_path(&target).display().to_string(),
+    ))
+}
+
+/// resolved_target_path(target)
+fn starlark_resolved_target_path(type_values: &TypeValues, target: String) -> ValueResult {
+    let raw_context = get_context_value(type_values)?;
+    let context = raw_context
+        .downcast_ref::<EnvironmentContext>()
+        .ok_or(ValueError::IncorrectParameterType)?;
+
+    let path = context.resolved_target_path(&target).map_err(|e| {
         ValueError::from(RuntimeError {
             code: "BUILD_TARGETS",
             message: e.to_string(),
-            label: "set_build_path()".to_string(),
+            label: "resolved_target_path()".to_string(),
         })
     })?;
 
-    Ok(Value::new(NoneType::None))
+    Ok(Value::from(path.display().to_string()))
 }
 
 starlark_module! { build_targets_module =>
-    print(*args) {
-        starlark_print(&args)
+    get_target_build_path(env env, target=NoneType::None) {
+        starlark_get_target_build_path(env, &target)
+    }
+
+    print(env env, *args, sep: String = " ".to_string(), end: String = "".to_string()) {
+        starlark_print(env, &args, &sep, &end)
     }
 
     register_target(
@@ -945,6 +1391,10 @@ starlark_module! { build_targets_module =>
         starlark_register_target(env, target, callable, depends, default, default_build_script)
     }
 
+    reset_build_path(env env) {
+        starlark_reset_build_path(env)
+    }
+
     resolve_target(env env, call_stack cs, target: String) {
         starlark_resolve_target(env, cs, target)
     }
@@ -953,8 +1403,12 @@ starlark_module! { build_targets_module =>
         starlark_resolve_targets(env, cs)
     }
 
-    set_build_path(env env, path: String) {
-        starlark_set_build_path(env, path)
+    resolved_target_path(env env, target: String) {
+        starlark_resolved_target_path(env, target)
+    }
+
+    set_build_path(env env, path: String, create: bool = false) {
+        starlark_set_build_path(env, path, create)
     }
 }
 
@@ -996,6 +1450,19 @@ pub fn populate_environment(
     Ok(())
 }
 
+/// Resolve a registered target in a Starlark environment.
+///
+/// Calls the functions of the target and its dependencies unless they are
+/// already resolved.
+pub fn resolve_target(
+    type_values: &TypeValues,
+    call_stack: &mut CallStack,
+    target: &str,
+) -> Result<Value> {
+    starlark_resolve_target(type_values, call_stack, target.to_string())
+        .map_err(|e| anyhow!("error resolving target {}: {:?}", target, e))
+}
+
 /// Build a registered target in a Starlark environment.
 pub fn build_target(
     _env: &mut Environment,
@@ -1092,6 +1559,48 @@ mod test {
     use super::*;
     use crate::testutil::*;
 
+    thread_local! {
+        static LOG_RECORDS: std::cell::RefCell<Vec<(Level, String)>> = Default::default();
+    }
+
+    /// Records log messages emitted on the logging thread.
+    ///
+    /// Records are kept per thread so tests running in parallel don't see each
+    /// other's messages.
+    struct CaptureLogger;
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOG_RECORDS.with(|records| {
+                records
+                    .borrow_mut()
+                    .push((record.level(), record.args().to_string()))
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE_LOGGER: CaptureLogger = CaptureLogger;
+
+    /// Obtain the log records emitted on this thread while running `f`.
+    fn capture_logs(f: impl FnOnce() -> Result<()>) -> Result<Vec<(Level, String)>> {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&CAPTURE_LOGGER).expect("logger should not be installed");
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+
+        LOG_RECORDS.with(|records| records.borrow_mut().clear());
+        f()?;
+
+        Ok(LOG_RECORDS.with(|records| records.take()))
+    }
+
     #[test]
     fn test_register_target() -> Result<()> {
         let mut env = StarlarkEnvironment::new()?;
@@ -1139,4 +1648,401 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_resolution_order() -> Result<()> {
+        let mut env = StarlarkEnvironment::new()?;
+        env.eval("order = []")?;
+        env.eval("def installer(exe): order.append('installer')")?;
+        env.eval("def exe(resources): order.append('exe')")?;
+        env.eval("def resources(): order.append('resources')")?;
+        env.eval("register_target('installer', installer, depends=['exe'])")?;
+        env.eval("register_target('exe', exe, depends=['resources'])")?;
+        env.eval("register_target('resources', resources)")?;
+
+        {
+            let context_value = get_context_value(&env.type_values).unwrap();
+            let mut context = context_value
+                .downcast_mut::<EnvironmentContext>()
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(
+                context.resolution_order(&["installer".to_string(), "resources".to_string()])?,
+                vec!["resources", "exe", "installer"]
+            );
+
+            context.set_resolve_targets(vec!["installer".to_string(), "exe".to_string()]);
+        }
+
+        env.eval("resolve_targets()")?;
+        assert!(env
+            .eval("order == ['resources', 'exe', 'installer']")?
+            .to_bool());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolution_order_cycle() -> Result<()> {
+        let mut env = StarlarkEnvironment::new()?;
+        env.eval("def noop(*args): pass")?;
+        env.eval("register_target('a', noop, depends=['b'])")?;
+        env.eval("register_target('b', noop, depends=['c'])")?;
+        env.eval("register_target('c', noop, depends=['a'])")?;
+        env.eval("register_target('d', noop, depends=['missing'])")?;
+
+        let err = env.eval("resolve_target('a')").unwrap_err();
+        assert!(format!("{:?}", err).contains("dependency cycle: a -> b -> c -> a"));
+
+        let err = env.eval("resolve_targets()").unwrap_err();
+        assert!(format!("{:?}", err).contains("dependency cycle: a -> b -> c -> a"));
+
+        let err = env.eval("resolve_target('d')").unwrap_err();
+        assert!(format!("{:?}", err).contains("target missing does not exist"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_target_build_path() -> Result<()> {
+        let mut env = StarlarkEnvironment::new()?;
+        env.eval("def foo(): return get_target_build_path()")?;
+        env.eval("def bar(): return get_target_build_path()")?;
+        env.eval("register_target('foo', foo)")?;
+        env.eval("register_target('bar', bar)")?;
+
+        let foo = env.eval("resolve_target('foo')")?.to_str();
+        let bar = env.eval("resolve_target('bar')")?.to_str();
+
+        let cwd = std::env::current_dir()?;
+        assert_eq!(foo, cwd.join("build").join("foo").display().to_string());
+        assert_eq!(bar, cwd.join("build").join("bar").display().to_string());
+        assert_eq!(env.eval("get_target_build_path('foo')")?.to_str(), foo);
+
+        // Outside of target resolution a name is required.
+        assert!(env.eval("get_target_build_path()").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_build_path_sandbox() -> Result<()> {
+        let mut env = StarlarkEnvironment::new()?;
+
+        {
+            let context_value = get_context_value(&env.type_values).unwrap();
+            let mut context = context_value
+                .downcast_mut::<EnvironmentContext>()
+                .unwrap()
+                .unwrap();
+            context.sandbox = true;
+        }
+
+        let err = env.eval("set_build_path('foo')").unwrap_err();
+        assert!(format!("{:?}", err).contains(SANDBOX_ERROR_CODE));
+        assert_eq!(env.eval("1 + 2")?.to_int().unwrap(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolved_target_path() -> Result<()> {
+        let mut env = StarlarkEnvironment::new()?;
+        env.eval("def foo(): pass")?;
+        env.eval("def bar(foo): return resolved_target_path('foo')")?;
+        env.eval("register_target('foo', foo)")?;
+        env.eval("register_target('bar', bar, depends=['foo'])")?;
+
+        assert!(env.eval("resolved_target_path('missing')").is_err());
+        assert!(env.eval("resolved_target_path('foo')").is_err());
+
+        let path = env.eval("resolve_target('bar')")?.to_str();
+        assert_eq!(
+            path,
+            std::env::current_dir()?
+                .join("build")
+                .join("foo")
+                .display()
+                .to_string()
+        );
+        assert_eq!(env.eval("resolved_target_path('foo')")?.to_str(), path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_print() -> Result<()> {
+        let mut env = StarlarkEnvironment::new()?;
+        env.eval("print('hello', 'world')")?;
+
+        let capture = PrintCapture::default();
+        {
+            let context_value = get_context_value(&env.type_values).unwrap();
+            let mut context = context_value
+                .downcast_mut::<EnvironmentContext>()
+                .unwrap()
+                .unwrap();
+            context.print_capture = Some(capture.clone());
+        }
+
+        env.eval("print('hello, world')")?;
+        assert_eq!(*capture.lock().unwrap(), vec!["hello, world".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_sep_end() -> Result<()> {
+        let args = vec![Value::from("a"), Value::from("b"), Value::from(1)];
+
+        assert_eq!(format_print_args(&args, " ", ""), "a b 1");
+        assert_eq!(format_print_args(&args, ", ", ""), "a, b, 1");
+        assert_eq!(format_print_args(&args, "", "!"), "ab1!");
+        assert_eq!(format_print_args(&[], " ", "\n"), "\n");
+
+        let mut env = StarlarkEnvironment::new()?;
+        let capture = PrintCapture::default();
+        {
+            let context_value = get_context_value(&env.type_values).unwrap();
+            let mut context = context_value
+                .downcast_mut::<EnvironmentContext>()
+                .unwrap()
+                .unwrap();
+            context.print_capture = Some(capture.clone());
+        }
+
+        env.eval("print('hello', 'world', sep = ', ')")?;
+        env.eval("print('hello', end = '!')")?;
+        env.eval("print('a', 'b', sep = '-', end = '.')")?;
+        assert_eq!(
+            *capture.lock().unwrap(),
+            vec![
+                "hello, world".to_string(),
+                "hello!".to_string(),
+                "a-b.".to_string()
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_verbose() -> Result<()> {
+        for (verbose, level) in [(false, Level::Debug), (true, Level::Info)] {
+            let mut env = StarlarkEnvironment::new()?;
+
+            {
+                let context_value = get_context_value(&env.type_values).unwrap();
+                let mut context = context_value
+                    .downcast_mut::<EnvironmentContext>()
+                    .unwrap()
+                    .unwrap();
+                context.verbose = verbose;
+            }
+
+            let records = capture_logs(|| {
+                env.eval("print('hello', 'world')")?;
+                Ok(())
+            })?;
+            assert_eq!(records, vec![(level, "hello world".to_string())]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_build_path_confined() -> Result<()> {
+        let cwd = std::env::current_dir()?;
+        let mut context = EnvironmentContext::new(cwd.clone());
+        context.confine_build_path = true;
+
+        context.set_build_path(Path::new("foo/../build"))?;
+        assert_eq!(context.build_path(), cwd.join("build"));
+
+        assert!(context.set_build_path(Path::new("../build")).is_err());
+        assert!(context
+            .set_build_path(&std::env::temp_dir().join("build"))
+            .is_err());
+        assert_eq!(context.build_path(), cwd.join("build"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_build_path() -> Result<()> {
+        let mut env = StarlarkEnvironment::new()?;
+        let default = std::env::current_dir()?.join("build");
+
+        let build_path = |env: &StarlarkEnvironment| {
+            let context_value = get_context_value(&env.type_values).unwrap();
+            let context = context_value.downcast_ref::<EnvironmentContext>().unwrap();
+            context.build_path().to_path_buf()
+        };
+
+        env.eval("set_build_path('other')")?;
+        assert_eq!(build_path(&env), std::env::current_dir()?.join("other"));
+
+        env.eval("reset_build_path()")?;
+        assert_eq!(build_path(&env), default);
+        assert_eq!(
+            env.eval("get_target_build_path('foo')")?.to_str(),
+            default.join("foo").display().to_string()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_build_path_create() -> Result<()> {
+        let root =
+            std::env::temp_dir().join(format!("build-targets-test-create-{}", std::process::id()));
+        let build_path = root.join("a").join("build");
+        let mut env = StarlarkEnvironment::new()?;
+
+        let code = |create: bool| {
+            format!(
+                "set_build_path('{}', create = {})",
+                build_path.display().to_string().replace('\\', "/"),
+                if create { "True" } else { "False" }
+            )
+        };
+
+        env.eval(&code(false))?;
+        assert!(!build_path.exists());
+
+        env.eval(&code(true))?;
+        assert!(build_path.is_dir());
+
+        std::fs::remove_dir_all(&root)?;
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_set_build_path_create_permission_denied() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!(
+            "build-targets-test-readonly-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&root)?;
+        std::fs::set_permissions(&root, std::fs::Permissions::from_mode(0o555))?;
+
+        // Privileged users can write to read-only directories.
+        let probe = root.join("probe");
+        if std::fs::create_dir(&probe).is_ok() {
+            std::fs::remove_dir(&probe)?;
+        } else {
+            let mut env = StarlarkEnvironment::new()?;
+            let err = env
+                .eval(&format!(
+                    "set_build_path('{}', create = True)",
+                    root.join("build").display()
+                ))
+                .unwrap_err();
+            assert!(format!("{:?}", err).contains(BUILD_PATH_ERROR_CODE));
+            assert!(format!("{:?}", err).contains("creating build path"));
+        }
+
+        std::fs::set_permissions(&root, std::fs::Permissions::from_mode(0o755))?;
+        std::fs::remove_dir_all(&root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_path_filesystem_warning() -> Result<()> {
+        let cwd = std::env::current_dir()?;
+        let mut context = EnvironmentContext::new(cwd.clone());
+
+        // Nonexistent paths use their nearest existing ancestor.
+        context.set_build_path(Path::new("does-not-exist/build"))?;
+        assert_eq!(filesystem_id(context.build_path()), filesystem_id(&cwd));
+        assert!(context.build_path_filesystem_warning().is_none());
+
+        // procfs is always a separate filesystem.
+        #[cfg(target_os = "linux")]
+        {
+            context.set_build_path(Path::new("/proc/build"))?;
+            let warning = context.build_path_filesystem_warning().unwrap();
+            assert!(warning.contains("different filesystem"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_env_vars() -> Result<()> {
+        std::env::set_var("BUILD_TARGETS_TEST_EXPAND", "value");
+        std::env::remove_var("BUILD_TARGETS_TEST_EXPAND_UNSET");
+
+        assert_eq!(expand_env_vars("foo/bar")?, "foo/bar");
+        assert_eq!(
+            expand_env_vars("$BUILD_TARGETS_TEST_EXPAND/bar")?,
+            "value/bar"
+        );
+        assert_eq!(
+            expand_env_vars("${BUILD_TARGETS_TEST_EXPAND}bar")?,
+            "valuebar"
+        );
+        assert_eq!(expand_env_vars("foo$/bar")?, "foo$/bar");
+        assert!(expand_env_vars("$BUILD_TARGETS_TEST_EXPAND_UNSET").is_err());
+        assert!(expand_env_vars("${BUILD_TARGETS_TEST_EXPAND").is_err());
+        assert!(expand_env_vars("${}").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_home_dir() -> Result<()> {
+        assert_eq!(expand_home_dir("foo/~/bar")?, "foo/~/bar");
+        assert_eq!(expand_home_dir("foo~")?, "foo~");
+        assert!(expand_home_dir("~someone/build").is_err());
+
+        if let Some(home) = home_dir() {
+            assert_eq!(PathBuf::from(expand_home_dir("~")?), home);
+            assert_eq!(PathBuf::from(expand_home_dir("~/sub")?), home.join("sub"));
+
+            let mut env = StarlarkEnvironment::new()?;
+            env.eval("set_build_path('~/build')")?;
+
+            let context_value = get_context_value(&env.type_values).unwrap();
+            let context = context_value
+                .downcast_ref::<EnvironmentContext>()
+                .ok_or(ValueError::IncorrectParameterType)
+                .unwrap();
+
+            assert_eq!(context.build_path(), home.join("build"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_build_path_env_var() -> Result<()> {
+        let temp_dir = std::env::temp_dir();
+        std::env::set_var("BUILD_TARGETS_TEST_BUILD_PATH", &temp_dir);
+
+        let mut env = StarlarkEnvironment::new()?;
+        env.eval("set_build_path('${BUILD_TARGETS_TEST_BUILD_PATH}/build')")?;
+
+        std::env::remove_var("BUILD_TARGETS_TEST_BUILD_PATH_UNSET");
+        let err = env
+            .eval("set_build_path('$BUILD_TARGETS_TEST_BUILD_PATH_UNSET/build')")
+            .unwrap_err();
+        assert!(format!("{:?}", err).contains(&format!("error[{}]", BUILD_PATH_ERROR_CODE)));
+
+        let context_value = get_context_value(&env.type_values).unwrap();
+        let context = context_value
+            .downcast_ref::<EnvironmentContext>()
+            .ok_or(ValueError::IncorrectParameterType)
+            .unwrap();
+
+        assert_eq!(context.build_path(), temp_dir.join("build"));
+
+        Ok(())
+    }
 }
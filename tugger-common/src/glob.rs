@@ -3,26 +3,84 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use {
-    anyhow::Result,
-    std::path::{Path, PathBuf},
+    anyhow::{anyhow, Result},
+    std::{
+        collections::BTreeSet,
+        path::{Path, PathBuf},
+    },
 };
 
+/// Resolve a glob pattern to an absolute search pattern.
+fn search_pattern(cwd: &Path, pattern: &str) -> String {
+    let pattern_path = PathBuf::from(pattern);
+
+    if pattern.starts_with('/') || pattern_path.is_absolute() {
+        pattern.to_string()
+    } else {
+        format!("{}/{}", cwd.display(), pattern)
+    }
+}
+
+/// Whether a path component contains glob special characters.
+fn is_wildcard(component: &str) -> bool {
+    component.contains(|c| matches!(c, '*' | '?' | '['))
+}
+
 /// Evaluate a file matching glob relative to the given directory.
 pub fn evaluate_glob<P>(cwd: P, pattern: &str) -> Result<Vec<PathBuf>>
 where
     P: AsRef<Path>,
 {
-    let pattern_path = PathBuf::from(pattern);
+    let search = search_pattern(cwd.as_ref(), pattern);
 
-    let search = if pattern.starts_with('/') || pattern_path.is_absolute() {
-        pattern.to_string()
-    } else {
-        format!("{}/{}", cwd.as_ref().display(), pattern)
+    let mut res = Vec::new();
+
+    for path in glob::glob(&search)? {
+        let path = path?;
+
+        if path.is_file() {
+            res.push(path);
+        }
+    }
+
+    Ok(res)
+}
+
+/// Evaluate a file matching glob relative to the given directory, strictly.
+///
+/// Unlike [evaluate_glob], hidden files and directories are only matched if
+/// the corresponding pattern component explicitly begins with `.`, the
+/// non-wildcard directory prefix of the pattern must exist, and results are
+/// sorted.
+pub fn evaluate_glob_strict<P>(cwd: P, pattern: &str) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let search = search_pattern(cwd.as_ref(), pattern);
+
+    let components = search.split('/').collect::<Vec<_>>();
+    let literal_count = components
+        .iter()
+        .position(|c| is_wildcard(c))
+        .unwrap_or(components.len() - 1);
+    let base = PathBuf::from(components[0..literal_count].join("/"));
+
+    if !base.as_os_str().is_empty() && !base.is_dir() {
+        return Err(anyhow!(
+            "directory {} does not exist (from pattern {})",
+            base.display(),
+            pattern
+        ));
+    }
+
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..glob::MatchOptions::new()
     };
 
     let mut res = Vec::new();
 
-    for path in glob::glob(&search)? {
+    for path in glob::glob_with(&search, options)? {
         let path = path?;
 
         if path.is_file() {
@@ -30,5 +88,97 @@ where
         }
     }
 
+    res.sort();
+
+    Ok(res)
+}
+
+/// Evaluate sets of include and exclude glob patterns.
+///
+/// Files matching any `include` pattern, evaluated with
+/// [evaluate_glob_strict], are returned unless they match an `exclude`
+/// pattern. Exclude patterns are evaluated with [evaluate_glob], so they also
+/// match hidden files and missing directories in them aren't an error.
+pub fn evaluate_globs<P>(
+    cwd: P,
+    include: &[impl AsRef<str>],
+    exclude: &[impl AsRef<str>],
+) -> Result<BTreeSet<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let mut res = BTreeSet::new();
+
+    for pattern in include {
+        res.extend(evaluate_glob_strict(cwd.as_ref(), pattern.as_ref())?);
+    }
+
+    for pattern in exclude {
+        for path in evaluate_glob(cwd.as_ref(), pattern.as_ref())? {
+            res.remove(&path);
+        }
+    }
+
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::testutil::*};
+
+    #[test]
+    fn strict_hidden_files() -> Result<()> {
+        let td = DEFAULT_TEMP_DIR.path().join("glob-strict-hidden");
+        std::fs::create_dir_all(td.join(".hidden"))?;
+        std::fs::write(td.join("b.txt"), b"b")?;
+        std::fs::write(td.join("a.txt"), b"a")?;
+        std::fs::write(td.join(".c.txt"), b"c")?;
+        std::fs::write(td.join(".hidden").join("d.txt"), b"d")?;
+
+        assert_eq!(
+            evaluate_glob_strict(&td, "**/*.txt")?,
+            vec![td.join("a.txt"), td.join("b.txt")]
+        );
+        assert_eq!(
+            evaluate_glob_strict(&td, ".*.txt")?,
+            vec![td.join(".c.txt")]
+        );
+        assert_eq!(
+            evaluate_glob_strict(&td, ".hidden/*")?,
+            vec![td.join(".hidden").join("d.txt")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn strict_missing_directory() -> Result<()> {
+        let td = DEFAULT_TEMP_DIR.path().join("glob-strict-missing");
+        std::fs::create_dir_all(&td)?;
+
+        assert!(evaluate_glob_strict(&td, "*.txt")?.is_empty());
+        assert!(evaluate_glob_strict(&td, "missing/*.txt").is_err());
+        assert!(evaluate_glob_strict(&td, "missing/file.txt").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn include_exclude() -> Result<()> {
+        let td = DEFAULT_TEMP_DIR.path().join("glob-include-exclude");
+        std::fs::create_dir_all(td.join("sub"))?;
+        std::fs::write(td.join("a.txt"), b"a")?;
+        std::fs::write(td.join("b.py"), b"b")?;
+        std::fs::write(td.join("sub").join("c.txt"), b"c")?;
+
+        assert_eq!(
+            evaluate_globs(&td, &["**/*"], &["*.py", "missing/*"])?
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![td.join("a.txt"), td.join("sub").join("c.txt")]
+        );
+        assert!(evaluate_globs(&td, &["missing/*"], &[] as &[&str]).is_err());
+
+        Ok(())
+    }
+}
@@ -37,29 +37,185 @@ fn sha256_path<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
     Ok(hasher.finalize().to_vec())
 }
 
-/// Obtain an HTTP client, taking proxy environment variables into account.
-pub fn get_http_client() -> reqwest::Result<reqwest::blocking::Client> {
-    let mut builder = reqwest::blocking::ClientBuilder::new();
-
-    for (key, value) in std::env::vars() {
-        let key = key.to_lowercase();
-        if key.ends_with("_proxy") {
-            let end = key.len() - "_proxy".len();
-            let schema = &key[..end];
-
-            if let Ok(url) = Url::parse(&value) {
-                if let Some(Ok(proxy)) = match schema {
-                    "http" => Some(reqwest::Proxy::http(url.as_str())),
-                    "https" => Some(reqwest::Proxy::https(url.as_str())),
-                    _ => None,
-                } {
-                    builder = builder.proxy(proxy);
+/// Proxy settings derived from `HTTP_PROXY`, `HTTPS_PROXY`, and `NO_PROXY`.
+///
+/// Lowercase variable names take precedence over uppercase ones.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ProxyConfig {
+    /// Proxy to use for `http://` URLs.
+    pub http: Option<Url>,
+    /// Proxy to use for `https://` URLs.
+    pub https: Option<Url>,
+    /// Hosts that should be connected to directly.
+    pub no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    /// Resolve proxy settings from environment variables.
+    pub fn from_env() -> Self {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Resolve proxy settings from `(key, value)` pairs.
+    pub fn from_vars(vars: impl Iterator<Item = (String, String)>) -> Self {
+        let mut res = Self::default();
+        let mut no_proxy = None;
+
+        // Sort so lowercase names (which sort after uppercase) win.
+        let mut vars = vars.collect::<Vec<_>>();
+        vars.sort();
+
+        for (key, value) in vars {
+            match key.to_lowercase().as_str() {
+                "http_proxy" => {
+                    if let Ok(url) = Url::parse(&value) {
+                        res.http = Some(url);
+                    }
+                }
+                "https_proxy" => {
+                    if let Ok(url) = Url::parse(&value) {
+                        res.https = Some(url);
+                    }
                 }
+                "no_proxy" => {
+                    no_proxy = Some(value);
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(value) = no_proxy {
+            res.no_proxy = value
+                .split(',')
+                .map(|x| x.trim().to_lowercase())
+                .filter(|x| !x.is_empty())
+                .collect();
+        }
+
+        res
+    }
+
+    /// Whether a host should bypass the proxy according to `NO_PROXY`.
+    fn bypasses_proxy(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+
+        self.no_proxy.iter().any(|entry| {
+            let entry = entry.trim_start_matches('.');
+
+            entry == "*" || host == entry || host.ends_with(&format!(".{}", entry))
+        })
+    }
+
+    /// Obtain the proxy to use for a URL, if any.
+    pub fn proxy_for(&self, url: &Url) -> Option<&Url> {
+        if let Some(host) = url.host_str() {
+            if self.bypasses_proxy(host) {
+                return None;
             }
         }
+
+        match url.scheme() {
+            "http" => self.http.as_ref(),
+            "https" => self.https.as_ref(),
+            _ => None,
+        }
     }
+}
+
+/// Obtain an HTTP client, taking proxy environment variables into account.
+pub fn get_http_client() -> reqwest::Result<reqwest::blocking::Client> {
+    http_client_builder(ProxyConfig::from_env()).build()
+}
+
+/// Obtain an HTTP client trusting certificates from an additional PEM bundle.
+///
+/// Proxy environment variables are taken into account.
+pub fn get_http_client_with_ca_bundle(
+    ca_bundle: Option<&Path>,
+) -> Result<reqwest::blocking::Client> {
+    let mut builder = http_client_builder(ProxyConfig::from_env());
+
+    if let Some(path) = ca_bundle {
+        for certificate in load_pem_certificates(path)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    Ok(builder.build()?)
+}
 
-    builder.build()
+fn http_client_builder(proxies: ProxyConfig) -> reqwest::blocking::ClientBuilder {
+    reqwest::blocking::ClientBuilder::new().proxy(reqwest::Proxy::custom(move |url| {
+        proxies.proxy_for(url).cloned()
+    }))
+}
+
+/// Load all certificates from a file containing PEM encoded certificates.
+fn load_pem_certificates(path: &Path) -> Result<Vec<reqwest::Certificate>> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("reading CA bundle {}", path.display()))?;
+
+    let mut res = vec![];
+    let mut remaining = data.as_str();
+
+    while let Some(start) = remaining.find(BEGIN) {
+        let end = remaining[start..]
+            .find(END)
+            .map(|offset| start + offset + END.len())
+            .ok_or_else(|| anyhow!("unterminated certificate in {}", path.display()))?;
+
+        res.push(
+            reqwest::Certificate::from_pem(remaining[start..end].as_bytes())
+                .with_context(|| format!("parsing certificate in {}", path.display()))?,
+        );
+
+        remaining = &remaining[end..];
+    }
+
+    if res.is_empty() {
+        return Err(anyhow!("no certificates found in {}", path.display()));
+    }
+
+    Ok(res)
+}
+
+/// Whether an error from an HTTP request is likely to go away on retry.
+///
+/// Timeouts, connection failures, interrupted response bodies, and server
+/// errors (5xx) are transient. Client errors such as 404 and errors not
+/// originating from the HTTP request are not.
+pub fn is_transient_error(error: &anyhow::Error) -> bool {
+    error.chain().any(is_transient_cause)
+}
+
+fn is_transient_cause(cause: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+        e.is_timeout()
+            || e.is_connect()
+            || e.is_body()
+            || e.status()
+                .map(|status| status.is_server_error())
+                .unwrap_or(false)
+    } else if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+        // Errors reading response bodies are reqwest errors wrapped in I/O errors.
+        matches!(
+            e.kind(),
+            std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::UnexpectedEof
+        ) || e
+            .get_ref()
+            .map(|inner| is_transient_cause(inner))
+            .unwrap_or(false)
+    } else {
+        false
+    }
 }
 
 /// Fetch a URL and verify its SHA-256 matches expectations.
@@ -144,3 +300,78 @@ pub fn download_to_path<P: AsRef<Path>>(entry: &RemoteContent, dest_path: P) ->
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn proxy_config() -> Result<()> {
+        let config = ProxyConfig::from_vars(vars(&[
+            ("HTTPS_PROXY", "http://upper:3128"),
+            ("https_proxy", "http://lower:3128"),
+            ("HTTP_PROXY", "http://plain:3128"),
+            ("NO_PROXY", "localhost, .internal.example.com"),
+        ]));
+
+        assert_eq!(
+            config.proxy_for(&Url::parse("https://github.com/foo")?),
+            Some(&Url::parse("http://lower:3128")?)
+        );
+        assert_eq!(
+            config.proxy_for(&Url::parse("http://github.com/foo")?),
+            Some(&Url::parse("http://plain:3128")?)
+        );
+        assert_eq!(config.proxy_for(&Url::parse("https://localhost/")?), None);
+        assert_eq!(
+            config.proxy_for(&Url::parse("https://mirror.internal.example.com/")?),
+            None
+        );
+        assert_eq!(
+            config.proxy_for(&Url::parse("https://internal.example.com/")?),
+            None
+        );
+        assert_eq!(config.proxy_for(&Url::parse("file:///tmp/foo")?), None);
+
+        let config = ProxyConfig::from_vars(vars(&[
+            ("HTTPS_PROXY", "http://proxy:3128"),
+            ("NO_PROXY", "*"),
+        ]));
+        assert_eq!(config.proxy_for(&Url::parse("https://github.com/")?), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ca_bundle_without_certificates() -> Result<()> {
+        let path = crate::testutil::DEFAULT_TEMP_DIR
+            .path()
+            .join("empty-ca-bundle.pem");
+        std::fs::write(&path, b"not a certificate")?;
+
+        assert!(get_http_client_with_ca_bundle(Some(&path)).is_err());
+        assert!(get_http_client_with_ca_bundle(None).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn transient_errors() {
+        let io = |kind| anyhow::Error::from(std::io::Error::new(kind, "error"));
+
+        assert!(is_transient_error(&io(std::io::ErrorKind::ConnectionReset)));
+        assert!(is_transient_error(
+            &io(std::io::ErrorKind::TimedOut).context("reading response")
+        ));
+        assert!(!is_transient_error(&io(std::io::ErrorKind::NotFound)));
+        assert!(!is_transient_error(&anyhow!("hash mismatch")));
+    }
+}
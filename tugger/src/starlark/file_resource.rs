@@ -17,11 +17,10 @@ use {
         },
     },
     starlark_dialect_build_targets::{
-        get_context_value, optional_list_arg, optional_str_arg, required_list_arg,
+        check_sandbox, get_context_value, optional_list_arg, optional_str_arg, required_list_arg,
         EnvironmentContext,
     },
-    std::collections::HashSet,
-    tugger_common::glob::evaluate_glob,
+    tugger_common::glob::evaluate_globs,
     tugger_file_manifest::{FileEntry, FileManifest},
 };
 
@@ -45,6 +44,8 @@ fn starlark_glob(
     exclude: &Value,
     strip_prefix: &Value,
 ) -> ValueResult {
+    check_sandbox(type_values, "glob()")?;
+
     required_list_arg("include", "string", include)?;
     optional_list_arg("exclude", "string", exclude)?;
     let strip_prefix = optional_str_arg("strip_prefix", strip_prefix)?;
@@ -66,21 +67,7 @@ fn starlark_glob(
         .ok_or(ValueError::IncorrectParameterType)?;
 
     let manifest = error_context("glob()", || {
-        let mut result = HashSet::new();
-
-        // Evaluate all the includes first.
-        for v in include {
-            for p in evaluate_glob(context.cwd(), &v)? {
-                result.insert(p);
-            }
-        }
-
-        // Then apply excludes.
-        for v in exclude {
-            for p in evaluate_glob(context.cwd(), &v)? {
-                result.remove(&p);
-            }
-        }
+        let result = evaluate_globs(context.cwd(), &include, &exclude)?;
 
         let mut manifest = FileManifest::default();
 
@@ -108,3 +95,46 @@ starlark_module! { file_resource_module =>
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::starlark::{file_manifest::FileManifestValue, testutil::*},
+        anyhow::Result,
+        tugger_common::testutil::*,
+    };
+
+    #[test]
+    fn test_glob() -> Result<()> {
+        let td = DEFAULT_TEMP_DIR.path().join("test_glob");
+        std::fs::create_dir_all(td.join(".git"))?;
+        std::fs::write(td.join("b.txt"), b"b")?;
+        std::fs::write(td.join("a.txt"), b"a")?;
+        std::fs::write(td.join("c.py"), b"c")?;
+        std::fs::write(td.join(".git").join("config"), b"config")?;
+
+        let mut env = StarlarkEnvironment::new()?;
+        let manifest = env.eval(&format!(
+            "glob(['{}/**/*'], exclude=['{}/*.py'], strip_prefix='{}')",
+            td.display().to_string().escape_default(),
+            td.display().to_string().escape_default(),
+            td.display().to_string().escape_default(),
+        ))?;
+        let manifest = manifest.downcast_ref::<FileManifestValue>().unwrap();
+        let paths = manifest
+            .inner("ignored")
+            .unwrap()
+            .iter_entries()
+            .map(|(path, _)| path.display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["a.txt", "b.txt"]);
+
+        let res = env.eval(&format!(
+            "glob(['{}/missing/*'])",
+            td.display().to_string().escape_default()
+        ));
+        assert!(res.is_err());
+
+        Ok(())
+    }
+}